@@ -2,15 +2,64 @@ use anyhow::*;
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
+use std::fmt::Write as _;
+use std::path::Path;
+
+#[allow(dead_code)]
+#[path = "src/pack.rs"]
+mod pack;
+
+// Shipped with every build, see `vfs::Vfs::default`
+const ASSET_DIRS: [&str; 3] = ["models", "images", "shaders"];
 
 fn main() -> Result<()> {
-    println!("cargo:rerun-if-changed=models/*");
+    for dir in ASSET_DIRS {
+        println!("cargo:rerun-if-changed={}", dir);
+    }
+    println!("cargo:rustc-check-cfg=cfg(packed_assets)");
 
     let out_dir = env::var("OUT_DIR")?;
-    let mut copy_options = CopyOptions::new();
-    copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("models/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    // Matches `cfg(debug_assertions)` in `vfs::Vfs::default`, which only
+    // mounts the copy in OUT_DIR for debug builds
+    if env::var_os("CARGO_CFG_DEBUG_ASSERTIONS").is_none() {
+        // Release builds embed a packed archive instead of relying on a copy in OUT_DIR
+        let mut writer = pack::ArchiveWriter::new(true);
+        for dir in ASSET_DIRS {
+            writer.add_dir(Path::new(dir), dir)?;
+        }
+        let mut file = std::fs::File::create(Path::new(&out_dir).join("assets.gzpk"))?;
+        writer.write(&mut file)?;
+        println!("cargo:rustc-cfg=packed_assets");
+    } else {
+        let mut copy_options = CopyOptions::new();
+        copy_options.overwrite = true;
+        copy_items(&ASSET_DIRS, &out_dir, &copy_options)?;
+    }
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        embed_assets(Path::new(&out_dir))?;
+    }
+    Ok(())
+}
+
+// Writes an array of `(path, include_bytes!(..))` pairs for `EmbeddedMount::bundled`
+fn embed_assets(out_dir: &Path) -> Result<()> {
+    let models_dir = Path::new(&env::var("CARGO_MANIFEST_DIR")?).join("models");
+    let mut source = String::from("&[\n");
+    for entry in glob::glob(&format!("{}/**/*", models_dir.display()))? {
+        let path = entry?;
+        if !path.is_file() {
+            continue;
+        }
+        let key = path
+            .strip_prefix(&models_dir)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        writeln!(source, "    ({:?}, include_bytes!({:?})),", key, path)?;
+    }
+    source.push(']');
+    std::fs::write(out_dir.join("embedded_assets.rs"), source)?;
     Ok(())
 }
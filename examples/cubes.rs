@@ -0,0 +1,201 @@
+use gamezap::{
+    ecs::{Entity, Light, MeshRenderer, Parent, World},
+    environment::{Environment, EnvironmentSettings},
+    light::DrawLight,
+    postprocess::{Fxaa, PostEffect, Vignette},
+    scene::{ModelId, Transform},
+    skybox::Sky,
+    texture::TextureFilter,
+    tonemap::{Bloom, TonemapSettings},
+};
+use nalgebra as na;
+use pollster::{self, block_on};
+
+const NUM_INSTANCES_PER_ROW: u32 = 10;
+
+struct State {
+    cube: ModelId,
+}
+
+fn main() {
+    block_on(gamezap::run(
+        |engine| {
+            if std::env::args().any(|arg| arg == "--hot-reload") {
+                engine
+                    .enable_hot_reload(
+                        concat!(env!("CARGO_MANIFEST_DIR"), "/models"),
+                        concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"),
+                    )
+                    .unwrap();
+            }
+            // An equirectangular `.hdr` from models/ to light the scene with
+            let args = std::env::args().collect::<Vec<_>>();
+            if let Some(file_name) = args
+                .iter()
+                .position(|arg| arg == "--environment")
+                .and_then(|i| args.get(i + 1))
+            {
+                let environment = block_on(Environment::load_hdr(
+                    file_name,
+                    &engine.device,
+                    &engine.queue,
+                    EnvironmentSettings::default(),
+                ))
+                .unwrap();
+                engine.set_sky(Sky::Cubemap(environment.cubemap.clone()));
+                engine.set_environment(environment);
+            }
+
+            if args.iter().any(|arg| arg == "--post-process") {
+                engine.set_tonemap_settings(TonemapSettings {
+                    bloom: Some(Bloom::default()),
+                    ..engine.tonemap_settings()
+                });
+                let effects = [
+                    PostEffect::vignette(&engine.device, &engine.post_process, Vignette::default()),
+                    PostEffect::fxaa(&engine.device, &engine.post_process, Fxaa::default()),
+                ];
+                for effect in effects {
+                    engine.post_process.push(effect.unwrap());
+                }
+            }
+            if args.iter().any(|arg| arg == "--msaa") {
+                engine.set_sample_count(4).unwrap();
+            }
+
+            // The far rows of the grid are seen at grazing angles
+            engine.assets.texture_filter = TextureFilter::Anisotropic(16);
+            let mut load = |file_name| {
+                let handle = block_on(engine.assets.load_model(
+                    file_name,
+                    &engine.device,
+                    &engine.queue,
+                    &engine.texture_bind_group_layout,
+                ))
+                .unwrap();
+                engine
+                    .scene
+                    .add_model(engine.assets.shared_model(&handle).unwrap())
+            };
+            let cube = load("cube.obj");
+            let blade = load("blade.obj");
+
+            let epsilon = 1e-6;
+            let space_between = 3.0;
+            let mut center_cube = None;
+            for z in 0..NUM_INSTANCES_PER_ROW {
+                for x in 0..NUM_INSTANCES_PER_ROW {
+                    let x = space_between * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                    let z = space_between * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                    let position = na::Vector3::new(x as f32, 0.0, z as f32);
+
+                    let rotation = if position.norm() < epsilon {
+                        na::UnitQuaternion::from_axis_angle(
+                            &na::Unit::new_normalize(na::Vector3::new(0.0, 0.0, 1.0)),
+                            0.0,
+                        )
+                    } else {
+                        na::UnitQuaternion::from_axis_angle(
+                            &na::Unit::new_normalize(position),
+                            45.0,
+                        )
+                    };
+
+                    let entity = engine.world.spawn();
+                    engine.world.insert(
+                        entity,
+                        Transform::new(position, rotation, na::Vector3::new(1.0, 1.0, 1.0)),
+                    );
+                    engine.world.insert(entity, MeshRenderer { model: cube });
+
+                    if position.norm() < epsilon {
+                        center_cube = Some(entity);
+                    }
+                }
+            }
+            let center_cube: Entity = center_cube.unwrap();
+
+            // The blade hangs off the center cube and spins along with it
+            let blade_entity = engine.world.spawn();
+            engine.world.insert(
+                blade_entity,
+                Transform::from_translation(na::Vector3::new(0.0, 1.5, 0.0)),
+            );
+            engine
+                .world
+                .insert(blade_entity, MeshRenderer { model: blade });
+            engine.world.insert(blade_entity, Parent(center_cube));
+
+            let red_light = engine.world.spawn();
+            engine.world.insert(
+                red_light,
+                Transform::from_translation(na::Vector3::new(-10.0, 3.0, -10.0)),
+            );
+            engine.world.insert(
+                red_light,
+                Light::point([1.0, 0.2, 0.2], 2.0, 15.0).with_shadows(),
+            );
+
+            // A spot light shining straight down onto the center of the grid
+            let spot_light = engine.world.spawn();
+            engine.world.insert(
+                spot_light,
+                Transform::new(
+                    na::Vector3::new(0.0, 10.0, 0.0),
+                    na::UnitQuaternion::from_axis_angle(
+                        &na::Vector3::x_axis(),
+                        -std::f32::consts::FRAC_PI_2,
+                    ),
+                    na::Vector3::new(1.0, 1.0, 1.0),
+                ),
+            );
+            engine.world.insert(
+                spot_light,
+                Light::spot([0.4, 0.6, 1.0], 3.0, 30.0, 0.2, 0.35).with_shadows(),
+            );
+
+            let sun = engine.world.spawn();
+            engine.world.insert(
+                sun,
+                Transform::new(
+                    na::Vector3::zeros(),
+                    na::UnitQuaternion::from_euler_angles(-1.0, 0.6, 0.0),
+                    na::Vector3::new(1.0, 1.0, 1.0),
+                ),
+            );
+            engine.world.insert(
+                sun,
+                Light::directional([1.0, 0.95, 0.8], 0.4).with_shadows(),
+            );
+
+            engine.add_system(|world: &mut World| {
+                let lights = world.query::<Light>().map(|(e, _)| e).collect::<Vec<_>>();
+                for light in lights {
+                    if let Some(transform) = world.get_mut::<Transform>(light) {
+                        transform.translation =
+                            na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.1)
+                                * transform.translation;
+                    }
+                }
+            });
+            engine.add_system(move |world: &mut World| {
+                if let Some(transform) = world.get_mut::<Transform>(center_cube) {
+                    transform.rotation *=
+                        na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.02);
+                }
+            });
+
+            State { cube }
+        },
+        |_engine, _state| {},
+        |render_pass, engine, state| {
+            render_pass.set_pipeline(&engine.light_pipeline);
+            render_pass.draw_light_model_instanced(
+                engine.scene.model(state.cube),
+                0..engine.light_count(),
+                &engine.camera_bind_group,
+                &engine.light_bind_group,
+            );
+        },
+    ));
+}
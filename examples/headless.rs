@@ -0,0 +1,35 @@
+use gamezap::{light::DrawLight, resources, scene::Transform, Engine};
+use pollster::block_on;
+
+fn main() {
+    env_logger::init();
+
+    let mut engine = block_on(Engine::new_headless((800, 600)));
+
+    let cube = engine.scene.add_model(
+        block_on(resources::load_model(
+            "cube.obj",
+            &engine.device,
+            &engine.queue,
+            &engine.texture_bind_group_layout,
+        ))
+        .unwrap(),
+    );
+    let node = engine.scene.add_node("cube", Transform::default(), None);
+    engine.scene.node_mut(node).model = Some(cube);
+
+    engine.update();
+    engine
+        .render(&cube, |render_pass, engine, cube| {
+            render_pass.set_pipeline(&engine.light_pipeline);
+            render_pass.draw_light_model_instanced(
+                engine.scene.model(*cube),
+                0..engine.light_count(),
+                &engine.camera_bind_group,
+                &engine.light_bind_group,
+            );
+        })
+        .unwrap();
+
+    engine.read_frame().unwrap().save("headless.png").unwrap();
+}
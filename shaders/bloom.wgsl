@@ -0,0 +1,68 @@
+// Runs on the HDR target before tonemapping, so only colors brighter than the
+// threshold in scene light spill into the glow
+
+struct Bloom {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+    texel_size: vec2<f32>,
+}
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+
+const SAMPLES: u32 = 48u;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32(index & 1u) * 4.0 - 1.0, f32(index >> 1u) * 4.0 - 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    return out;
+}
+
+// How much of a color spills into the glow, with a soft knee below the threshold
+fn bright_part(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.00001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+// A single pass gather over a golden angle spiral, weighted towards the center
+@fragment
+fn fs_gather(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let scale = vec2<f32>(bloom.texel_size.x / bloom.texel_size.y, 1.0) * bloom.radius;
+
+    var glow = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < SAMPLES; i++) {
+        let distance = sqrt((f32(i) + 0.5) / f32(SAMPLES));
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(angle), sin(angle)) * distance * scale;
+        let weight = exp(-3.0 * distance * distance);
+        let tap = textureSampleLevel(t_source, s_source, uv + offset, 0.0).rgb;
+        glow += bright_part(tap) * weight;
+        total_weight += weight;
+    }
+    return vec4<f32>(glow * bloom.intensity / total_weight, 1.0);
+}
+
+// Drawn with additive blending onto the HDR target
+@fragment
+fn fs_composite(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSampleLevel(t_source, s_source, uv, 0.0).rgb, 0.0);
+}
@@ -0,0 +1,15 @@
+struct ChromaticAberration {
+    intensity: f32,
+}
+@group(1) @binding(0)
+var<uniform> aberration: ChromaticAberration;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    // Red and blue are pulled apart towards the edges of the screen
+    let offset = (uv - 0.5) * aberration.intensity;
+    let r = textureSample(t_input, s_input, uv + offset).r;
+    let color = textureSample(t_input, s_input, uv);
+    let b = textureSample(t_input, s_input, uv - offset).b;
+    return vec4<f32>(r, color.g, b, color.a);
+}
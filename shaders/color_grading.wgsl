@@ -0,0 +1,28 @@
+struct ColorGrading {
+    intensity: f32,
+}
+@group(1) @binding(0)
+var<uniform> grading: ColorGrading;
+@group(1) @binding(1)
+var t_lut: texture_3d<f32>;
+@group(1) @binding(2)
+var s_lut: sampler;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, uv);
+
+    // The LUT is indexed by sRGB encoded colors and stored as sRGB, so
+    // sampling it returns linear colors
+    var encoded = saturate(color.rgb);
+    if post.srgb_encoded == 0u {
+        encoded = linear_to_srgb(encoded);
+    }
+    let size = vec3<f32>(textureDimensions(t_lut));
+    let coords = (encoded * (size - 1.0) + 0.5) / size;
+    var graded = textureSampleLevel(t_lut, s_lut, coords, 0.0).rgb;
+    if post.srgb_encoded != 0u {
+        graded = linear_to_srgb(graded);
+    }
+    return vec4<f32>(mix(color.rgb, graded, grading.intensity), color.a);
+}
@@ -0,0 +1,220 @@
+// Compute passes that turn an equirectangular HDR image into the cube maps
+// and lookup table used for image-based lighting. Each entry point has its own
+// bind group, see `environment.rs`.
+
+const PI: f32 = 3.14159265359;
+
+// Direction through the center of texel `id.xy` on cube face `id.z`
+fn cube_direction(id: vec3<u32>, size: u32) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(size) * 2.0 - 1.0;
+    switch id.z {
+        case 0u: { return normalize(vec3<f32>(1.0, -uv.y, -uv.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -uv.y, uv.x)); }
+        case 2u: { return normalize(vec3<f32>(uv.x, 1.0, uv.y)); }
+        case 3u: { return normalize(vec3<f32>(uv.x, -1.0, -uv.y)); }
+        case 4u: { return normalize(vec3<f32>(uv.x, -uv.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-uv.x, -uv.y, -1.0)); }
+    }
+}
+
+// Largest value a 16 bit float holds, bright HDR pixels would become infinity
+const MAX_HALF: f32 = 65504.0;
+
+@group(0) @binding(0)
+var equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var equirect_target: texture_storage_2d_array<rgba16float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(equirect_target).x;
+    if id.x >= size || id.y >= size {
+        return;
+    }
+    let direction = cube_direction(id, size);
+    let dimensions = vec2<i32>(textureDimensions(equirect));
+    let uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+
+    // Bilinear filtering by hand, 32 bit float textures aren't filterable
+    let texel = uv * vec2<f32>(dimensions) - 0.5;
+    let base = vec2<i32>(floor(texel));
+    let weight = texel - floor(texel);
+    var color = vec3<f32>(0.0);
+    for (var y = 0; y < 2; y += 1) {
+        for (var x = 0; x < 2; x += 1) {
+            let coords = base + vec2<i32>(x, y);
+            // Wraps around horizontally and stops at the poles
+            let wrapped = vec2<i32>(
+                (coords.x % dimensions.x + dimensions.x) % dimensions.x,
+                clamp(coords.y, 0, dimensions.y - 1),
+            );
+            let w = select(1.0 - weight.x, weight.x, x == 1) * select(1.0 - weight.y, weight.y, y == 1);
+            color += textureLoad(equirect, wrapped, 0).rgb * w;
+        }
+    }
+    textureStore(equirect_target, id.xy, id.z, vec4<f32>(min(color, vec3<f32>(MAX_HALF)), 1.0));
+}
+
+@group(0) @binding(0)
+var downsample_source: texture_2d_array<f32>;
+@group(0) @binding(1)
+var downsample_target: texture_storage_2d_array<rgba16float, write>;
+
+// Averages 2x2 texels of the previous mip level
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(downsample_target).x;
+    if id.x >= size || id.y >= size {
+        return;
+    }
+    let source = vec2<i32>(id.xy * 2u);
+    let layer = i32(id.z);
+    let color = textureLoad(downsample_source, source, layer, 0)
+        + textureLoad(downsample_source, source + vec2<i32>(1, 0), layer, 0)
+        + textureLoad(downsample_source, source + vec2<i32>(0, 1), layer, 0)
+        + textureLoad(downsample_source, source + vec2<i32>(1, 1), layer, 0);
+    textureStore(downsample_target, id.xy, id.z, color * 0.25);
+}
+
+struct FilterParams {
+    roughness: f32,
+    sample_count: u32,
+}
+
+@group(0) @binding(0)
+var environment: texture_cube<f32>;
+@group(0) @binding(1)
+var environment_sampler: sampler;
+@group(0) @binding(2)
+var filter_target: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3)
+var<uniform> params: FilterParams;
+
+// Cosine weighted average of the environment over the hemisphere around each direction
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(filter_target).x;
+    if id.x >= size || id.y >= size {
+        return;
+    }
+    let normal = cube_direction(id, size);
+    let up_hint = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.y) > 0.999);
+    let right = normalize(cross(up_hint, normal));
+    let up = cross(normal, right);
+
+    let delta = 0.025;
+    // A mip whose texels are about as wide as the sample spacing avoids aliasing
+    let source_size = f32(textureDimensions(environment).x);
+    let lod = max(log2(delta * source_size * 2.0 / PI), 0.0);
+    var irradiance = vec3<f32>(0.0);
+    var samples = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let tangent_sample = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = tangent_sample.x * right + tangent_sample.y * up + tangent_sample.z * normal;
+            irradiance += textureSampleLevel(environment, environment_sampler, direction, lod).rgb
+                * cos(theta) * sin(theta);
+            samples += 1.0;
+        }
+    }
+    textureStore(filter_target, id.xy, id.z, vec4<f32>(PI * irradiance / samples, 1.0));
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let half_vector = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    let up = select(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.z) > 0.999);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * half_vector.x + bitangent * half_vector.y + normal * half_vector.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Convolves the environment with the GGX lobe for one roughness, assuming the
+// view direction equals the reflection direction
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(filter_target).x;
+    if id.x >= size || id.y >= size {
+        return;
+    }
+    let normal = cube_direction(id, size);
+    let source_size = f32(textureDimensions(environment).x);
+    // Never sample finer than the texels being written
+    let min_lod = log2(source_size / f32(size));
+    let texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i += 1u) {
+        let half_vector = importance_sample_ggx(hammersley(i, params.sample_count), normal, params.roughness);
+        let light_dir = normalize(2.0 * dot(normal, half_vector) * half_vector - normal);
+        let n_dot_l = dot(normal, light_dir);
+        if n_dot_l > 0.0 {
+            // Rarely sampled directions read a blurrier mip to hide the noise
+            let n_dot_h = max(dot(normal, half_vector), 0.0);
+            let pdf = distribution_ggx(n_dot_h, params.roughness) / 4.0 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf + 0.0001);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle), min_lod);
+            color += textureSampleLevel(environment, environment_sampler, light_dir, lod).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    textureStore(filter_target, id.xy, id.z, vec4<f32>(color / max(total_weight, 0.0001), 1.0));
+}
+
+@group(0) @binding(0)
+var brdf_lut_target: texture_storage_2d<rgba16float, write>;
+
+// Scale and bias applied to F0 by the split sum approximation, indexed by
+// n_dot_v horizontally and roughness vertically
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(brdf_lut_target);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let n_dot_v = (f32(id.x) + 0.5) / f32(size.x);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+    let view_dir = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+    // Image-based lighting uses a smaller k than direct lights
+    let k = roughness * roughness / 2.0;
+
+    let sample_count = 1024u;
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < sample_count; i += 1u) {
+        let half_vector = importance_sample_ggx(hammersley(i, sample_count), normal, roughness);
+        let light_dir = normalize(2.0 * dot(view_dir, half_vector) * half_vector - view_dir);
+        let n_dot_l = max(light_dir.z, 0.0);
+        let n_dot_h = max(half_vector.z, 0.0);
+        let v_dot_h = max(dot(view_dir, half_vector), 0.0);
+        if n_dot_l > 0.0 {
+            let geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    let samples = f32(sample_count);
+    textureStore(brdf_lut_target, id.xy, vec4<f32>(scale / samples, bias / samples, 0.0, 1.0));
+}
@@ -0,0 +1,78 @@
+// Measures the average scene luminance for automatic exposure with a
+// histogram of log luminance, see `tonemap.rs`.
+
+struct Tonemap {
+    curve: u32,
+    auto_exposure: u32,
+    exposure: f32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    encode_srgb: u32,
+}
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: Tonemap;
+@group(0) @binding(2)
+var<storage, read_write> bins: array<atomic<u32>, 256>;
+@group(0) @binding(3)
+var<storage, read_write> average_luminance: f32;
+
+var<workgroup> local_histogram: array<atomic<u32>, 256>;
+var<workgroup> weighted_counts: array<u32, 256>;
+
+// Bin 0 holds black pixels, the others cover the log luminance range
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < 0.0001 {
+        return 0u;
+    }
+    let log_luminance = saturate((log2(luminance) - tonemap.min_log_luminance) / tonemap.log_luminance_range);
+    return u32(log_luminance * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn build_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_histogram[index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(t_hdr);
+    if id.x < size.x && id.y < size.y {
+        let color = textureLoad(t_hdr, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_histogram[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&bins[index], atomicLoad(&local_histogram[index]));
+}
+
+// Averages the histogram in a single workgroup and eases towards the result
+@compute @workgroup_size(16, 16, 1)
+fn average_histogram(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&bins[index]);
+    weighted_counts[index] = count * index;
+    // Ready for the next frame
+    atomicStore(&bins[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride >>= 1u) {
+        if index < stride {
+            weighted_counts[index] += weighted_counts[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index == 0u {
+        let size = textureDimensions(t_hdr);
+        // Black pixels are left out of the average
+        let lit_pixels = max(size.x * size.y - count, 1u);
+        let mean_bin = f32(weighted_counts[0]) / f32(lit_pixels) - 1.0;
+        let log_luminance = mean_bin / 254.0 * tonemap.log_luminance_range + tonemap.min_log_luminance;
+        let luminance = exp2(log_luminance);
+        average_luminance = mix(average_luminance, luminance, tonemap.adaptation);
+    }
+}
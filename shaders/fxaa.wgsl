@@ -0,0 +1,55 @@
+struct Fxaa {
+    span_max: f32,
+    reduce_multiplier: f32,
+    reduce_min: f32,
+}
+@group(1) @binding(0)
+var<uniform> fxaa: Fxaa;
+
+// Luma of the gamma encoded color, which tracks perceived edges better
+fn luma(color: vec3<f32>) -> f32 {
+    var encoded = color;
+    if post.srgb_encoded == 0u {
+        encoded = sqrt(color);
+    }
+    return dot(encoded, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// Timothy Lottes' FXAA, in the compact form without the end of edge search
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let texel = post.texel_size;
+    let color = textureSampleLevel(t_input, s_input, uv, 0.0);
+    let luma_nw = luma(textureSampleLevel(t_input, s_input, uv + vec2<f32>(-1.0, -1.0) * texel, 0.0).rgb);
+    let luma_ne = luma(textureSampleLevel(t_input, s_input, uv + vec2<f32>(1.0, -1.0) * texel, 0.0).rgb);
+    let luma_sw = luma(textureSampleLevel(t_input, s_input, uv + vec2<f32>(-1.0, 1.0) * texel, 0.0).rgb);
+    let luma_se = luma(textureSampleLevel(t_input, s_input, uv + vec2<f32>(1.0, 1.0) * texel, 0.0).rgb);
+    let luma_m = luma(color.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, perpendicular to the luma gradient
+    var direction = vec2<f32>(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * fxaa.reduce_multiplier,
+        fxaa.reduce_min,
+    );
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-fxaa.span_max), vec2<f32>(fxaa.span_max)) * texel;
+
+    let near = 0.5 * (
+        textureSampleLevel(t_input, s_input, uv + direction * (1.0 / 3.0 - 0.5), 0.0).rgb +
+        textureSampleLevel(t_input, s_input, uv + direction * (2.0 / 3.0 - 0.5), 0.0).rgb
+    );
+    let far = near * 0.5 + 0.25 * (
+        textureSampleLevel(t_input, s_input, uv - direction * 0.5, 0.0).rgb +
+        textureSampleLevel(t_input, s_input, uv + direction * 0.5, 0.0).rgb
+    );
+    // The wider blur is only kept if it didn't reach past the local contrast
+    let luma_far = luma(far);
+    let result = select(far, near, luma_far < luma_min || luma_far > luma_max);
+    return vec4<f32>(result, color.a);
+}
@@ -6,13 +6,28 @@ struct Camera {
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
+const MAX_LIGHTS: u32 = 16u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+
 struct Light {
     position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    radius: f32,
     color: vec3<f32>,
+    intensity: f32,
+    inner_cos: f32,
+    outer_cos: f32,
+    shadow_index: i32,
+}
+struct Lights {
+    lights: array<Light, MAX_LIGHTS>,
+    ambient: vec3<f32>,
+    count: u32,
 }
 
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<uniform> lights: Lights;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -23,13 +38,20 @@ struct VertexOutput {
     @location(0) color: vec3<f32>,
 };
 
+// Each instance draws the marker for the light with the same index
 @vertex
 fn vs_main(
     model: VertexInput,
+    @builtin(instance_index) instance: u32,
 ) -> VertexOutput {
+    let light = lights.lights[min(instance, MAX_LIGHTS - 1u)];
     let scale = 0.25;
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
+    // Directional lights have no position, so their marker collapses to nothing
+    if light.kind == LIGHT_DIRECTIONAL {
+        out.clip_position = vec4<f32>(0.0);
+    }
     out.color = light.color;
     return out;
 }
@@ -0,0 +1,39 @@
+// Put in front of `shader.wgsl` when the adapter supports cube array
+// textures, with one depth cube per point shadow slot
+
+@group(2) @binding(4)
+var t_point_shadow: texture_depth_cube_array;
+
+// Directions for filtering point shadows, spread so a few taps cover the
+// kernel evenly instead of sampling a whole (2r + 1)^3 cube
+var<private> point_shadow_offsets: array<vec3<f32>, 20> = array<vec3<f32>, 20>(
+    vec3<f32>(1.0, 1.0, 1.0), vec3<f32>(1.0, -1.0, 1.0), vec3<f32>(-1.0, -1.0, 1.0), vec3<f32>(-1.0, 1.0, 1.0),
+    vec3<f32>(1.0, 1.0, -1.0), vec3<f32>(1.0, -1.0, -1.0), vec3<f32>(-1.0, -1.0, -1.0), vec3<f32>(-1.0, 1.0, -1.0),
+    vec3<f32>(1.0, 1.0, 0.0), vec3<f32>(1.0, -1.0, 0.0), vec3<f32>(-1.0, -1.0, 0.0), vec3<f32>(-1.0, 1.0, 0.0),
+    vec3<f32>(1.0, 0.0, 1.0), vec3<f32>(-1.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, -1.0), vec3<f32>(-1.0, 0.0, -1.0),
+    vec3<f32>(0.0, 1.0, 1.0), vec3<f32>(0.0, -1.0, 1.0), vec3<f32>(0.0, -1.0, -1.0), vec3<f32>(0.0, 1.0, -1.0),
+);
+
+// Point shadow cubes store the distance to the light over its radius
+fn point_shadow_factor(light: Light, world_position: vec3<f32>) -> f32 {
+    let slot = light.shadow_index;
+    let to_fragment = world_position - light.position;
+    let depth = length(to_fragment) / max(light.radius, 0.0001) - shadows.point_depth_bias;
+    // Implicit derivatives rather than textureSampleCompareLevel, which the GL
+    // backend can't translate for cube arrays. The map has one mip level and
+    // this is only reached in uniform control flow.
+    if shadows.pcf_radius == 0u {
+        return textureSampleCompare(t_point_shadow, s_shadow, to_fragment, slot, depth);
+    }
+    // Roughly pcf_radius cube face texels at the fragment's distance, widened
+    // further from the camera where the extra blur isn't noticed
+    let texel = 2.0 * length(to_fragment) / f32(textureDimensions(t_point_shadow).x);
+    let view_distance = length(camera.view_pos.xyz - world_position);
+    let disk = texel * f32(shadows.pcf_radius) * (1.0 + view_distance / max(light.radius, 0.0001));
+    var lit = 0.0;
+    for (var i = 0; i < 20; i += 1) {
+        let offset = point_shadow_offsets[i] * disk;
+        lit += textureSampleCompare(t_point_shadow, s_shadow, to_fragment + offset, slot, depth);
+    }
+    return lit / 20.0;
+}
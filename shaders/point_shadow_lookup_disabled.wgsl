@@ -0,0 +1,9 @@
+// Put in front of `shader.wgsl` instead of `point_shadow_lookup.wgsl` on
+// adapters without cube array textures, where point lights cast no shadows
+
+@group(2) @binding(4)
+var t_point_shadow: texture_depth_cube;
+
+fn point_shadow_factor(light: Light, world_position: vec3<f32>) -> f32 {
+    return 1.0;
+}
@@ -0,0 +1,30 @@
+// Put in front of every post-processing effect, after `srgb.wgsl`, with the
+// input from the previous pass in group 0 and the vertex stage. Effects
+// declare their own uniform and textures in group 1.
+
+struct PostProcess {
+    texel_size: vec2<f32>,
+    // Colors are stored with the sRGB curve applied rather than linear
+    srgb_encoded: u32,
+}
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> post: PostProcess;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32(index & 1u) * 4.0 - 1.0, f32(index >> 1u) * 4.0 - 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    return out;
+}
@@ -20,9 +20,10 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
-    @location(1) tangent_position: vec3<f32>,
-    @location(2) tangent_light_position: vec3<f32>,
-    @location(3) tangent_view_position: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
 };
 
 struct Camera {
@@ -46,23 +47,16 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
         instance.normal_matrix_2
     );
 
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
-    let tangent_matrix = transpose(mat3x3<f32>(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
-
     let world_position = model_matrix * vec4<f32>(model.position, 1.0);
 
+    // Lighting happens in world space so any number of lights can share the interpolants
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.tex_coords = model.tex_coords;
-    out.tangent_position = tangent_matrix * world_position.xyz;
-    out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
-    out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
+    out.world_normal = normalize(normal_matrix * model.normal);
+    out.world_tangent = normalize(normal_matrix * model.tangent);
+    out.world_bitangent = normalize(normal_matrix * model.bitangent);
     return out;
 }
 
@@ -75,41 +69,279 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+struct Material {
+    diffuse_color: vec3<f32>,
+    alpha: f32,
+    specular_color: vec3<f32>,
+    shininess: f32,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    shading_model: u32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+@group(0) @binding(5)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(6)
+var s_metallic_roughness: sampler;
+@group(0) @binding(7)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(8)
+var s_occlusion: sampler;
+@group(0) @binding(9)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(10)
+var s_emissive: sampler;
+
+const SHADING_PHONG: u32 = 0u;
+const SHADING_PBR: u32 = 1u;
+const PI: f32 = 3.14159265359;
+
+const MAX_LIGHTS: u32 = 16u;
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
 struct Light {
     position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    radius: f32,
     color: vec3<f32>,
+    intensity: f32,
+    inner_cos: f32,
+    outer_cos: f32,
+    shadow_index: i32,
+}
+struct Lights {
+    lights: array<Light, MAX_LIGHTS>,
+    ambient: vec3<f32>,
+    count: u32,
 }
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<uniform> lights: Lights;
+
+const MAX_SHADOW_MAPS: u32 = 8u;
+
+struct Shadows {
+    view_proj: array<mat4x4<f32>, MAX_SHADOW_MAPS>,
+    cascade_count: u32,
+    pcf_radius: u32,
+    texel_size: f32,
+    point_depth_bias: f32,
+}
+@group(2) @binding(1)
+var<uniform> shadows: Shadows;
+@group(2) @binding(2)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(3)
+var s_shadow: sampler_comparison;
+// Binding 4 and `point_shadow_factor` come from `point_shadow_lookup.wgsl`,
+// or `point_shadow_lookup_disabled.wgsl` where cube arrays are unsupported
+
+struct Environment {
+    intensity: f32,
+    max_lod: f32,
+    enabled: u32,
+}
+@group(2) @binding(5)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(6)
+var t_prefiltered: texture_cube<f32>;
+@group(2) @binding(7)
+var t_brdf_lut: texture_2d<f32>;
+@group(2) @binding(8)
+var s_environment: sampler;
+@group(2) @binding(9)
+var<uniform> environment: Environment;
+
+// Returns the position in shadow map space, or a negative depth outside the map
+fn shadow_coords(layer: u32, world_position: vec3<f32>) -> vec3<f32> {
+    let clip = shadows.view_proj[layer] * vec4<f32>(world_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if clip.w <= 0.0 || any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return vec3<f32>(0.0, 0.0, -1.0);
+    }
+    return vec3<f32>(uv, ndc.z);
+}
+
+// Percentage closer filtering over a (2 * pcf_radius + 1)^2 texel square
+fn sample_shadow(layer: u32, coords: vec3<f32>) -> f32 {
+    let radius = i32(shadows.pcf_radius);
+    var lit = 0.0;
+    for (var x = -radius; x <= radius; x += 1) {
+        for (var y = -radius; y <= radius; y += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadows.texel_size;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, coords.xy + offset, layer, coords.z);
+        }
+    }
+    let taps = 2 * radius + 1;
+    return lit / f32(taps * taps);
+}
+
+fn shadow_factor(light: Light, world_position: vec3<f32>) -> f32 {
+    if light.shadow_index < 0 {
+        return 1.0;
+    }
+    if light.kind == LIGHT_POINT {
+        return point_shadow_factor(light, world_position);
+    }
+    let first = u32(light.shadow_index);
+    // Directional lights use the first cascade that covers the fragment
+    let layers = select(1u, shadows.cascade_count, light.kind == LIGHT_DIRECTIONAL);
+    for (var cascade = 0u; cascade < layers; cascade += 1u) {
+        let layer = first + cascade;
+        let coords = shadow_coords(layer, world_position);
+        if coords.z >= 0.0 {
+            return sample_shadow(layer, coords);
+        }
+    }
+    return 1.0;
+}
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view * light;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(saturate(1.0 - cos_theta), 5.0);
+}
+
+// Rough surfaces reflect less at grazing angles, used for the ambient term
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(saturate(1.0 - cos_theta), 5.0);
+}
+
+// Cook-Torrance BRDF times the cosine term for one light
+fn cook_torrance(
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    light_dir: vec3<f32>,
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    let half_dir = normalize(view_dir + light_dir);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness)
+        * fresnel / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+    // Metals have no diffuse reflection
+    let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * n_dot_l;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords)
+        * vec4<f32>(material.diffuse_color, material.alpha);
     let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let occlusion_sample = textureSample(t_occlusion, s_occlusion, in.tex_coords).r;
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive;
 
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let tangent_normal = (object_normal.xyz * 2.0 - 1.0) * vec3<f32>(material.normal_scale, material.normal_scale, 1.0);
+    let normal = normalize(tangent_matrix * tangent_normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
 
-    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
-    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
-    let half_dir = normalize(view_dir + light_dir);
+    let metallic = saturate(material.metallic * metallic_roughness.b);
+    // Very low roughness turns point lights into invisible specks
+    let roughness = clamp(material.roughness * metallic_roughness.g, 0.04, 1.0);
+    let occlusion = mix(1.0, occlusion_sample, material.occlusion_strength);
 
-    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    // Image-based lighting with the split sum approximation
+    let n_dot_v = max(dot(normal, view_dir), 0.0);
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, normal, 0.0).rgb;
+    let reflected = reflect(-view_dir, normal);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflected, roughness * environment.max_lod).rgb;
+    let brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness), 0.0).rg;
 
-    // let reflect_dir = reflect(-light_dir, in.world_normal);
-    // let specular_strength = pow(max(dot(view_dir, reflect_dir), 0.0), 32.0);
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    var ambient_color = lights.ambient;
+    var diffuse_color = vec3<f32>(0.0);
+    var specular_color = vec3<f32>(0.0);
+    var pbr_color = vec3<f32>(0.0);
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i += 1u) {
+        let light = lights.lights[i];
+        let light_color = light.color * light.intensity;
 
-    let coordinate_system = mat3x3<f32>(
-        vec3(1.0, 0.0, 0.0),
-        vec3(0.0, 1.0, 0.0),
-        vec3(0.0, 0.0, 1.0),
-    );
+        var light_dir: vec3<f32>;
+        var attenuation = 1.0;
+        if light.kind == LIGHT_DIRECTIONAL {
+            light_dir = -normalize(light.direction);
+        } else {
+            let to_light = light.position - in.world_position;
+            let distance = max(length(to_light), 0.0001);
+            light_dir = to_light / distance;
+
+            // Smooth window so the light reaches exactly zero at its radius,
+            // and lights with no radius reach nothing
+            let falloff = saturate(1.0 - pow(distance / max(light.radius, 0.0001), 4.0));
+            attenuation = falloff * falloff;
+            if light.kind == LIGHT_SPOT {
+                let cos_angle = dot(-light_dir, normalize(light.direction));
+                attenuation *= smoothstep(light.outer_cos, light.inner_cos, cos_angle);
+            }
+        }
+
+        attenuation *= shadow_factor(light, in.world_position);
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+        if material.shading_model == SHADING_PBR {
+            pbr_color += cook_torrance(normal, view_dir, light_dir, object_color.rgb, metallic, roughness)
+                * light_color * attenuation;
+            continue;
+        }
 
-    return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+        let half_dir = normalize(view_dir + light_dir);
+        let diffuse_strength = max(dot(normal, light_dir), 0.0);
+        diffuse_color += light_color * diffuse_strength * attenuation;
+
+        let specular_strength = pow(max(dot(normal, half_dir), 0.0), material.shininess);
+        specular_color += specular_strength * light_color * attenuation * material.specular_color;
+    }
+
+    var result: vec3<f32>;
+    if material.shading_model == SHADING_PBR {
+        var ambient = ambient_color * object_color.rgb;
+        if environment.enabled != 0u {
+            let f0 = mix(vec3<f32>(0.04), object_color.rgb, metallic);
+            let fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+            let diffuse = (1.0 - fresnel) * (1.0 - metallic) * irradiance * object_color.rgb;
+            let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+            ambient = (diffuse + specular) * environment.intensity;
+        }
+        result = ambient * occlusion + pbr_color;
+    } else {
+        if environment.enabled != 0u {
+            ambient_color = irradiance * environment.intensity;
+        }
+        result = (ambient_color * occlusion + diffuse_color + specular_color) * object_color.rgb;
+    }
+
+    return vec4<f32>(result + emissive, object_color.a);
+}
@@ -0,0 +1,51 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    sky_inv_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+const SKY_GRADIENT: u32 = 1u;
+
+struct Sky {
+    zenith: vec3<f32>,
+    mode: u32,
+    horizon: vec3<f32>,
+    ground: vec3<f32>,
+}
+@group(1) @binding(0)
+var<uniform> sky: Sky;
+@group(1) @binding(1)
+var t_sky: texture_cube<f32>;
+@group(1) @binding(2)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+// One triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32(index & 1u) * 4.0 - 1.0, f32(index >> 1u) * 4.0 - 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let far = camera.sky_inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w);
+
+    if sky.mode == SKY_GRADIENT {
+        let up = direction.y;
+        let above = mix(sky.horizon, sky.zenith, sqrt(saturate(up)));
+        let below = mix(sky.horizon, sky.ground, saturate(-up * 4.0));
+        return vec4<f32>(select(below, above, up >= 0.0), 1.0);
+    }
+    return vec4<f32>(textureSampleLevel(t_sky, s_sky, direction, 0.0).rgb, 1.0);
+}
@@ -0,0 +1,65 @@
+struct Tonemap {
+    curve: u32,
+    auto_exposure: u32,
+    exposure: f32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    encode_srgb: u32,
+}
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: Tonemap;
+@group(0) @binding(2)
+var<storage, read> average_luminance: f32;
+
+// One triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let ndc = vec2<f32>(f32(index & 1u) * 4.0 - 1.0, f32(index >> 1u) * 4.0 - 1.0);
+    return vec4<f32>(ndc, 0.0, 1.0);
+}
+
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return saturate((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14));
+}
+
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn filmic(x: vec3<f32>) -> vec3<f32> {
+    let white_point = 11.2;
+    return hable(x * 2.0) / hable(vec3<f32>(white_point));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(t_hdr, vec2<i32>(position.xy), 0).rgb;
+
+    var exposure = exp2(tonemap.exposure);
+    if tonemap.auto_exposure != 0u {
+        // Brings the average luminance to middle gray
+        exposure *= 0.18 / max(average_luminance, 0.0001);
+    }
+    let color = hdr * exposure;
+
+    var mapped: vec3<f32>;
+    switch tonemap.curve {
+        // TonemapOperator::Reinhard and Filmic
+        case 1u: { mapped = color / (1.0 + color); }
+        case 2u: { mapped = filmic(color); }
+        default: { mapped = aces(color); }
+    }
+    if tonemap.encode_srgb != 0u {
+        mapped = linear_to_srgb(mapped);
+    }
+    return vec4<f32>(mapped, 1.0);
+}
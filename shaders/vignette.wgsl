@@ -0,0 +1,19 @@
+struct Vignette {
+    intensity: f32,
+    radius: f32,
+    smoothness: f32,
+}
+@group(1) @binding(0)
+var<uniform> vignette: Vignette;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, uv);
+    // Distance from the center, corrected for the aspect ratio so the vignette
+    // stays round and scaled so the corners are at 1
+    let aspect = vec2<f32>(post.texel_size.y / post.texel_size.x, 1.0);
+    let distance = length((uv - 0.5) * aspect) / length(0.5 * aspect);
+    let falloff = 1.0 - smoothstep(vignette.radius - vignette.smoothness, vignette.radius, distance);
+    let shade = mix(1.0, falloff, vignette.intensity);
+    return vec4<f32>(color.rgb * shade, color.a);
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+
+use crate::{
+    pack::{Compression, MAGIC, VERSION},
+    vfs::Mount,
+};
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub offset: u64,
+    pub stored_size: u64,
+    pub size: u64,
+    pub compression: Compression,
+    pub hash: [u8; 32],
+}
+
+/// A packed asset archive written by `pack::ArchiveWriter`.
+pub struct Archive<R> {
+    entries: Vec<ArchiveEntry>,
+    index: HashMap<String, usize>,
+    reader: Mutex<R>,
+}
+
+impl Archive<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        Self::new(BufReader::new(file)).with_context(|| format!("Failed to load {:?}", path))
+    }
+}
+
+impl Archive<Cursor<&'static [u8]>> {
+    pub fn from_static(data: &'static [u8]) -> anyhow::Result<Self> {
+        Self::new(Cursor::new(data))
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            anyhow::bail!("Not an asset archive");
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            anyhow::bail!("Unsupported archive version {}", version);
+        }
+
+        let count = read_u32(&mut reader)?;
+        // The count isn't trusted until the entries have actually been read
+        let mut entries = Vec::with_capacity(count.min(1024) as usize);
+        for _ in 0..count {
+            let mut path_len = [0; 2];
+            reader.read_exact(&mut path_len)?;
+            let mut path = vec![0; u16::from_le_bytes(path_len) as usize];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).context("Archive path is not valid UTF-8")?;
+
+            let offset = read_u64(&mut reader)?;
+            let stored_size = read_u64(&mut reader)?;
+            let size = read_u64(&mut reader)?;
+            let mut compression = [0; 1];
+            reader.read_exact(&mut compression)?;
+            let mut hash = [0; 32];
+            reader.read_exact(&mut hash)?;
+
+            entries.push(ArchiveEntry {
+                path,
+                offset,
+                stored_size,
+                size,
+                compression: Compression::from_u8(compression[0])?,
+                hash,
+            });
+        }
+
+        let len = reader.seek(SeekFrom::End(0))?;
+        for entry in &entries {
+            if entry
+                .offset
+                .checked_add(entry.stored_size)
+                .is_none_or(|end| end > len)
+            {
+                anyhow::bail!("{:?} extends past the end of the archive", entry.path);
+            }
+        }
+
+        let index = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.path.clone(), i))
+            .collect();
+        Ok(Archive {
+            entries,
+            index,
+            reader: Mutex::new(reader),
+        })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&ArchiveEntry> {
+        self.index.get(path).map(|&i| &self.entries[i])
+    }
+
+    pub fn read(&self, path: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match self.entry(path) {
+            Some(entry) => self.read_entry(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read_entry(&self, entry: &ArchiveEntry) -> std::io::Result<Vec<u8>> {
+        let mut stored = vec![0; entry.stored_size as usize];
+        {
+            let mut reader = self.reader.lock().unwrap();
+            reader.seek(SeekFrom::Start(entry.offset))?;
+            reader.read_exact(&mut stored)?;
+        }
+
+        let data = entry.compression.decompress(stored, entry.size)?;
+        if data.len() as u64 != entry.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{:?} has the wrong size", entry.path),
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Checks every entry against its content hash, returning the paths that don't match.
+    pub fn verify(&self) -> Vec<String> {
+        let mut mismatched = Vec::new();
+        for entry in &self.entries {
+            let matches = self
+                .read_entry(entry)
+                .map(|data| *blake3::hash(&data).as_bytes() == entry.hash)
+                .unwrap_or(false);
+            if !matches {
+                mismatched.push(entry.path.clone());
+            }
+        }
+        mismatched
+    }
+}
+
+/// Mounts the archive entries below `root`, e.g. `models`, as a VFS root.
+pub struct ArchiveMount<R> {
+    pub archive: Arc<Archive<R>>,
+    pub root: String,
+}
+
+impl<R> ArchiveMount<R> {
+    pub fn new(archive: Arc<Archive<R>>, root: &str) -> Self {
+        ArchiveMount {
+            archive,
+            root: root.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl<R: Read + Seek + Send> Mount for ArchiveMount<R> {
+    fn read(&self, path: &str) -> std::io::Result<Option<Vec<u8>>> {
+        if self.root.is_empty() {
+            self.archive.read(path)
+        } else {
+            self.archive.read(&format!("{}/{}", self.root, path))
+        }
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::ArchiveWriter;
+
+    fn pack(compress: bool) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(compress);
+        writer.add("models/cube.obj", b"v 0 0 0\n".repeat(64));
+        writer.add("shaders/shader.wgsl", b"@vertex fn vs_main() {}".to_vec());
+        writer.add("images/empty.png", Vec::new());
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn packed_files_round_trip() {
+        for compress in [false, true] {
+            let archive = Archive::new(Cursor::new(pack(compress))).unwrap();
+
+            let paths = archive
+                .entries()
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                paths,
+                ["images/empty.png", "models/cube.obj", "shaders/shader.wgsl"]
+            );
+            let cube = archive.entry("models/cube.obj").unwrap();
+            assert_eq!(cube.compression == Compression::Deflate, compress);
+            assert_eq!(cube.size, 8 * 64);
+
+            assert!(archive.verify().is_empty());
+            assert_eq!(
+                archive.read("models/cube.obj").unwrap().unwrap(),
+                b"v 0 0 0\n".repeat(64)
+            );
+            assert_eq!(archive.read("images/empty.png").unwrap().unwrap(), b"");
+            assert!(archive.read("models/missing.obj").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn mounts_read_below_their_root() {
+        let archive = Arc::new(Archive::new(Cursor::new(pack(true))).unwrap());
+        let models = ArchiveMount::new(archive.clone(), "models/");
+        let root = ArchiveMount::new(archive, "");
+
+        assert!(models.read("cube.obj").unwrap().is_some());
+        assert!(models.read("shaders/shader.wgsl").unwrap().is_none());
+        assert!(root.read("shaders/shader.wgsl").unwrap().is_some());
+    }
+
+    #[test]
+    fn verify_reports_corrupted_entries() {
+        let mut data = pack(false);
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let archive = Archive::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.verify(), ["shaders/shader.wgsl"]);
+    }
+
+    #[test]
+    fn truncated_archives_are_rejected() {
+        let data = pack(false);
+        assert!(Archive::new(Cursor::new(data[..data.len() - 1].to_vec())).is_err());
+        assert!(Archive::new(Cursor::new(data[..20].to_vec())).is_err());
+        assert!(Archive::new(Cursor::new(b"ZIP!".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::Path,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    model::{MaterialTextures, Model},
+    resources,
+    texture::{SamplerCache, SamplerOptions, Texture, TextureFilter, TextureKind},
+    vfs,
+};
+
+/// A reference-counted handle to an asset owned by an `AssetServer`.
+pub struct Handle<T> {
+    id: u64,
+    token: Rc<()>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub fn ref_count(&self) -> usize {
+        Rc::strong_count(&self.token)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            id: self.id,
+            token: self.token.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle").field("id", &self.id).finish()
+    }
+}
+
+struct AssetEntry<K, T> {
+    key: K,
+    asset: Rc<T>,
+    token: Weak<()>,
+}
+
+struct Assets<K, T> {
+    next_id: u64,
+    entries: HashMap<u64, AssetEntry<K, T>>,
+    ids: HashMap<K, u64>,
+}
+
+impl<K: Hash + Eq + Clone, T> Assets<K, T> {
+    fn new() -> Self {
+        Assets {
+            next_id: 0,
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    fn handle(&mut self, key: &K) -> Option<Handle<T>> {
+        let id = *self.ids.get(key)?;
+        let entry = self.entries.get_mut(&id)?;
+        let token = entry.token.upgrade().unwrap_or_else(|| {
+            let token = Rc::new(());
+            entry.token = Rc::downgrade(&token);
+            token
+        });
+        Some(Handle {
+            id,
+            token,
+            _marker: PhantomData,
+        })
+    }
+
+    fn shared(&self, key: &K) -> Option<Rc<T>> {
+        let id = self.ids.get(key)?;
+        self.entries.get(id).map(|entry| entry.asset.clone())
+    }
+
+    fn insert(&mut self, key: K, asset: Rc<T>) -> Handle<T> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let token = Rc::new(());
+        self.ids.insert(key.clone(), id);
+        self.entries.insert(
+            id,
+            AssetEntry {
+                key,
+                asset,
+                token: Rc::downgrade(&token),
+            },
+        );
+
+        Handle {
+            id,
+            token,
+            _marker: PhantomData,
+        }
+    }
+
+    fn replace(&mut self, key: &K, asset: Rc<T>) -> Option<Rc<T>> {
+        let id = self.ids.get(key)?;
+        let entry = self.entries.get_mut(id)?;
+        Some(std::mem::replace(&mut entry.asset, asset))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&K, &Rc<T>)> {
+        self.entries
+            .values()
+            .map(|entry| (&entry.key, &entry.asset))
+    }
+
+    fn get(&self, handle: &Handle<T>) -> Option<&Rc<T>> {
+        self.entries.get(&handle.id).map(|entry| &entry.asset)
+    }
+
+    /// Removes the asset behind `handle` unless other handles to it are still live.
+    fn unload(&mut self, handle: Handle<T>) -> bool {
+        let id = handle.id;
+        drop(handle);
+        match self.entries.get(&id) {
+            Some(entry) if entry.token.strong_count() == 0 => self.remove(id).is_some(),
+            _ => false,
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Rc<T>> {
+        let entry = self.entries.remove(&id)?;
+        self.ids.remove(&entry.key);
+        Some(entry.asset)
+    }
+
+    /// Drops assets that have no live handles and are not shared with another asset.
+    fn free_unused(&mut self) -> usize {
+        let unused = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.token.strong_count() == 0 && Rc::strong_count(&entry.asset) == 1
+            })
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for &id in &unused {
+            self.remove(id);
+        }
+        unused.len()
+    }
+}
+
+pub struct AssetServer {
+    models: Assets<String, Model>,
+    textures: Assets<TextureKey, Texture>,
+    // Created on first use and shared by every material
+    default_textures: Option<MaterialTextures>,
+    pub samplers: SamplerCache,
+    /// Filtering for textures loaded by models from now on, unless their
+    /// material asks for something else.
+    pub texture_filter: TextureFilter,
+}
+
+// Samplers aren't part of the key, materials pair them with the texture
+// through `samplers` when they create their bind group
+type TextureKey = (String, TextureKind);
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetServer {
+    pub fn new() -> Self {
+        AssetServer {
+            models: Assets::new(),
+            textures: Assets::new(),
+            default_textures: None,
+            samplers: SamplerCache::new(),
+            texture_filter: TextureFilter::default(),
+        }
+    }
+
+    pub async fn load_model(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Handle<Model>> {
+        let key = asset_key(file_name);
+        if let Some(handle) = self.models.handle(&key) {
+            return Ok(handle);
+        }
+
+        let model =
+            resources::load_model_with_assets(file_name, device, queue, layout, self).await?;
+        Ok(self.models.insert(key, Rc::new(model)))
+    }
+
+    /// Loads a texture with the default sampler. Bind it with another one from
+    /// `samplers` to sample it differently.
+    pub async fn load_texture(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        kind: TextureKind,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let key = (asset_key(file_name), kind);
+        if let Some(handle) = self.textures.handle(&key) {
+            return Ok(handle);
+        }
+
+        let texture = self
+            .load_texture_uncached(file_name, device, queue, kind)
+            .await?;
+        Ok(self.textures.insert(key, Rc::new(texture)))
+    }
+
+    /// The white and flat normal maps materials start from, shared by every
+    /// material instead of being uploaded again for each one.
+    pub fn default_material_textures(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<MaterialTextures> {
+        if let Some(textures) = &self.default_textures {
+            return Ok(textures.clone());
+        }
+        let textures = MaterialTextures::new(device, queue, &mut self.samplers)?;
+        self.default_textures = Some(textures.clone());
+        Ok(textures)
+    }
+
+    pub fn model(&self, handle: &Handle<Model>) -> Option<&Model> {
+        self.models.get(handle).map(Rc::as_ref)
+    }
+
+    pub fn texture(&self, handle: &Handle<Texture>) -> Option<&Texture> {
+        self.textures.get(handle).map(Rc::as_ref)
+    }
+
+    /// Shares ownership of a loaded model, e.g. to add it to a `Scene`.
+    pub fn shared_model(&self, handle: &Handle<Model>) -> Option<Rc<Model>> {
+        self.models.get(handle).cloned()
+    }
+
+    /// Removes a model from the cache, unless other handles to it are still
+    /// live. Returns whether it was removed. Its GPU resources are released
+    /// once nothing else shares the model.
+    pub fn unload_model(&mut self, handle: Handle<Model>) -> bool {
+        self.models.unload(handle)
+    }
+
+    pub fn unload_texture(&mut self, handle: Handle<Texture>) -> bool {
+        self.textures.unload(handle)
+    }
+
+    /// Frees every asset whose handles have all been dropped, returning how many were freed.
+    pub fn free_unused(&mut self) -> usize {
+        // Models go first so the textures they were holding become unused too
+        self.models.free_unused() + self.textures.free_unused()
+    }
+
+    /// Reloads every cached asset that depends on `file_name`, returning the
+    /// replaced models as `(old, new)` pairs so they can be swapped wherever
+    /// they are shared. On error the old versions of all of them are kept.
+    pub async fn reload(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Vec<(Rc<Model>, Rc<Model>)>> {
+        let file_name = asset_key(file_name);
+        let texture_keys = self
+            .textures
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(name, _)| *name == file_name)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut textures = Vec::new();
+        for key in texture_keys {
+            let texture = self
+                .load_texture_uncached(&file_name, device, queue, key.1)
+                .await?;
+            textures.push((key, Rc::new(texture)));
+        }
+
+        // The new textures go in first so rebuilt models pick them up, and
+        // are swapped back out if one of the models fails
+        let mut old_textures = Vec::new();
+        let mut stale_models = Vec::new();
+        for (key, texture) in textures {
+            if let Some(old) = self.textures.replace(&key, texture) {
+                // Materials hold bind groups for the old texture, so their models are rebuilt
+                stale_models.extend(
+                    self.models
+                        .iter()
+                        .filter(|(_, model)| uses_texture(model, &old))
+                        .map(|(key, _)| key.clone()),
+                );
+                old_textures.push((key, old));
+            }
+        }
+
+        // MTL files and glTF buffers are only referenced from their model, which shares their name
+        let stem = Path::new(&file_name).with_extension("");
+        stale_models.extend(
+            self.models
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|key| **key == file_name || Path::new(key).with_extension("") == stem)
+                .cloned(),
+        );
+        stale_models.sort();
+        stale_models.dedup();
+
+        let mut models = Vec::new();
+        for key in stale_models {
+            match resources::load_model_with_assets(&key, device, queue, layout, self).await {
+                Ok(model) => models.push((key, Rc::new(model))),
+                Err(e) => {
+                    for (key, old) in old_textures {
+                        self.textures.replace(&key, old);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(models
+            .into_iter()
+            .filter_map(|(key, model)| Some((self.models.replace(&key, model.clone())?, model)))
+            .collect())
+    }
+
+    pub(crate) fn cached_texture(&self, key: &str, kind: TextureKind) -> Option<Rc<Texture>> {
+        self.textures.shared(&(asset_key(key), kind))
+    }
+
+    pub(crate) fn insert_texture(
+        &mut self,
+        key: &str,
+        kind: TextureKind,
+        texture: Texture,
+    ) -> Rc<Texture> {
+        let texture = Rc::new(texture);
+        self.textures
+            .insert((asset_key(key), kind), texture.clone());
+        texture
+    }
+
+    pub(crate) async fn shared_texture(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        kind: TextureKind,
+    ) -> anyhow::Result<Rc<Texture>> {
+        if let Some(texture) = self.cached_texture(file_name, kind) {
+            return Ok(texture);
+        }
+
+        let texture = self
+            .load_texture_uncached(file_name, device, queue, kind)
+            .await?;
+        Ok(self.insert_texture(file_name, kind, texture))
+    }
+
+    /// The default sampler, for textures until a material pairs them with its own.
+    pub(crate) fn default_sampler(&mut self, device: &wgpu::Device) -> Rc<wgpu::Sampler> {
+        self.samplers.get(device, SamplerOptions::default())
+    }
+
+    async fn load_texture_uncached(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        kind: TextureKind,
+    ) -> anyhow::Result<Texture> {
+        let sampler = self.default_sampler(device);
+        resources::load_texture(file_name, device, queue, kind, sampler).await
+    }
+}
+
+// Spellings of the same path share an entry, e.g. `./cube.obj` and `cube.obj`
+fn asset_key(file_name: &str) -> String {
+    vfs::normalize(file_name).unwrap_or_else(|| file_name.to_string())
+}
+
+fn uses_texture(model: &Model, texture: &Rc<Texture>) -> bool {
+    model.materials.iter().any(|material| {
+        material
+            .textures
+            .all()
+            .into_iter()
+            .any(|map| Rc::ptr_eq(&map.texture, texture))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_are_shared_per_key() {
+        let mut assets = Assets::<String, u32>::new();
+        let a = assets.insert("a".to_string(), Rc::new(1));
+        let b = assets.handle(&"a".to_string()).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.ref_count(), 2);
+        assert!(assets.handle(&"b".to_string()).is_none());
+
+        drop(b);
+        assert_eq!(a.ref_count(), 1);
+        assert_eq!(assets.get(&a).map(|v| **v), Some(1));
+    }
+
+    #[test]
+    fn free_unused_keeps_assets_with_handles_or_shares() {
+        let mut assets = Assets::<String, u32>::new();
+        let a = assets.insert("a".to_string(), Rc::new(1));
+        let b = assets.insert("b".to_string(), Rc::new(2));
+        let c = assets.insert("c".to_string(), Rc::new(3));
+        let shared = assets.shared(&"b".to_string()).unwrap();
+        drop(b);
+        drop(c);
+
+        assert_eq!(assets.free_unused(), 1);
+        assert!(assets.get(&a).is_some());
+        assert!(assets.shared(&"b".to_string()).is_some());
+        assert!(assets.shared(&"c".to_string()).is_none());
+
+        drop(shared);
+        assert_eq!(assets.free_unused(), 1);
+        assert!(assets.shared(&"b".to_string()).is_none());
+    }
+
+    #[test]
+    fn dropped_handles_can_be_reissued() {
+        let mut assets = Assets::<String, u32>::new();
+        drop(assets.insert("a".to_string(), Rc::new(1)));
+        let a = assets.handle(&"a".to_string()).unwrap();
+        assert_eq!(a.ref_count(), 1);
+        assert_eq!(assets.free_unused(), 0);
+    }
+
+    #[test]
+    fn unload_waits_for_the_last_handle() {
+        let mut assets = Assets::<String, u32>::new();
+        let a = assets.insert("a".to_string(), Rc::new(1));
+        let other = a.clone();
+
+        assert!(!assets.unload(a));
+        assert_eq!(assets.get(&other).map(|v| **v), Some(1));
+        assert!(assets.unload(other));
+        assert!(assets.shared(&"a".to_string()).is_none());
+    }
+
+    #[test]
+    fn keys_ignore_path_spelling() {
+        assert_eq!(asset_key("./cube/cube.obj"), "cube/cube.obj");
+        assert_eq!(asset_key("cube\\textures/../cube.obj"), "cube/cube.obj");
+    }
+
+    #[test]
+    fn replace_keeps_handles_valid() {
+        let mut assets = Assets::<String, u32>::new();
+        let a = assets.insert("a".to_string(), Rc::new(1));
+        let old = assets.replace(&"a".to_string(), Rc::new(2)).unwrap();
+        assert_eq!(*old, 1);
+        assert_eq!(assets.get(&a).map(|v| **v), Some(2));
+    }
+}
@@ -0,0 +1,1119 @@
+//! CPU decoder for 2D ASTC blocks of every size, in the LDR, sRGB and HDR
+//! profiles.
+
+// Every range ASTC stores integers in, as levels made of trits, quints and bits
+const RANGES: [u32; 21] = [
+    2, 3, 4, 5, 6, 8, 10, 12, 16, 20, 24, 32, 40, 48, 64, 80, 96, 128, 160, 192, 256,
+];
+
+// Magenta, which blocks with invalid encodings decode to
+const ERROR_COLOR: [u16; 4] = [0xFFFF, 0, 0xFFFF, 0xFFFF];
+
+/// How decoded values are turned into texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Profile {
+    /// RGBA8 texels, with HDR blocks decoding to magenta.
+    Ldr,
+    /// Like `Ldr`, but with endpoints expanded for sRGB decoding.
+    Srgb,
+    /// RGBA16 float texels.
+    Hdr,
+}
+
+impl Profile {
+    /// Bytes per decoded texel.
+    pub(crate) fn texel_size(self) -> usize {
+        match self {
+            Profile::Ldr | Profile::Srgb => 4,
+            Profile::Hdr => 8,
+        }
+    }
+}
+
+/// Decodes a 16 byte block of `width` x `height` texels into `texels`, row by
+/// row, `profile.texel_size()` bytes each.
+pub(crate) fn decode_block(
+    block: &[u8],
+    width: usize,
+    height: usize,
+    profile: Profile,
+    texels: &mut [u8],
+) {
+    let bits = u128::from_le_bytes(block[..16].try_into().unwrap());
+    let values = match decode(bits, width, height, profile) {
+        Some(values) => values,
+        None => vec![Texel::ldr(ERROR_COLOR); width * height],
+    };
+    let texel_size = profile.texel_size();
+    for (texel, out) in values.iter().zip(texels.chunks_exact_mut(texel_size)) {
+        match profile {
+            Profile::Ldr | Profile::Srgb => {
+                for (value, out) in texel.values.iter().zip(out.iter_mut()) {
+                    *out = (value >> 8) as u8;
+                }
+            }
+            Profile::Hdr => {
+                for (channel, out) in out.chunks_exact_mut(2).enumerate() {
+                    out.copy_from_slice(&texel.to_f16(channel).to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+// A decoded texel. Channels from HDR endpoints hold a 16 bit logarithmic
+// value, the rest UNORM16, or half floats for HDR void-extent blocks.
+#[derive(Debug, Clone, Copy)]
+struct Texel {
+    values: [u16; 4],
+    hdr: [bool; 4],
+    float: bool,
+}
+
+impl Texel {
+    fn ldr(values: [u16; 4]) -> Self {
+        Texel {
+            values,
+            hdr: [false; 4],
+            float: false,
+        }
+    }
+
+    fn to_f16(self, channel: usize) -> u16 {
+        let value = self.values[channel];
+        if self.float {
+            value
+        } else if self.hdr[channel] {
+            lns_to_f16(value)
+        } else if value == 0xFFFF {
+            0x3C00
+        } else {
+            f32_to_f16(value as f32 / 65535.0)
+        }
+    }
+}
+
+fn decode(bits: u128, width: usize, height: usize, profile: Profile) -> Option<Vec<Texel>> {
+    let read = |start: u32, count: u32| (bits >> start) as u32 & ((1u64 << count) - 1) as u32;
+
+    if read(0, 9) == 0x1FC {
+        return decode_void_extent(bits, width * height, profile);
+    }
+
+    let mode = decode_block_mode(read(0, 11))?;
+    if mode.width > width || mode.height > height {
+        return None;
+    }
+    let planes = if mode.dual_plane { 2 } else { 1 };
+    let weight_count = mode.width * mode.height * planes;
+    let weight_bits = ise_size(weight_count, mode.weight_range);
+    if weight_count > 64 || !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+
+    let partitions = read(11, 2) as usize + 1;
+    if partitions == 4 && mode.dual_plane {
+        return None;
+    }
+
+    // Endpoint modes of each partition. With several partitions and different
+    // modes, some bits of the mode sit right below the weights.
+    let weights_start = 128 - weight_bits;
+    let mut color_start = 17;
+    let mut below_weights = 0;
+    let mut endpoint_modes = [0u32; 4];
+    if partitions == 1 {
+        endpoint_modes[0] = read(13, 4);
+    } else {
+        color_start = 29;
+        let selector = read(23, 2);
+        if selector == 0 {
+            endpoint_modes = [read(25, 4); 4];
+        } else {
+            below_weights = 3 * partitions as u32 - 4;
+            let extra = read(weights_start - below_weights, below_weights);
+            let field = read(23, 6) | (extra << 6);
+            for (partition, mode) in endpoint_modes.iter_mut().take(partitions).enumerate() {
+                let class = (field >> (2 + partition)) & 1;
+                let index = (field >> (2 + partitions + 2 * partition)) & 3;
+                *mode = (selector - 1 + class) * 4 + index;
+            }
+        }
+    }
+    let plane_channel_start = weights_start - below_weights - 2;
+    let plane_channel = mode
+        .dual_plane
+        .then(|| read(plane_channel_start, 2) as usize);
+    let color_end = if mode.dual_plane {
+        plane_channel_start
+    } else {
+        weights_start - below_weights
+    };
+
+    let value_count: usize = endpoint_modes[..partitions]
+        .iter()
+        .map(|&mode| ((mode / 4 + 1) * 2) as usize)
+        .sum();
+    if value_count > 18 || color_end < color_start {
+        return None;
+    }
+    // Colors use the largest range that fits, of at least 6 levels
+    let color_bits = color_end - color_start;
+    let color_range = (4..RANGES.len())
+        .rev()
+        .find(|&range| ise_size(value_count, range) <= color_bits)?;
+    let color_values = decode_ise(bits, color_start, value_count, color_range)
+        .into_iter()
+        .map(|value| unquantize_color(value, color_range))
+        .collect::<Vec<_>>();
+
+    let mut endpoints = [[[0u16; 4]; 2]; 4];
+    let mut hdr = [[false; 4]; 4];
+    let mut values = color_values.as_slice();
+    for partition in 0..partitions {
+        let mode = endpoint_modes[partition];
+        let count = ((mode / 4 + 1) * 2) as usize;
+        let (pair, channels_hdr) = decode_endpoints(mode, &values[..count], profile)?;
+        values = &values[count..];
+        endpoints[partition] = pair;
+        hdr[partition] = channels_hdr;
+    }
+
+    // Weights are stored backwards from the top of the block
+    let weights = decode_ise(bits.reverse_bits(), 0, weight_count, mode.weight_range)
+        .into_iter()
+        .map(|value| unquantize_weight(value, mode.weight_range))
+        .collect::<Vec<_>>();
+
+    let partition_seed = read(13, 10);
+    let small_block = width * height < 31;
+    let mut texels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let partition = if partitions > 1 {
+                select_partition(partition_seed, x, y, partitions, small_block)
+            } else {
+                0
+            };
+            let [e0, e1] = endpoints[partition];
+            let mut texel = Texel {
+                values: [0; 4],
+                hdr: hdr[partition],
+                float: false,
+            };
+            for channel in 0..4 {
+                let plane = (plane_channel == Some(channel)) as usize;
+                let weight = infill_weight(&weights, &mode, width, height, x, y, planes, plane);
+                let (c0, c1) = (e0[channel] as u32, e1[channel] as u32);
+                texel.values[channel] = ((c0 * (64 - weight) + c1 * weight + 32) >> 6) as u16;
+            }
+            texels.push(texel);
+        }
+    }
+    Some(texels)
+}
+
+fn decode_void_extent(bits: u128, texels: usize, profile: Profile) -> Option<Vec<Texel>> {
+    let read = |start: u32, count: u32| (bits >> start) as u32 & ((1 << count) - 1);
+    // The reserved bits must be set, and an extent that isn't all ones must be
+    // a real rectangle
+    if read(10, 2) != 3 {
+        return None;
+    }
+    let [min_s, max_s, min_t, max_t] = [12, 25, 38, 51].map(|start| read(start, 13));
+    let all_ones = [min_s, max_s, min_t, max_t]
+        .iter()
+        .all(|&value| value == 0x1FFF);
+    if !all_ones && (min_s >= max_s || min_t >= max_t) {
+        return None;
+    }
+
+    let is_hdr = (bits >> 9) & 1 == 1;
+    let values: [u16; 4] = std::array::from_fn(|channel| (bits >> (64 + 16 * channel)) as u16);
+    let texel = match (is_hdr, profile) {
+        (true, Profile::Hdr) => Texel {
+            values,
+            hdr: [false; 4],
+            float: true,
+        },
+        (true, _) => return None,
+        (false, _) => Texel::ldr(values),
+    };
+    Some(vec![texel; texels])
+}
+
+struct BlockMode {
+    width: usize,
+    height: usize,
+    dual_plane: bool,
+    /// Index into `RANGES`.
+    weight_range: usize,
+}
+
+fn decode_block_mode(mode: u32) -> Option<BlockMode> {
+    let bit = |index: u32| (mode >> index) & 1;
+    let a = ((mode >> 5) & 3) as usize;
+    let b = ((mode >> 7) & 3) as usize;
+    let mut dual_plane = bit(10) == 1;
+    let mut high_precision = bit(9) == 1;
+
+    let (range, width, height) = if mode & 3 != 0 {
+        let range = bit(4) | ((mode & 3) << 1);
+        let (width, height) = match (mode >> 2) & 3 {
+            0 => (b + 4, a + 2),
+            1 => (b + 8, a + 2),
+            2 => (a + 2, b + 8),
+            _ if bit(8) == 0 => (a + 2, (b & 1) + 6),
+            _ => ((b & 1) + 2, a + 2),
+        };
+        (range, width, height)
+    } else {
+        let range = bit(4) | (((mode >> 2) & 3) << 1);
+        if range < 2 {
+            return None;
+        }
+        let (width, height) = match (mode >> 7) & 3 {
+            0 => (12, a + 2),
+            1 => (a + 2, 12),
+            2 => {
+                let b = ((mode >> 9) & 3) as usize;
+                dual_plane = false;
+                high_precision = false;
+                (a + 6, b + 6)
+            }
+            _ => match a {
+                0 => (6, 10),
+                1 => (10, 6),
+                _ => return None,
+            },
+        };
+        (range, width, height)
+    };
+    if range < 2 {
+        return None;
+    }
+    Some(BlockMode {
+        width,
+        height,
+        dual_plane,
+        weight_range: (range - 2) as usize + if high_precision { 6 } else { 0 },
+    })
+}
+
+// Bits and trits or quints that each level of `RANGES` is made of
+fn range_encoding(range: usize) -> (u32, u32, u32) {
+    let levels = RANGES[range];
+    if levels.is_power_of_two() {
+        (levels.trailing_zeros(), 0, 0)
+    } else if levels.is_multiple_of(3) {
+        ((levels / 3).trailing_zeros(), 1, 0)
+    } else {
+        ((levels / 5).trailing_zeros(), 0, 1)
+    }
+}
+
+// Bits taken by `count` values of `range` in integer sequence encoding
+fn ise_size(count: usize, range: usize) -> u32 {
+    let count = count as u32;
+    let (bits, trits, quints) = range_encoding(range);
+    count * bits + trits * (8 * count).div_ceil(5) + quints * (7 * count).div_ceil(3)
+}
+
+// Reads `count` integers of `range` starting at bit `start`. Trits come in
+// groups of five sharing 8 bits, quints in groups of three sharing 7, and the
+// bits a last partial group leaves out read as zero.
+fn decode_ise(bits: u128, start: u32, count: usize, range: usize) -> Vec<u32> {
+    let end = start + ise_size(count, range);
+    let bits = bits & u128::MAX.checked_shr(128 - end).unwrap_or(0);
+    let read = |start: u32, count: u32| {
+        (bits.checked_shr(start).unwrap_or(0)) as u32 & ((1u64 << count) - 1) as u32
+    };
+    let (bit_count, trits, quints) = range_encoding(range);
+    let mut values = Vec::with_capacity(count);
+    let mut position = start;
+
+    if trits == 0 && quints == 0 {
+        for _ in 0..count {
+            values.push(read(position, bit_count));
+            position += bit_count;
+        }
+        return values;
+    }
+
+    // Bits of the packed digit between each value's own bits
+    let (group, packed_bits): (usize, &[u32]) = if trits == 1 {
+        (5, &[2, 2, 1, 2, 1])
+    } else {
+        (3, &[3, 2, 2])
+    };
+    while values.len() < count {
+        let mut low_bits = [0u32; 5];
+        let mut packed = 0;
+        let mut packed_position = 0;
+        for (low, &digit_bits) in low_bits.iter_mut().zip(packed_bits) {
+            *low = read(position, bit_count);
+            position += bit_count;
+            packed |= read(position, digit_bits) << packed_position;
+            position += digit_bits;
+            packed_position += digit_bits;
+        }
+        let digits = if trits == 1 {
+            decode_trits(packed).to_vec()
+        } else {
+            decode_quints(packed).to_vec()
+        };
+        for (digit, low) in digits.into_iter().zip(low_bits).take(group) {
+            if values.len() < count {
+                values.push((digit << bit_count) | low);
+            }
+        }
+    }
+    values
+}
+
+fn decode_trits(packed: u32) -> [u32; 5] {
+    let bit = |index: u32| (packed >> index) & 1;
+    let (c, t3, t4);
+    if (packed >> 2) & 7 == 7 {
+        c = ((packed >> 5) & 7) << 2 | (packed & 3);
+        t4 = 2;
+        t3 = 2;
+    } else {
+        c = packed & 0x1F;
+        if (packed >> 5) & 3 == 3 {
+            t4 = 2;
+            t3 = bit(7);
+        } else {
+            t4 = bit(7);
+            t3 = (packed >> 5) & 3;
+        }
+    }
+    let c_bit = |index: u32| (c >> index) & 1;
+    let (t0, t1, t2);
+    if c & 3 == 3 {
+        t2 = 2;
+        t1 = c_bit(4);
+        t0 = (c_bit(3) << 1) | (c_bit(2) & !c_bit(3) & 1);
+    } else if (c >> 2) & 3 == 3 {
+        t2 = 2;
+        t1 = 2;
+        t0 = c & 3;
+    } else {
+        t2 = c_bit(4);
+        t1 = (c >> 2) & 3;
+        t0 = (c_bit(1) << 1) | (c_bit(0) & !c_bit(1) & 1);
+    }
+    [t0, t1, t2, t3, t4]
+}
+
+fn decode_quints(packed: u32) -> [u32; 3] {
+    let bit = |index: u32| (packed >> index) & 1;
+    if (packed >> 1) & 3 == 3 && (packed >> 5) & 3 == 0 {
+        let not0 = !bit(0) & 1;
+        let q2 = (bit(0) << 2) | ((bit(4) & not0) << 1) | (bit(3) & not0);
+        return [4, 4, q2];
+    }
+    let (q2, c);
+    if (packed >> 1) & 3 == 3 {
+        q2 = 4;
+        c = (((packed >> 3) & 3) << 3) | ((!(packed >> 5) & 3) << 1) | bit(0);
+    } else {
+        q2 = (packed >> 5) & 3;
+        c = packed & 0x1F;
+    }
+    let (q0, q1) = if c & 7 == 5 {
+        ((c >> 3) & 3, 4)
+    } else {
+        (c & 7, (c >> 3) & 3)
+    };
+    [q0, q1, q2]
+}
+
+// Scales an integer of `range` to 0..=255
+fn unquantize_color(value: u32, range: usize) -> u32 {
+    let (bits, trits, _) = range_encoding(range);
+    if trits == 0 && RANGES[range].is_power_of_two() {
+        return replicate(value, bits, 8);
+    }
+    let digit = value >> bits;
+    let low = value & ((1 << bits) - 1);
+    let a = if low & 1 == 1 { 0x1FF } else { 0 };
+    let high = low >> 1;
+    let (b, c) = match (trits == 1, bits) {
+        (true, 1) => (0, 204),
+        (false, 1) => (0, 113),
+        (true, 2) => ((high << 8) | (high << 4) | (high << 2) | (high << 1), 93),
+        (false, 2) => ((high << 8) | (high << 3) | (high << 2), 54),
+        (true, 3) => ((high << 7) | (high << 2) | high, 44),
+        (false, 3) => ((high << 7) | (high << 1) | (high >> 1), 26),
+        (true, 4) => ((high << 6) | high, 22),
+        (false, 4) => ((high << 6) | (high >> 1), 13),
+        (true, 5) => ((high << 5) | (high >> 2), 11),
+        (false, 5) => ((high << 5) | (high >> 3), 6),
+        (_, _) => ((high << 4) | (high >> 4), 5),
+    };
+    let t = (digit * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+// Scales an integer of `range` to 0..=64
+fn unquantize_weight(value: u32, range: usize) -> u32 {
+    let (bits, trits, quints) = range_encoding(range);
+    let weight = if trits == 0 && quints == 0 {
+        replicate(value, bits, 6)
+    } else if bits == 0 {
+        if trits == 1 {
+            [0, 32, 63][value as usize]
+        } else {
+            [0, 16, 32, 47, 63][value as usize]
+        }
+    } else {
+        let digit = value >> bits;
+        let low = value & ((1 << bits) - 1);
+        let a = if low & 1 == 1 { 0x7F } else { 0 };
+        let high = low >> 1;
+        let (b, c) = match (trits == 1, bits) {
+            (true, 1) => (0, 50),
+            (false, 1) => (0, 28),
+            (true, 2) => ((high << 6) | (high << 2) | high, 23),
+            (false, 2) => ((high << 6) | (high << 1), 13),
+            (_, _) => ((high << 5) | high, 11),
+        };
+        let t = (digit * c + b) ^ a;
+        (a & 0x20) | (t >> 2)
+    };
+    if weight > 32 {
+        weight + 1
+    } else {
+        weight
+    }
+}
+
+// Repeats the `bits` wide `value` until it fills `target` bits
+fn replicate(value: u32, bits: u32, target: u32) -> u32 {
+    if bits == 0 {
+        return 0;
+    }
+    let mut result = 0;
+    let mut filled = 0;
+    while filled < target {
+        let shift = target as i32 - filled as i32 - bits as i32;
+        result |= if shift >= 0 {
+            value << shift
+        } else {
+            value >> -shift
+        };
+        filled += bits;
+    }
+    result
+}
+
+// The two endpoints of a partition as 16 bit values, and which channels hold
+// logarithmic HDR values
+fn decode_endpoints(mode: u32, v: &[u32], profile: Profile) -> Option<([[u16; 4]; 2], [bool; 4])> {
+    let v = |index: usize| v[index] as i32;
+    let ldr = |e0: [i32; 4], e1: [i32; 4]| {
+        let expand = |value: i32| {
+            let value = value.clamp(0, 255) as u16;
+            if profile == Profile::Srgb {
+                (value << 8) | 0x80
+            } else {
+                (value << 8) | value
+            }
+        };
+        Some(([e0.map(expand), e1.map(expand)], [false; 4]))
+    };
+    let hdr = |e0: [i32; 4], e1: [i32; 4], alpha_hdr: bool| {
+        if profile != Profile::Hdr {
+            return None;
+        }
+        let pair = [e0.map(|value| value as u16), e1.map(|value| value as u16)];
+        Some((pair, [true, true, true, alpha_hdr]))
+    };
+
+    match mode {
+        0 => ldr([v(0), v(0), v(0), 255], [v(1), v(1), v(1), 255]),
+        1 => {
+            let l0 = (v(0) >> 2) | (v(1) & 0xC0);
+            let l1 = (l0 + (v(1) & 0x3F)).min(255);
+            ldr([l0, l0, l0, 255], [l1, l1, l1, 255])
+        }
+        2 => {
+            let (y0, y1) = if v(1) >= v(0) {
+                (v(0) << 4, v(1) << 4)
+            } else {
+                ((v(1) << 4) + 8, (v(0) << 4) - 8)
+            };
+            hdr(
+                [y0 << 4, y0 << 4, y0 << 4, 0x7800],
+                [y1 << 4, y1 << 4, y1 << 4, 0x7800],
+                true,
+            )
+        }
+        3 => {
+            let (y0, d) = if v(0) & 0x80 != 0 {
+                (
+                    ((v(1) & 0xE0) << 4) | ((v(0) & 0x7F) << 2),
+                    (v(1) & 0x1F) << 2,
+                )
+            } else {
+                (
+                    ((v(1) & 0xF0) << 4) | ((v(0) & 0x7F) << 1),
+                    (v(1) & 0x0F) << 1,
+                )
+            };
+            let y1 = (y0 + d).min(0xFFF);
+            hdr(
+                [y0 << 4, y0 << 4, y0 << 4, 0x7800],
+                [y1 << 4, y1 << 4, y1 << 4, 0x7800],
+                true,
+            )
+        }
+        4 => ldr([v(0), v(0), v(0), v(2)], [v(1), v(1), v(1), v(3)]),
+        5 => {
+            let (d0, b0) = bit_transfer_signed(v(1), v(0));
+            let (d2, b2) = bit_transfer_signed(v(3), v(2));
+            ldr([b0, b0, b0, b2], [b0 + d0, b0 + d0, b0 + d0, b2 + d2])
+        }
+        6 => ldr(
+            [
+                (v(0) * v(3)) >> 8,
+                (v(1) * v(3)) >> 8,
+                (v(2) * v(3)) >> 8,
+                255,
+            ],
+            [v(0), v(1), v(2), 255],
+        ),
+        7 => {
+            let (e0, e1) = hdr_rgb_scale([v(0), v(1), v(2), v(3)]);
+            hdr(e0, e1, true)
+        }
+        8 | 12 => {
+            let (a0, a1) = if mode == 12 { (v(6), v(7)) } else { (255, 255) };
+            if v(1) + v(3) + v(5) >= v(0) + v(2) + v(4) {
+                ldr([v(0), v(2), v(4), a0], [v(1), v(3), v(5), a1])
+            } else {
+                ldr(
+                    blue_contract([v(1), v(3), v(5), a1]),
+                    blue_contract([v(0), v(2), v(4), a0]),
+                )
+            }
+        }
+        9 | 13 => {
+            let (d0, b0) = bit_transfer_signed(v(1), v(0));
+            let (d1, b1) = bit_transfer_signed(v(3), v(2));
+            let (d2, b2) = bit_transfer_signed(v(5), v(4));
+            let (d3, b3) = if mode == 13 {
+                bit_transfer_signed(v(7), v(6))
+            } else {
+                (0, 255)
+            };
+            let base = [b0, b1, b2, b3];
+            let offset = [b0 + d0, b1 + d1, b2 + d2, b3 + d3];
+            if d0 + d1 + d2 >= 0 {
+                ldr(base, offset)
+            } else {
+                ldr(blue_contract(offset), blue_contract(base))
+            }
+        }
+        10 => ldr(
+            [
+                (v(0) * v(3)) >> 8,
+                (v(1) * v(3)) >> 8,
+                (v(2) * v(3)) >> 8,
+                v(4),
+            ],
+            [v(0), v(1), v(2), v(5)],
+        ),
+        11 => {
+            let (e0, e1) = hdr_rgb([v(0), v(1), v(2), v(3), v(4), v(5)]);
+            hdr(e0, e1, true)
+        }
+        14 => {
+            let (mut e0, mut e1) = hdr_rgb([v(0), v(1), v(2), v(3), v(4), v(5)]);
+            // The alpha channel stays LDR
+            e0[3] = v(6) * 257;
+            e1[3] = v(7) * 257;
+            hdr(e0, e1, false)
+        }
+        _ => {
+            let (mut e0, mut e1) = hdr_rgb([v(0), v(1), v(2), v(3), v(4), v(5)]);
+            (e0[3], e1[3]) = hdr_alpha(v(6), v(7));
+            hdr(e0, e1, true)
+        }
+    }
+}
+
+// Moves the top bit of `a` into `b`, leaving `a` a signed 6 bit offset
+fn bit_transfer_signed(a: i32, b: i32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let a = (a >> 1) & 0x3F;
+    let a = if a & 0x20 != 0 { a - 0x40 } else { a };
+    (a, b)
+}
+
+fn blue_contract([r, g, b, a]: [i32; 4]) -> [i32; 4] {
+    [(r + b) >> 1, (g + b) >> 1, b, a]
+}
+
+// HDR RGB from a base color and a scale, endpoint mode 7
+fn hdr_rgb_scale([v0, v1, v2, v3]: [i32; 4]) -> ([i32; 4], [i32; 4]) {
+    let mode_value = ((v0 & 0xC0) >> 6) | ((v1 & 0x80) >> 5) | ((v2 & 0x80) >> 4);
+    let (major, mode) = if mode_value & 0xC != 0xC {
+        (mode_value >> 2, mode_value & 3)
+    } else if mode_value != 0xF {
+        (mode_value & 3, 4)
+    } else {
+        (0, 5)
+    };
+
+    let (mut red, mut green, mut blue, mut scale) = (v0 & 0x3F, v1 & 0x1F, v2 & 0x1F, v3 & 0x1F);
+    let bits = [
+        (v1 >> 6) & 1,
+        (v1 >> 5) & 1,
+        (v2 >> 6) & 1,
+        (v2 >> 5) & 1,
+        (v3 >> 7) & 1,
+        (v3 >> 6) & 1,
+        (v3 >> 5) & 1,
+    ];
+    // Which mode each extra bit belongs to, as a mask of modes
+    let one_hot = 1 << mode;
+    let place = |target: &mut i32, modes: i32, bit: usize, shift: i32| {
+        if one_hot & modes != 0 {
+            *target |= bits[bit] << shift;
+        }
+    };
+    place(&mut green, 0x30, 0, 6);
+    place(&mut green, 0x3A, 1, 5);
+    place(&mut blue, 0x30, 2, 6);
+    place(&mut blue, 0x3A, 3, 5);
+    place(&mut scale, 0x3D, 6, 5);
+    place(&mut scale, 0x2D, 5, 6);
+    place(&mut scale, 0x04, 4, 7);
+    place(&mut red, 0x3B, 4, 6);
+    place(&mut red, 0x04, 3, 6);
+    place(&mut red, 0x10, 5, 7);
+    place(&mut red, 0x0F, 2, 7);
+    place(&mut red, 0x05, 1, 8);
+    place(&mut red, 0x0A, 0, 8);
+    place(&mut red, 0x05, 0, 9);
+    place(&mut red, 0x02, 6, 9);
+    place(&mut red, 0x01, 3, 10);
+    place(&mut red, 0x02, 5, 10);
+
+    let shift = [1, 1, 2, 3, 4, 5][mode as usize];
+    red <<= shift;
+    green <<= shift;
+    blue <<= shift;
+    scale <<= shift;
+    if mode != 5 {
+        green = red - green;
+        blue = red - blue;
+    }
+    let mut color = [red, green, blue];
+    if major > 0 {
+        color.swap(0, major as usize);
+    }
+    let e1 = color.map(|c| c.clamp(0, 0xFFF) << 4);
+    let e0 = color.map(|c| (c - scale).clamp(0, 0xFFF) << 4);
+    ([e0[0], e0[1], e0[2], 0x7800], [e1[0], e1[1], e1[2], 0x7800])
+}
+
+// HDR RGB endpoints stored directly, endpoint modes 11, 14 and 15
+fn hdr_rgb([v0, v1, v2, v3, v4, v5]: [i32; 6]) -> ([i32; 4], [i32; 4]) {
+    let mode = ((v1 & 0x80) >> 7) | ((v2 & 0x80) >> 6) | ((v3 & 0x80) >> 5);
+    let major = ((v4 & 0x80) >> 7) | ((v5 & 0x80) >> 6);
+    if major == 3 {
+        return (
+            [v0 << 8, v2 << 8, (v4 & 0x7F) << 9, 0x7800],
+            [v1 << 8, v3 << 8, (v5 & 0x7F) << 9, 0x7800],
+        );
+    }
+
+    let mut a = v0 | ((v1 & 0x40) << 2);
+    let (mut b0, mut b1, mut c) = (v2 & 0x3F, v3 & 0x3F, v1 & 0x3F);
+    let (mut d0, mut d1) = (v4 & 0x7F, v5 & 0x7F);
+    let d_bits = [7, 6, 7, 6, 5, 6, 5, 6][mode as usize];
+    let bits = [
+        (v2 >> 6) & 1,
+        (v3 >> 6) & 1,
+        (v4 >> 6) & 1,
+        (v5 >> 6) & 1,
+        (v4 >> 5) & 1,
+        (v5 >> 5) & 1,
+    ];
+    let one_hot = 1 << mode;
+    let place = |target: &mut i32, modes: i32, bit: usize, shift: i32| {
+        if one_hot & modes != 0 {
+            *target |= bits[bit] << shift;
+        }
+    };
+    place(&mut a, 0xA4, 0, 9);
+    place(&mut a, 0x08, 2, 9);
+    place(&mut a, 0x50, 4, 9);
+    place(&mut a, 0x50, 5, 10);
+    place(&mut a, 0xA0, 1, 10);
+    place(&mut a, 0xC0, 2, 11);
+    place(&mut c, 0x04, 1, 6);
+    place(&mut c, 0xE8, 3, 6);
+    place(&mut c, 0x20, 2, 7);
+    place(&mut b0, 0x5B, 0, 6);
+    place(&mut b1, 0x5B, 1, 6);
+    place(&mut b0, 0x12, 2, 7);
+    place(&mut b1, 0x12, 3, 7);
+    place(&mut d0, 0xAF, 4, 5);
+    place(&mut d1, 0xAF, 5, 5);
+    place(&mut d0, 0x05, 2, 6);
+    place(&mut d1, 0x05, 3, 6);
+
+    let sign_extend = |value: i32| (value << (32 - d_bits)) >> (32 - d_bits);
+    let (d0, d1) = (sign_extend(d0), sign_extend(d1));
+    let shift = (mode >> 1) ^ 3;
+    let [a, b0, b1, c, d0, d1] = [a, b0, b1, c, d0, d1].map(|value| value << shift);
+
+    let mut e1 = [a, a - b0, a - b1];
+    let mut e0 = [a - c, a - b0 - c - d0, a - b1 - c - d1];
+    if major > 0 {
+        e0.swap(0, major as usize);
+        e1.swap(0, major as usize);
+    }
+    let [e0, e1] = [e0, e1].map(|e| e.map(|c| c.clamp(0, 0xFFF) << 4));
+    ([e0[0], e0[1], e0[2], 0x7800], [e1[0], e1[1], e1[2], 0x7800])
+}
+
+// HDR alpha endpoints, endpoint mode 15
+fn hdr_alpha(v6: i32, v7: i32) -> (i32, i32) {
+    let selector = ((v6 >> 7) & 1) | ((v7 >> 6) & 2);
+    let (mut a0, mut a1) = (v6 & 0x7F, v7 & 0x7F);
+    if selector == 3 {
+        a0 <<= 5;
+        a1 <<= 5;
+    } else {
+        a0 |= (a1 << (selector + 1)) & 0x780;
+        a1 &= 0x3F >> selector;
+        a1 ^= 32 >> selector;
+        a1 -= 32 >> selector;
+        a0 <<= 4 - selector;
+        a1 <<= 4 - selector;
+        a1 = (a1 + a0).clamp(0, 0xFFF);
+    }
+    (a0 << 4, a1 << 4)
+}
+
+// Bilinearly resamples the weight grid at a texel, as 0..=64
+#[allow(clippy::too_many_arguments)]
+fn infill_weight(
+    weights: &[u32],
+    mode: &BlockMode,
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    planes: usize,
+    plane: usize,
+) -> u32 {
+    let weight = |index: usize| weights.get(index * planes + plane).copied().unwrap_or(0);
+    if mode.width == width && mode.height == height {
+        return weight(y * width + x);
+    }
+
+    let scale = |size: usize| (1024 + size / 2) / (size - 1).max(1);
+    let cs = scale(width) * x;
+    let ct = scale(height) * y;
+    let gs = (cs * (mode.width - 1) + 32) >> 6;
+    let gt = (ct * (mode.height - 1) + 32) >> 6;
+    let (js, fs) = (gs >> 4, (gs & 0xF) as u32);
+    let (jt, ft) = (gt >> 4, (gt & 0xF) as u32);
+    let index = js + jt * mode.width;
+
+    let w11 = (fs * ft + 8) >> 4;
+    let w10 = ft - w11;
+    let w01 = fs - w11;
+    let w00 = 16 + w11 - fs - ft;
+    (weight(index) * w00
+        + weight(index + 1) * w01
+        + weight(index + mode.width) * w10
+        + weight(index + mode.width + 1) * w11
+        + 8)
+        >> 4
+}
+
+// The partition a texel belongs to, from a hash of the partition seed
+fn select_partition(seed: u32, x: usize, y: usize, partitions: usize, small_block: bool) -> usize {
+    let (x, y) = if small_block {
+        (x as u32 * 2, y as u32 * 2)
+    } else {
+        (x as u32, y as u32)
+    };
+    let seed = seed + (partitions as u32 - 1) * 1024;
+    let random = hash52(seed);
+
+    let mut seeds: [u32; 8] = std::array::from_fn(|i| {
+        let value = (random >> (4 * i)) & 0xF;
+        value * value
+    });
+    let (shift1, shift2) = if seed & 1 == 1 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partitions == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if partitions == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    for (i, value) in seeds.iter_mut().enumerate() {
+        *value >>= if i % 2 == 0 { shift1 } else { shift2 };
+    }
+
+    let a = (seeds[0] * x + seeds[1] * y + (random >> 14)) & 0x3F;
+    let b = (seeds[2] * x + seeds[3] * y + (random >> 10)) & 0x3F;
+    let c = if partitions >= 3 {
+        (seeds[4] * x + seeds[5] * y + (random >> 6)) & 0x3F
+    } else {
+        0
+    };
+    let d = if partitions >= 4 {
+        (seeds[6] * x + seeds[7] * y + (random >> 2)) & 0x3F
+    } else {
+        0
+    };
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+fn hash52(mut value: u32) -> u32 {
+    value ^= value >> 15;
+    value = value.wrapping_mul(0xEEDE0891);
+    value ^= value >> 5;
+    value = value.wrapping_add(value << 16);
+    value ^= value >> 7;
+    value ^= value >> 3;
+    value ^= value << 6;
+    value ^= value >> 17;
+    value
+}
+
+// Converts a logarithmic HDR value to the bits of a half float
+fn lns_to_f16(value: u16) -> u16 {
+    let exponent = value >> 11;
+    let mantissa = (value & 0x7FF) as u32;
+    let mantissa = if mantissa < 512 {
+        3 * mantissa
+    } else if mantissa >= 1536 {
+        5 * mantissa - 2048
+    } else {
+        4 * mantissa - 512
+    };
+    ((exponent << 10) | (mantissa >> 3) as u16).min(0x7BFF)
+}
+
+// Rounds a value in 0..=1 to the nearest half float
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+    if value == 0.0 {
+        0
+    } else if exponent <= 0 {
+        // Subnormal halves, with the implicit bit made explicit
+        let shift = (14 - exponent) as u32;
+        let mantissa = mantissa | 0x80_0000;
+        let rounded = (mantissa + (1 << (shift - 1))) >> shift;
+        rounded as u16
+    } else {
+        let half = ((exponent as u32) << 10) | (mantissa >> 13);
+        let rest = mantissa & 0x1FFF;
+        let round_up = rest > 0x1000 || (rest == 0x1000 && half & 1 == 1);
+        (half + round_up as u32) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_ldr(block: u128, width: usize, height: usize) -> Vec<[u8; 4]> {
+        let mut texels = vec![0; width * height * 4];
+        decode_block(
+            &block.to_le_bytes(),
+            width,
+            height,
+            Profile::Ldr,
+            &mut texels,
+        );
+        texels
+            .chunks_exact(4)
+            .map(|texel| texel.try_into().unwrap())
+            .collect()
+    }
+
+    // Packs (value, bit count) pairs into a block, lowest bits first
+    fn pack(fields: &[(u128, u32)]) -> u128 {
+        let mut bits = 0;
+        let mut position = 0;
+        for &(value, count) in fields {
+            bits |= value << position;
+            position += count;
+        }
+        bits
+    }
+
+    #[test]
+    fn void_extent_blocks_are_one_color() {
+        let color = [0xFFFF, 0x8000, 0x0000, 0x4000];
+        let block = pack(&[
+            (0x1FC, 9),
+            (0, 1),
+            (0x3, 2),
+            // Extent coordinates all ones
+            (u128::MAX >> 76, 52),
+            (color[0], 16),
+            (color[1], 16),
+            (color[2], 16),
+            (color[3], 16),
+        ]);
+        for (width, height) in [(4, 4), (6, 5), (12, 12)] {
+            let texels = decode_ldr(block, width, height);
+            assert_eq!(texels.len(), width * height);
+            assert!(texels.iter().all(|&texel| texel == [255, 128, 0, 64]));
+        }
+
+        let mut texels = vec![0; 16 * 8];
+        decode_block(&block.to_le_bytes(), 4, 4, Profile::Hdr, &mut texels);
+        assert_eq!(&texels[..8], &[0x00, 0x3C, 0x00, 0x38, 0, 0, 0x00, 0x34]);
+    }
+
+    #[test]
+    fn hdr_void_extent_blocks_are_errors_in_ldr() {
+        let block = pack(&[
+            (0x1FC, 9),
+            (1, 1),
+            (0x3, 2),
+            (u128::MAX >> 76, 52),
+            (0x3C00, 16),
+            (0, 48),
+        ]);
+        assert!(decode_ldr(block, 4, 4)
+            .iter()
+            .all(|&texel| texel == [255, 0, 255, 255]));
+
+        let mut texels = vec![0; 16 * 8];
+        decode_block(&block.to_le_bytes(), 4, 4, Profile::Hdr, &mut texels);
+        assert_eq!(&texels[..2], &[0x00, 0x3C]);
+    }
+
+    #[test]
+    fn reserved_block_modes_are_errors() {
+        assert!(decode_ldr(0, 4, 4)
+            .iter()
+            .all(|&texel| texel == [255, 0, 255, 255]));
+    }
+
+    // A single partition block with a 4x4 grid of 2 bit weights and direct
+    // luminance endpoints, which get 8 bits each and so read back as is
+    fn luminance_block(l0: u128, l1: u128, weights: [u128; 16]) -> u128 {
+        // Width B + 4, height A + 2 and a weight range of 4 levels
+        let mode = (2 << 5) | 0b10;
+        let mut bits = pack(&[(mode, 11), (0, 2), (0, 4), (l0, 8), (l1, 8)]);
+        for (i, weight) in weights.into_iter().enumerate() {
+            bits |= (weight & 1) << (127 - 2 * i) | (weight >> 1) << (126 - 2 * i);
+        }
+        bits
+    }
+
+    #[test]
+    fn weights_interpolate_between_endpoints() {
+        let mut weights = [0; 16];
+        weights[1] = 1;
+        weights[2] = 2;
+        weights[3] = 3;
+        let texels = decode_ldr(luminance_block(10, 250, weights), 4, 4);
+        assert_eq!(texels[0], [10, 10, 10, 255]);
+        assert_eq!(texels[1], [89, 89, 89, 255]);
+        assert_eq!(texels[2], [171, 171, 171, 255]);
+        assert_eq!(texels[3], [250, 250, 250, 255]);
+        assert_eq!(texels[15], [10, 10, 10, 255]);
+    }
+
+    #[test]
+    fn trits_and_quints_round_trip_their_digits() {
+        // Every packed trit value decodes to digits below 3, and every
+        // quint value to digits below 5, with each combination exactly once
+        let mut trits = std::collections::HashSet::new();
+        for packed in 0..256 {
+            let digits = decode_trits(packed);
+            assert!(digits.iter().all(|&t| t < 3));
+            trits.insert(digits);
+        }
+        assert_eq!(trits.len(), 243);
+        let mut quints = std::collections::HashSet::new();
+        for packed in 0..128 {
+            let digits = decode_quints(packed);
+            assert!(digits.iter().all(|&q| q < 5));
+            quints.insert(digits);
+        }
+        assert_eq!(quints.len(), 125);
+    }
+
+    #[test]
+    fn partial_groups_read_missing_bits_as_zero() {
+        // A lone quint takes 3 bits, and the set bits after them aren't part of it
+        assert_eq!(decode_ise(u128::MAX, 0, 1, 3), [4]);
+        assert_eq!(decode_ise(u128::MAX, 125, 1, 3), [4]);
+    }
+
+    #[test]
+    fn unquantized_values_span_the_full_range() {
+        for (range, &levels) in RANGES.iter().enumerate() {
+            let mut colors = (0..levels)
+                .map(|value| unquantize_color(value, range))
+                .collect::<Vec<_>>();
+            colors.sort();
+            if range >= 4 {
+                assert_eq!((colors[0], colors[levels as usize - 1]), (0, 255));
+            }
+            if range < 12 {
+                let mut weights = (0..levels)
+                    .map(|value| unquantize_weight(value, range))
+                    .collect::<Vec<_>>();
+                weights.sort();
+                assert_eq!((weights[0], weights[levels as usize - 1]), (0, 64));
+            }
+        }
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(1.0 / 65535.0), 0x0100);
+        assert_eq!(f32_to_f16(0.0), 0);
+        assert_eq!(lns_to_f16(0x7800), 0x3C00);
+        assert_eq!(lns_to_f16(0xFFFF), 0x7BFF);
+    }
+
+    #[test]
+    fn partitions_use_every_subset() {
+        for partitions in 2..=4 {
+            let mut used = [false; 4];
+            for seed in 0..1024 {
+                for y in 0..6 {
+                    for x in 0..6 {
+                        used[select_partition(seed, x, y, partitions, false)] = true;
+                    }
+                }
+            }
+            assert_eq!(used.iter().filter(|&&used| used).count(), partitions);
+        }
+    }
+}
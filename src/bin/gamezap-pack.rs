@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use anyhow::Context;
+use gamezap::{archive::Archive, pack::ArchiveWriter};
+
+const USAGE: &str = "Usage:
+    gamezap-pack build <archive> <dir>... [--no-compress]
+    gamezap-pack list <archive>
+    gamezap-pack verify <archive>
+
+Each directory is stored under its own name, e.g. `models/cube.obj`.";
+
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["build", archive, rest @ ..] if !rest.is_empty() => build(archive, rest),
+        ["list", archive] => list(archive),
+        ["verify", archive] => verify(archive),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn build(archive: &str, args: &[&str]) -> anyhow::Result<()> {
+    let compress = !args.contains(&"--no-compress");
+    let mut writer = ArchiveWriter::new(compress);
+    for dir in args.iter().filter(|arg| !arg.starts_with("--")) {
+        let dir = Path::new(dir);
+        let prefix = dir
+            .canonicalize()?
+            .file_name()
+            .context("Directory has no name")?
+            .to_string_lossy()
+            .into_owned();
+        writer.add_dir(dir, &prefix)?;
+    }
+
+    let mut file = std::fs::File::create(archive)
+        .with_context(|| format!("Failed to create {:?}", archive))?;
+    writer.write(&mut file)?;
+    Ok(())
+}
+
+fn list(archive: &str) -> anyhow::Result<()> {
+    let archive = Archive::open(archive)?;
+    for entry in archive.entries() {
+        let hash = entry.hash[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        println!(
+            "{:>10} {:>10} {:<8} {} {}",
+            entry.size,
+            entry.stored_size,
+            format!("{:?}", entry.compression),
+            hash,
+            entry.path
+        );
+    }
+    Ok(())
+}
+
+fn verify(archive: &str) -> anyhow::Result<()> {
+    let archive = Archive::open(archive)?;
+    let mismatched = archive.verify();
+    for path in &mismatched {
+        eprintln!("Hash mismatch: {}", path);
+    }
+    if !mismatched.is_empty() {
+        anyhow::bail!(
+            "{} of {} entries failed verification",
+            mismatched.len(),
+            archive.entries().len()
+        );
+    }
+    println!("{} entries OK", archive.entries().len());
+    Ok(())
+}
@@ -0,0 +1,945 @@
+//! CPU decoders for BC6H and BC7 blocks, together called BPTC.
+
+// Subset of each texel for the 64 two-subset partitions, one bit per texel
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+// Subset of each texel for the 64 three-subset partitions, two bits per texel
+const PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+// The texel whose index drops its top bit, for the second subset of two
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// Likewise for the second and third subsets of three
+const ANCHORS_3: [[u8; 2]; 64] = [
+    [3, 15],
+    [3, 8],
+    [15, 8],
+    [15, 3],
+    [8, 15],
+    [3, 15],
+    [15, 3],
+    [15, 8],
+    [8, 15],
+    [8, 15],
+    [6, 15],
+    [6, 15],
+    [6, 15],
+    [5, 15],
+    [3, 15],
+    [3, 8],
+    [3, 15],
+    [3, 8],
+    [8, 15],
+    [15, 3],
+    [3, 15],
+    [3, 8],
+    [6, 15],
+    [10, 8],
+    [5, 3],
+    [8, 15],
+    [8, 6],
+    [6, 10],
+    [8, 15],
+    [5, 15],
+    [15, 10],
+    [15, 8],
+    [8, 15],
+    [15, 3],
+    [3, 15],
+    [5, 10],
+    [6, 10],
+    [10, 8],
+    [8, 9],
+    [15, 10],
+    [15, 6],
+    [3, 15],
+    [15, 8],
+    [5, 15],
+    [15, 3],
+    [15, 6],
+    [15, 6],
+    [15, 8],
+    [3, 15],
+    [15, 3],
+    [5, 15],
+    [5, 15],
+    [5, 15],
+    [8, 15],
+    [5, 15],
+    [10, 15],
+    [5, 15],
+    [10, 15],
+    [8, 15],
+    [13, 15],
+    [15, 3],
+    [12, 15],
+    [3, 15],
+    [3, 8],
+];
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+struct Bits {
+    bits: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn new(block: &[u8]) -> Self {
+        Bits {
+            bits: u128::from_le_bytes(block[..16].try_into().unwrap()),
+            position: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let bits = self.bits.checked_shr(self.position).unwrap_or(0);
+        let value = bits as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+fn subset(partition: usize, subsets: usize, texel: usize) -> usize {
+    match subsets {
+        2 => (PARTITIONS_2[partition] >> texel) as usize & 1,
+        3 => (PARTITIONS_3[partition] >> (2 * texel)) as usize & 3,
+        _ => 0,
+    }
+}
+
+fn is_anchor(partition: usize, subsets: usize, texel: usize) -> bool {
+    texel == 0
+        || match subsets {
+            2 => ANCHORS_2[partition] as usize == texel,
+            3 => ANCHORS_3[partition].contains(&(texel as u8)),
+            _ => false,
+        }
+}
+
+fn weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+fn interpolate(e0: u32, e1: u32, weight: u32) -> u32 {
+    ((64 - weight) * e0 + weight * e1 + 32) >> 6
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn bc7_mode(
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    p_bits: u32,
+    index_bits: u32,
+    secondary_index_bits: u32,
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        index_selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_p_bits: p_bits == 1,
+        shared_p_bits: p_bits == 2,
+        index_bits,
+        secondary_index_bits,
+    }
+}
+
+// P-bits are 1 per endpoint or 2 for one per subset
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, 4, 0, 1, 3, 0),
+    bc7_mode(2, 6, 0, 0, 6, 0, 2, 3, 0),
+    bc7_mode(3, 6, 0, 0, 5, 0, 0, 2, 0),
+    bc7_mode(2, 6, 0, 0, 7, 0, 1, 2, 0),
+    bc7_mode(1, 0, 2, 1, 5, 6, 0, 2, 3),
+    bc7_mode(1, 0, 2, 0, 7, 8, 0, 2, 2),
+    bc7_mode(1, 0, 0, 0, 7, 7, 1, 4, 0),
+    bc7_mode(2, 6, 0, 0, 5, 5, 1, 2, 0),
+];
+
+/// Decodes a 16 byte BC7 block into RGBA8 texels in row order. Reserved
+/// modes decode to transparent black, as the format requires.
+pub(crate) fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut bits = Bits::new(block);
+    let mode_number = block[0].trailing_zeros() as usize;
+    let Some(mode) = BC7_MODES.get(mode_number) else {
+        return [[0; 4]; 16];
+    };
+    bits.read(mode_number as u32 + 1);
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // Endpoints as [subset][endpoint][channel]
+    let mut endpoints = [[[0u32; 4]; 2]; 3];
+    for channel in 0..4 {
+        let channel_bits = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for subset in endpoints.iter_mut().take(mode.subsets) {
+            for endpoint in subset.iter_mut() {
+                endpoint[channel] = bits.read(channel_bits);
+            }
+        }
+    }
+
+    let mut color_bits = mode.color_bits;
+    let mut alpha_bits = mode.alpha_bits;
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        for subset in endpoints.iter_mut().take(mode.subsets) {
+            let shared = mode.shared_p_bits.then(|| bits.read(1));
+            for endpoint in subset.iter_mut() {
+                let p_bit = shared.unwrap_or_else(|| bits.read(1));
+                for value in endpoint.iter_mut() {
+                    *value = (*value << 1) | p_bit;
+                }
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for subset in endpoints.iter_mut().take(mode.subsets) {
+        for endpoint in subset.iter_mut() {
+            for (channel, value) in endpoint.iter_mut().enumerate() {
+                *value = match (channel, alpha_bits) {
+                    (3, 0) => 255,
+                    (3, _) => expand(*value, alpha_bits),
+                    _ => expand(*value, color_bits),
+                };
+            }
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(partition, mode.subsets, texel) as u32;
+        *index = bits.read(mode.index_bits - anchor);
+    }
+    let mut secondary_indices = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (texel, index) in secondary_indices.iter_mut().enumerate() {
+            *index = bits.read(mode.secondary_index_bits - (texel == 0) as u32);
+        }
+    }
+
+    std::array::from_fn(|texel| {
+        let [e0, e1] = endpoints[subset(partition, mode.subsets, texel)];
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = weights(mode.index_bits)[indices[texel] as usize];
+            (weight, weight)
+        } else {
+            let primary = weights(mode.index_bits)[indices[texel] as usize];
+            let secondary = weights(mode.secondary_index_bits)[secondary_indices[texel] as usize];
+            if index_selection == 0 {
+                (primary, secondary)
+            } else {
+                (secondary, primary)
+            }
+        };
+        let mut texel: [u8; 4] = std::array::from_fn(|channel| {
+            let weight = if channel == 3 {
+                alpha_weight
+            } else {
+                color_weight
+            };
+            interpolate(e0[channel], e1[channel], weight) as u8
+        });
+        if rotation > 0 {
+            texel.swap(rotation as usize - 1, 3);
+        }
+        texel
+    })
+}
+
+// Replicates the top bits of a `bits` wide value into the bottom of a byte
+fn expand(value: u32, bits: u32) -> u32 {
+    let value = value << (8 - bits);
+    value | (value >> bits)
+}
+
+// Fields of the BC6H header, which each mode scatters differently
+const RW: u8 = 0;
+const GW: u8 = 1;
+const BW: u8 = 2;
+const RX: u8 = 3;
+const GX: u8 = 4;
+const BX: u8 = 5;
+const RY: u8 = 6;
+const GY: u8 = 7;
+const BY: u8 = 8;
+const RZ: u8 = 9;
+const GZ: u8 = 10;
+const BZ: u8 = 11;
+
+struct Bc6hMode {
+    /// The value of the mode field, 2 bits for the first two modes and 5 for the rest.
+    mode: u32,
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    /// Runs of (field, first bit, bit count) in the order they are stored.
+    layout: &'static [(u8, u8, u8)],
+}
+
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode {
+        mode: 0b00,
+        transformed: true,
+        endpoint_bits: 10,
+        delta_bits: [5, 5, 5],
+        layout: &[
+            (GY, 4, 1),
+            (BY, 4, 1),
+            (BZ, 4, 1),
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 5),
+            (GZ, 4, 1),
+            (GY, 0, 4),
+            (GX, 0, 5),
+            (BZ, 0, 1),
+            (GZ, 0, 4),
+            (BX, 0, 5),
+            (BZ, 1, 1),
+            (BY, 0, 4),
+            (RY, 0, 5),
+            (BZ, 2, 1),
+            (RZ, 0, 5),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b01,
+        transformed: true,
+        endpoint_bits: 7,
+        delta_bits: [6, 6, 6],
+        layout: &[
+            (GY, 5, 1),
+            (GZ, 4, 1),
+            (GZ, 5, 1),
+            (RW, 0, 7),
+            (BZ, 0, 1),
+            (BZ, 1, 1),
+            (BY, 4, 1),
+            (GW, 0, 7),
+            (BY, 5, 1),
+            (BZ, 2, 1),
+            (GY, 4, 1),
+            (BW, 0, 7),
+            (BZ, 3, 1),
+            (BZ, 5, 1),
+            (BZ, 4, 1),
+            (RX, 0, 6),
+            (GY, 0, 4),
+            (GX, 0, 6),
+            (GZ, 0, 4),
+            (BX, 0, 6),
+            (BY, 0, 4),
+            (RY, 0, 6),
+            (RZ, 0, 6),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b00010,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [5, 4, 4],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 5),
+            (RW, 10, 1),
+            (GY, 0, 4),
+            (GX, 0, 4),
+            (GW, 10, 1),
+            (BZ, 0, 1),
+            (GZ, 0, 4),
+            (BX, 0, 4),
+            (BW, 10, 1),
+            (BZ, 1, 1),
+            (BY, 0, 4),
+            (RY, 0, 5),
+            (BZ, 2, 1),
+            (RZ, 0, 5),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b00110,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [4, 5, 4],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 4),
+            (RW, 10, 1),
+            (GZ, 4, 1),
+            (GY, 0, 4),
+            (GX, 0, 5),
+            (GW, 10, 1),
+            (GZ, 0, 4),
+            (BX, 0, 4),
+            (BW, 10, 1),
+            (BZ, 1, 1),
+            (BY, 0, 4),
+            (RY, 0, 4),
+            (BZ, 0, 1),
+            (BZ, 2, 1),
+            (RZ, 0, 4),
+            (GY, 4, 1),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b01010,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [4, 4, 5],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 4),
+            (RW, 10, 1),
+            (BY, 4, 1),
+            (GY, 0, 4),
+            (GX, 0, 4),
+            (GW, 10, 1),
+            (BZ, 0, 1),
+            (GZ, 0, 4),
+            (BX, 0, 5),
+            (BW, 10, 1),
+            (BY, 0, 4),
+            (RY, 0, 4),
+            (BZ, 1, 1),
+            (BZ, 2, 1),
+            (RZ, 0, 4),
+            (BZ, 4, 1),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b01110,
+        transformed: true,
+        endpoint_bits: 9,
+        delta_bits: [5, 5, 5],
+        layout: &[
+            (RW, 0, 9),
+            (BY, 4, 1),
+            (GW, 0, 9),
+            (GY, 4, 1),
+            (BW, 0, 9),
+            (BZ, 4, 1),
+            (RX, 0, 5),
+            (GZ, 4, 1),
+            (GY, 0, 4),
+            (GX, 0, 5),
+            (BZ, 0, 1),
+            (GZ, 0, 4),
+            (BX, 0, 5),
+            (BZ, 1, 1),
+            (BY, 0, 4),
+            (RY, 0, 5),
+            (BZ, 2, 1),
+            (RZ, 0, 5),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b10010,
+        transformed: true,
+        endpoint_bits: 8,
+        delta_bits: [6, 5, 5],
+        layout: &[
+            (RW, 0, 8),
+            (GZ, 4, 1),
+            (BY, 4, 1),
+            (GW, 0, 8),
+            (BZ, 2, 1),
+            (GY, 4, 1),
+            (BW, 0, 8),
+            (BZ, 3, 1),
+            (BZ, 4, 1),
+            (RX, 0, 6),
+            (GY, 0, 4),
+            (GX, 0, 5),
+            (BZ, 0, 1),
+            (GZ, 0, 4),
+            (BX, 0, 5),
+            (BZ, 1, 1),
+            (BY, 0, 4),
+            (RY, 0, 6),
+            (RZ, 0, 6),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b10110,
+        transformed: true,
+        endpoint_bits: 8,
+        delta_bits: [5, 6, 5],
+        layout: &[
+            (RW, 0, 8),
+            (BZ, 0, 1),
+            (BY, 4, 1),
+            (GW, 0, 8),
+            (GY, 5, 1),
+            (GY, 4, 1),
+            (BW, 0, 8),
+            (GZ, 5, 1),
+            (BZ, 4, 1),
+            (RX, 0, 5),
+            (GZ, 4, 1),
+            (GY, 0, 4),
+            (GX, 0, 6),
+            (GZ, 0, 4),
+            (BX, 0, 5),
+            (BZ, 1, 1),
+            (BY, 0, 4),
+            (RY, 0, 5),
+            (BZ, 2, 1),
+            (RZ, 0, 5),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b11010,
+        transformed: true,
+        endpoint_bits: 8,
+        delta_bits: [5, 5, 6],
+        layout: &[
+            (RW, 0, 8),
+            (BZ, 1, 1),
+            (BY, 4, 1),
+            (GW, 0, 8),
+            (BY, 5, 1),
+            (GY, 4, 1),
+            (BW, 0, 8),
+            (BZ, 5, 1),
+            (BZ, 4, 1),
+            (RX, 0, 5),
+            (GZ, 4, 1),
+            (GY, 0, 4),
+            (GX, 0, 5),
+            (BZ, 0, 1),
+            (GZ, 0, 4),
+            (BX, 0, 6),
+            (BY, 0, 4),
+            (RY, 0, 5),
+            (BZ, 2, 1),
+            (RZ, 0, 5),
+            (BZ, 3, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b11110,
+        transformed: false,
+        endpoint_bits: 6,
+        delta_bits: [6, 6, 6],
+        layout: &[
+            (RW, 0, 6),
+            (GZ, 4, 1),
+            (BZ, 0, 1),
+            (BZ, 1, 1),
+            (BY, 4, 1),
+            (GW, 0, 6),
+            (GY, 5, 1),
+            (BY, 5, 1),
+            (BZ, 2, 1),
+            (GY, 4, 1),
+            (BW, 0, 6),
+            (GZ, 5, 1),
+            (BZ, 3, 1),
+            (BZ, 5, 1),
+            (BZ, 4, 1),
+            (RX, 0, 6),
+            (GY, 0, 4),
+            (GX, 0, 6),
+            (GZ, 0, 4),
+            (BX, 0, 6),
+            (BY, 0, 4),
+            (RY, 0, 6),
+            (RZ, 0, 6),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b00011,
+        transformed: false,
+        endpoint_bits: 10,
+        delta_bits: [10, 10, 10],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 10),
+            (GX, 0, 10),
+            (BX, 0, 10),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b00111,
+        transformed: true,
+        endpoint_bits: 11,
+        delta_bits: [9, 9, 9],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 9),
+            (RW, 10, 1),
+            (GX, 0, 9),
+            (GW, 10, 1),
+            (BX, 0, 9),
+            (BW, 10, 1),
+        ],
+    },
+    // The top endpoint bits of the last two modes are stored in reverse
+    Bc6hMode {
+        mode: 0b01011,
+        transformed: true,
+        endpoint_bits: 12,
+        delta_bits: [8, 8, 8],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 8),
+            (RW, 11, 1),
+            (RW, 10, 1),
+            (GX, 0, 8),
+            (GW, 11, 1),
+            (GW, 10, 1),
+            (BX, 0, 8),
+            (BW, 11, 1),
+            (BW, 10, 1),
+        ],
+    },
+    Bc6hMode {
+        mode: 0b01111,
+        transformed: true,
+        endpoint_bits: 16,
+        delta_bits: [4, 4, 4],
+        layout: &[
+            (RW, 0, 10),
+            (GW, 0, 10),
+            (BW, 0, 10),
+            (RX, 0, 4),
+            (RW, 15, 1),
+            (RW, 14, 1),
+            (RW, 13, 1),
+            (RW, 12, 1),
+            (RW, 11, 1),
+            (RW, 10, 1),
+            (GX, 0, 4),
+            (GW, 15, 1),
+            (GW, 14, 1),
+            (GW, 13, 1),
+            (GW, 12, 1),
+            (GW, 11, 1),
+            (GW, 10, 1),
+            (BX, 0, 4),
+            (BW, 15, 1),
+            (BW, 14, 1),
+            (BW, 13, 1),
+            (BW, 12, 1),
+            (BW, 11, 1),
+            (BW, 10, 1),
+        ],
+    },
+];
+
+/// Decodes a 16 byte BC6H block into the bits of RGBA16 float texels in row
+/// order. Reserved modes decode to black.
+pub(crate) fn decode_bc6h(block: &[u8], signed: bool) -> [[u16; 4]; 16] {
+    const ONE: u16 = 0x3C00;
+
+    let mut bits = Bits::new(block);
+    let mut mode_value = bits.read(2);
+    if mode_value > 1 {
+        mode_value |= bits.read(3) << 2;
+    }
+    let Some(mode) = BC6H_MODES.iter().find(|mode| mode.mode == mode_value) else {
+        return [[0, 0, 0, ONE]; 16];
+    };
+
+    let mut fields = [0i32; 12];
+    for &(field, first_bit, count) in mode.layout {
+        fields[field as usize] |= (bits.read(count as u32) << first_bit) as i32;
+    }
+    // Modes ending in 0b11 have a single subset
+    let subsets = if mode.mode & 0b11 == 0b11 { 1 } else { 2 };
+    let partition = if subsets == 2 {
+        bits.read(5) as usize
+    } else {
+        0
+    };
+
+    // Endpoints as [subset * 2 + endpoint][channel], from the W, X, Y, Z fields
+    let endpoint_count = subsets * 2;
+    let mut endpoints = [[0i32; 3]; 4];
+    for (endpoint, values) in endpoints.iter_mut().take(endpoint_count).enumerate() {
+        for (channel, value) in values.iter_mut().enumerate() {
+            let raw = fields[endpoint * 3 + channel];
+            *value = if endpoint == 0 {
+                if signed {
+                    sign_extend(raw, mode.endpoint_bits)
+                } else {
+                    raw
+                }
+            } else if mode.transformed {
+                // Later endpoints are deltas from the first
+                let delta = sign_extend(raw, mode.delta_bits[channel]);
+                let base = fields[channel];
+                let value = (base + delta) & ((1 << mode.endpoint_bits) - 1);
+                if signed {
+                    sign_extend(value, mode.endpoint_bits)
+                } else {
+                    value
+                }
+            } else if signed {
+                sign_extend(raw, mode.delta_bits[channel])
+            } else {
+                raw
+            };
+        }
+    }
+    for values in endpoints.iter_mut().take(endpoint_count) {
+        for value in values.iter_mut() {
+            *value = unquantize_bc6h(*value, mode.endpoint_bits, signed);
+        }
+    }
+
+    let index_bits = if subsets == 2 { 3 } else { 4 };
+    let mut indices = [0u32; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(partition, subsets, texel) as u32;
+        *index = bits.read(index_bits - anchor);
+    }
+
+    std::array::from_fn(|texel| {
+        let subset = subset(partition, subsets, texel);
+        let weight = weights(index_bits)[indices[texel] as usize] as i32;
+        let [e0, e1] = [endpoints[subset * 2], endpoints[subset * 2 + 1]];
+        let mut color = [0, 0, 0, ONE];
+        for channel in 0..3 {
+            let value = ((64 - weight) * e0[channel] + weight * e1[channel] + 32) >> 6;
+            color[channel] = finish_bc6h(value, signed);
+        }
+        color
+    })
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+// Scales an endpoint to the 16 bit range interpolation works in
+fn unquantize_bc6h(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xFFFF
+        } else {
+            ((value << 16) + 0x8000) >> bits
+        }
+    } else if bits >= 16 {
+        value
+    } else {
+        let magnitude = value.abs();
+        let unquantized = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 {
+            -unquantized
+        } else {
+            unquantized
+        }
+    }
+}
+
+// Scales an interpolated value to the bits of a half float
+fn finish_bc6h(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else if value < 0 {
+        0x8000 | (((-value) * 31) >> 5) as u16
+    } else {
+        ((value * 31) >> 5) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs (value, bit count) pairs into a block, lowest bits first
+    fn pack(fields: &[(u128, u32)]) -> [u8; 16] {
+        let mut bits = 0u128;
+        let mut position = 0;
+        for &(value, count) in fields {
+            bits |= value << position;
+            position += count;
+        }
+        assert_eq!(position, 128);
+        bits.to_le_bytes()
+    }
+
+    #[test]
+    fn bc7_mode_6_interpolates_between_endpoints() {
+        // Endpoints 0 and 254 | p-bit 1 = 255 in every channel
+        let mut fields = vec![(1 << 6, 7)];
+        for _ in 0..4 {
+            fields.extend([(0, 7), (127, 7)]);
+        }
+        fields.extend([(0, 1), (1, 1)]);
+        // Texel 0 has index 0 with its top bit dropped, texel 15 the last index
+        fields.push((0, 3));
+        fields.extend((1..16).map(|i| (i as u128, 4)));
+        let texels = decode_bc7(&pack(&fields));
+
+        assert_eq!(texels[0], [0; 4]);
+        assert_eq!(texels[15], [255; 4]);
+        // Index 8 has weight 34 of 64
+        assert_eq!(texels[8], [135; 4]);
+    }
+
+    #[test]
+    fn bc7_mode_5_rotates_alpha_into_red() {
+        let mut fields = vec![(1 << 5, 6), (1, 2)];
+        // Color endpoints, then alpha endpoints
+        fields.extend([(127, 7), (127, 7), (0, 7), (0, 7), (0, 7), (0, 7)]);
+        fields.extend([(0, 8), (255, 8)]);
+        fields.push((0, 1));
+        fields.extend((1..16).map(|_| (0, 2)));
+        fields.push((1, 1));
+        fields.extend((1..16).map(|_| (3, 2)));
+        let texels = decode_bc7(&pack(&fields));
+
+        // Alpha reaches 255 everywhere but texel 0, then swaps places with red
+        assert_eq!(texels[0], [84, 0, 0, 255]);
+        assert_eq!(texels[1], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        assert_eq!(decode_bc7(&[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn bc7_partitions_pick_their_subset() {
+        // Mode 1, partition 13 splits the block into top and bottom halves
+        let mut fields = vec![(1 << 1, 2), (13, 6)];
+        for channel in 0..3 {
+            let value = if channel == 0 { 63 } else { 0 };
+            fields.extend([(value, 6), (value, 6), (0, 6), (0, 6)]);
+        }
+        fields.extend([(1, 1), (0, 1)]);
+        fields.extend((0..46).map(|_| (0, 1)));
+        let texels = decode_bc7(&pack(&fields));
+
+        for (texel, color) in texels.iter().enumerate() {
+            // The p-bit of the first subset also sets the bottom bit of green and blue
+            let expected = if texel < 8 {
+                [255, 2, 2, 255]
+            } else {
+                [0, 0, 0, 255]
+            };
+            assert_eq!(*color, expected, "texel {texel}");
+        }
+    }
+
+    #[test]
+    fn bc6h_mode_11_decodes_endpoints() {
+        // Unsigned endpoints 0 and 1023 are 0 and the largest finite value
+        let mut fields = vec![(0b00011, 5)];
+        fields.extend([(0, 10), (0, 10), (0, 10), (1023, 10), (512, 10), (0, 10)]);
+        fields.push((0, 3));
+        fields.extend((1..16).map(|_| (15, 4)));
+        let texels = decode_bc6h(&pack(&fields), false);
+
+        assert_eq!(texels[0], [0, 0, 0, 0x3C00]);
+        assert_eq!(texels[1][0], 0x7BFF);
+        // (512 << 16 + 0x8000) >> 10 = 32800, times 31/64
+        assert_eq!(texels[1][1], 15887);
+        assert_eq!(texels[1][2], 0);
+    }
+
+    #[test]
+    fn bc6h_signed_endpoints_keep_their_sign() {
+        let mut fields = vec![(0b00011, 5)];
+        // -1 and the largest positive value, in 10 bit two's complement
+        fields.extend([(0x3FF, 10), (0, 10), (0, 10), (511, 10), (0, 10), (0, 10)]);
+        fields.push((0, 3));
+        fields.extend((1..16).map(|_| (15, 4)));
+        let texels = decode_bc6h(&pack(&fields), true);
+
+        // -1 unquantizes to -((1 << 15) + 0x4000) >> 9 = -96
+        assert_eq!(texels[0][0], 0x8000 | ((96 * 31) >> 5));
+        assert_eq!(texels[1][0], ((0x7FFF * 31) >> 5) as u16);
+    }
+
+    #[test]
+    fn bc6h_reserved_mode_is_black() {
+        let block = pack(&[(0b10011, 5), (0, 123)]);
+        assert_eq!(decode_bc6h(&block, false), [[0, 0, 0, 0x3C00]; 16]);
+    }
+}
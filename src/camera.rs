@@ -133,6 +133,9 @@ impl Camera {
 pub struct CameraUniform {
     pub view_pos: [f32; 4],
     pub view_proj: [[f32; 4]; 4],
+    /// Inverse of `view_proj` without the camera translation, turning clip
+    /// space positions into sky directions.
+    pub sky_inv_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -140,11 +143,16 @@ impl CameraUniform {
         CameraUniform {
             view_pos: [0.0; 4],
             view_proj: na::Matrix4::identity().into(),
+            sky_inv_view_proj: na::Matrix4::identity().into(),
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &mut Camera) {
         self.view_pos = camera.position.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
+        let perspective =
+            na::Perspective3::new(camera.aspect, camera.fovy, camera.znear, camera.zfar);
+        self.sky_inv_view_proj =
+            (camera.rotation_matrix.transpose() * perspective.inverse()).into();
     }
 }
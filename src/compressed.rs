@@ -0,0 +1,583 @@
+//! KTX2 and DDS containers holding GPU-compressed textures with pre-baked mips.
+
+use std::io::Read;
+
+use anyhow::{bail, Context};
+
+use crate::texture::TextureKind;
+use crate::{astc, bptc};
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+/// Texel data in the format it's uploaded in, with every mip level.
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    /// The layers include the six faces of cube maps, or hold the depth of volume textures.
+    pub size: wgpu::Extent3d,
+    pub dimension: wgpu::TextureViewDimension,
+    /// Each mip level with all of its layers, laid out for `Queue::write_texture`.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Whether `bytes` start like a KTX2 or DDS file.
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(&KTX2_MAGIC) || bytes.starts_with(&DDS_MAGIC)
+    }
+
+    pub fn from_bytes(bytes: &[u8], kind: TextureKind) -> anyhow::Result<Self> {
+        if bytes.starts_with(&KTX2_MAGIC) {
+            Self::from_ktx2(bytes)
+        } else {
+            Self::from_dds(bytes, kind)
+        }
+    }
+
+    /// Reads a KTX2 file without supercompression or with zlib supercompression.
+    pub fn from_ktx2(bytes: &[u8]) -> anyhow::Result<Self> {
+        let reader = ktx2::Reader::new(bytes).context("Invalid KTX2 file")?;
+        let header = reader.header();
+        let format = header
+            .format
+            .and_then(ktx2_format)
+            .with_context(|| format!("Unsupported KTX2 format {:?}", header.format))?;
+
+        let cube = header.face_count == 6;
+        let dimension = match (header.pixel_depth, cube, header.layer_count) {
+            (depth, _, _) if depth > 0 => wgpu::TextureViewDimension::D3,
+            (_, true, 0 | 1) => wgpu::TextureViewDimension::Cube,
+            (_, true, _) => wgpu::TextureViewDimension::CubeArray,
+            (_, false, 0) => wgpu::TextureViewDimension::D2,
+            (_, false, _) => wgpu::TextureViewDimension::D2Array,
+        };
+        let size = wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers: match dimension {
+                wgpu::TextureViewDimension::D3 => header.pixel_depth,
+                _ => header.layer_count.max(1) * header.face_count,
+            },
+        };
+
+        let mut levels = Vec::new();
+        for level in reader.levels() {
+            let level = match header.supercompression_scheme {
+                None => level.to_vec(),
+                Some(ktx2::SupercompressionScheme::ZLIB) => {
+                    let mut data = Vec::new();
+                    flate2::read::ZlibDecoder::new(level).read_to_end(&mut data)?;
+                    data
+                }
+                Some(scheme) => bail!("Unsupported KTX2 supercompression {:?}", scheme),
+            };
+            levels.push(level);
+        }
+
+        let image = CompressedImage {
+            format,
+            size,
+            dimension,
+            levels,
+        };
+        image.validate()?;
+        Ok(image)
+    }
+
+    /// Reads a DDS file. Files without a DX10 header don't say whether they
+    /// hold colors, so BC1-3 are taken as sRGB only for `TextureKind::Color`.
+    pub fn from_dds(bytes: &[u8], kind: TextureKind) -> anyhow::Result<Self> {
+        let dds = ddsfile::Dds::read(bytes).context("Invalid DDS file")?;
+        let dxgi_format = dds.get_dxgi_format();
+        let mut format = dxgi_format
+            .and_then(dds_format)
+            .with_context(|| format!("Unsupported DDS format {:?}", dxgi_format))?;
+        if dds.header10.is_none() && kind != TextureKind::Color {
+            format = format.remove_srgb_suffix();
+        }
+
+        let (dimension, layers) = match &dds.header10 {
+            Some(header10)
+                if header10.resource_dimension == ddsfile::D3D10ResourceDimension::Texture3D =>
+            {
+                (wgpu::TextureViewDimension::D3, dds.get_depth())
+            }
+            Some(header10) if header10.misc_flag.contains(ddsfile::MiscFlag::TEXTURECUBE) => {
+                let dimension = if header10.array_size > 1 {
+                    wgpu::TextureViewDimension::CubeArray
+                } else {
+                    wgpu::TextureViewDimension::Cube
+                };
+                (dimension, header10.array_size.max(1) * 6)
+            }
+            Some(header10) if header10.array_size > 1 => {
+                (wgpu::TextureViewDimension::D2Array, header10.array_size)
+            }
+            None if dds.header.caps2.contains(ddsfile::Caps2::CUBEMAP) => {
+                (wgpu::TextureViewDimension::Cube, 6)
+            }
+            None if dds.header.caps2.contains(ddsfile::Caps2::VOLUME) => {
+                (wgpu::TextureViewDimension::D3, dds.get_depth())
+            }
+            _ => (wgpu::TextureViewDimension::D2, 1),
+        };
+        let size = wgpu::Extent3d {
+            width: dds.get_width(),
+            height: dds.get_height(),
+            depth_or_array_layers: layers,
+        };
+
+        // DDS stores each layer's whole mip chain in turn, while uploads go level by level
+        let level_count = dds.get_num_mipmap_levels().max(1) as usize;
+        let file_layers = match dimension {
+            wgpu::TextureViewDimension::D3 => 1,
+            _ => layers,
+        };
+        let mut levels = vec![Vec::new(); level_count];
+        let mut data = dds.data.as_slice();
+        for _ in 0..file_layers {
+            for (level, level_data) in levels.iter_mut().enumerate() {
+                let mut level_size = mip_size(size, dimension, level as u32);
+                if dimension != wgpu::TextureViewDimension::D3 {
+                    level_size.depth_or_array_layers = 1;
+                }
+                let length = byte_size(format, level_size);
+                if data.len() < length {
+                    bail!("DDS file is missing texel data");
+                }
+                let (layer_data, rest) = data.split_at(length);
+                level_data.extend_from_slice(layer_data);
+                data = rest;
+            }
+        }
+
+        let image = CompressedImage {
+            format,
+            size,
+            dimension,
+            levels,
+        };
+        image.validate()?;
+        Ok(image)
+    }
+
+    pub fn texture_dimension(&self) -> wgpu::TextureDimension {
+        match self.dimension {
+            wgpu::TextureViewDimension::D3 => wgpu::TextureDimension::D3,
+            _ => wgpu::TextureDimension::D2,
+        }
+    }
+
+    pub fn level_size(&self, level: u32) -> wgpu::Extent3d {
+        mip_size(self.size, self.dimension, level)
+    }
+
+    /// Decodes BC and ASTC formats for devices without the feature they need.
+    /// UNORM formats become RGBA8, BC4 and BC5 SNORM become RGBA8 SNORM, and
+    /// BC6H and HDR ASTC become RGBA16 float.
+    pub fn decompress(&self) -> anyhow::Result<Self> {
+        use wgpu::TextureFormat as F;
+
+        let format = match self.format {
+            F::Bc1RgbaUnormSrgb
+            | F::Bc2RgbaUnormSrgb
+            | F::Bc3RgbaUnormSrgb
+            | F::Bc7RgbaUnormSrgb
+            | F::Astc {
+                channel: wgpu::AstcChannel::UnormSrgb,
+                ..
+            } => F::Rgba8UnormSrgb,
+            F::Bc1RgbaUnorm
+            | F::Bc2RgbaUnorm
+            | F::Bc3RgbaUnorm
+            | F::Bc4RUnorm
+            | F::Bc5RgUnorm
+            | F::Bc7RgbaUnorm
+            | F::Astc {
+                channel: wgpu::AstcChannel::Unorm,
+                ..
+            } => F::Rgba8Unorm,
+            F::Bc4RSnorm | F::Bc5RgSnorm => F::Rgba8Snorm,
+            F::Bc6hRgbUfloat
+            | F::Bc6hRgbFloat
+            | F::Astc {
+                channel: wgpu::AstcChannel::Hdr,
+                ..
+            } => F::Rgba16Float,
+            format => bail!("No CPU decoder for {:?}", format),
+        };
+
+        let (block_width, block_height) = self.format.block_dimensions();
+        let (block_width, block_height) = (block_width as usize, block_height as usize);
+        let block_size = self.format.block_size(None).unwrap_or(16) as usize;
+        let texel_size = format.block_size(None).unwrap_or(4) as usize;
+        let mut texels = vec![0; block_width * block_height * texel_size];
+
+        let mut levels = Vec::with_capacity(self.levels.len());
+        for (level, data) in self.levels.iter().enumerate() {
+            let size = self.level_size(level as u32);
+            let (width, height) = (size.width as usize, size.height as usize);
+            let blocks_wide = width.div_ceil(block_width);
+            let blocks_high = height.div_ceil(block_height);
+
+            let mut pixels =
+                vec![0; width * height * size.depth_or_array_layers as usize * texel_size];
+            let mut blocks = data.chunks_exact(block_size);
+            for layer in 0..size.depth_or_array_layers as usize {
+                let layer_start = layer * width * height;
+                for block_y in 0..blocks_high {
+                    for block_x in 0..blocks_wide {
+                        let block = blocks.next().context("Texel data ends early")?;
+                        decode_block(self.format, block, &mut texels);
+                        // Edge blocks can hang over textures that aren't a multiple of their size
+                        for (i, texel) in texels.chunks_exact(texel_size).enumerate() {
+                            let x = block_x * block_width + i % block_width;
+                            let y = block_y * block_height + i / block_width;
+                            if x < width && y < height {
+                                let start = (layer_start + y * width + x) * texel_size;
+                                pixels[start..start + texel_size].copy_from_slice(texel);
+                            }
+                        }
+                    }
+                }
+            }
+            levels.push(pixels);
+        }
+
+        Ok(CompressedImage {
+            format,
+            size: self.size,
+            dimension: self.dimension,
+            levels,
+        })
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let (block_width, block_height) = self.format.block_dimensions();
+        if !self.size.width.is_multiple_of(block_width)
+            || !self.size.height.is_multiple_of(block_height)
+        {
+            bail!(
+                "{}x{} isn't a multiple of the {}x{} blocks of {:?}",
+                self.size.width,
+                self.size.height,
+                block_width,
+                block_height,
+                self.format
+            );
+        }
+        if self.levels.is_empty() {
+            bail!("Texture has no mip levels");
+        }
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected = byte_size(self.format, self.level_size(level as u32));
+            if data.len() != expected {
+                bail!(
+                    "Mip level {} has {} bytes instead of {}",
+                    level,
+                    data.len(),
+                    expected
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mip_size(
+    size: wgpu::Extent3d,
+    dimension: wgpu::TextureViewDimension,
+    level: u32,
+) -> wgpu::Extent3d {
+    let dimension = match dimension {
+        wgpu::TextureViewDimension::D3 => wgpu::TextureDimension::D3,
+        _ => wgpu::TextureDimension::D2,
+    };
+    size.mip_level_size(level, dimension)
+}
+
+/// The number of bytes `size` texels take up in `format`, counting partial blocks as whole.
+pub fn byte_size(format: wgpu::TextureFormat, size: wgpu::Extent3d) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_size(None).unwrap_or(0);
+    let blocks_wide = size.width.div_ceil(block_width);
+    let blocks_high = size.height.div_ceil(block_height);
+    (blocks_wide * blocks_high * block_size) as usize * size.depth_or_array_layers as usize
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        K::R8G8B8A8_UNORM => F::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => F::Rgba8UnormSrgb,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        _ => return astc_format(format.0.get()),
+    })
+}
+
+// The Vulkan ASTC formats come in UNORM/SRGB pairs, in the same block order as wgpu's
+fn astc_format(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::AstcBlock as B;
+
+    const FIRST_ASTC: u32 = 157;
+    const BLOCKS: [B; 14] = [
+        B::B4x4,
+        B::B5x4,
+        B::B5x5,
+        B::B6x5,
+        B::B6x6,
+        B::B8x5,
+        B::B8x6,
+        B::B8x8,
+        B::B10x5,
+        B::B10x6,
+        B::B10x8,
+        B::B10x10,
+        B::B12x10,
+        B::B12x12,
+    ];
+    let index = vk_format.checked_sub(FIRST_ASTC)? as usize;
+    let block = *BLOCKS.get(index / 2)?;
+    let channel = if index.is_multiple_of(2) {
+        wgpu::AstcChannel::Unorm
+    } else {
+        wgpu::AstcChannel::UnormSrgb
+    };
+    Some(wgpu::TextureFormat::Astc { block, channel })
+}
+
+fn dds_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        D::R8G8B8A8_UNorm => F::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => F::Rgba8UnormSrgb,
+        D::B8G8R8A8_UNorm => F::Bgra8Unorm,
+        D::B8G8R8A8_UNorm_sRGB => F::Bgra8UnormSrgb,
+        D::BC1_UNorm => F::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm => F::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm => F::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        D::BC4_UNorm => F::Bc4RUnorm,
+        D::BC4_SNorm => F::Bc4RSnorm,
+        D::BC5_UNorm => F::Bc5RgUnorm,
+        D::BC5_SNorm => F::Bc5RgSnorm,
+        D::BC6H_UF16 => F::Bc6hRgbUfloat,
+        D::BC6H_SF16 => F::Bc6hRgbFloat,
+        D::BC7_UNorm => F::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+// Decodes one block into `texels` in row order, in the format `decompress` picks
+fn decode_block(format: wgpu::TextureFormat, block: &[u8], texels: &mut [u8]) {
+    use wgpu::TextureFormat as F;
+
+    let rgba8 = match format {
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => decode_bc1(block, false),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => {
+            let mut texels = decode_bc1(&block[8..], true);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = ((alpha >> (4 * i)) & 0xF) as u8 * 17;
+            }
+            texels
+        }
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => {
+            let mut texels = decode_bc1(&block[8..], true);
+            for (texel, alpha) in texels.iter_mut().zip(decode_bc4(&block[..8])) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        F::Bc4RUnorm => decode_bc4(block).map(|red| [red, 0, 0, 255]),
+        F::Bc5RgUnorm => {
+            let red = decode_bc4(&block[..8]);
+            let green = decode_bc4(&block[8..]);
+            std::array::from_fn(|i| [red[i], green[i], 0, 255])
+        }
+        F::Bc4RSnorm => decode_bc4_snorm(block).map(|red| [red as u8, 0, 0, 127]),
+        F::Bc5RgSnorm => {
+            let red = decode_bc4_snorm(&block[..8]);
+            let green = decode_bc4_snorm(&block[8..]);
+            std::array::from_fn(|i| [red[i] as u8, green[i] as u8, 0, 127])
+        }
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => bptc::decode_bc7(block),
+        F::Bc6hRgbUfloat | F::Bc6hRgbFloat => {
+            let signed = format == F::Bc6hRgbFloat;
+            let halves = bptc::decode_bc6h(block, signed).concat();
+            for (half, out) in halves.iter().zip(texels.chunks_exact_mut(2)) {
+                out.copy_from_slice(&half.to_le_bytes());
+            }
+            return;
+        }
+        F::Astc { channel, .. } => {
+            let (width, height) = format.block_dimensions();
+            let profile = match channel {
+                wgpu::AstcChannel::Unorm => astc::Profile::Ldr,
+                wgpu::AstcChannel::UnormSrgb => astc::Profile::Srgb,
+                wgpu::AstcChannel::Hdr => astc::Profile::Hdr,
+            };
+            astc::decode_block(block, width as usize, height as usize, profile, texels);
+            return;
+        }
+        _ => [[0; 4]; 16],
+    };
+    texels.copy_from_slice(rgba8.as_flattened());
+}
+
+// BC2 and BC3 always use four colors, BC1 switches to three and transparent
+// black when the endpoints are in descending order
+fn decode_bc1(block: &[u8], always_opaque: bool) -> [[u8; 4]; 16] {
+    let endpoint0 = u16::from_le_bytes([block[0], block[1]]);
+    let endpoint1 = u16::from_le_bytes([block[2], block[3]]);
+    let color0 = expand_565(endpoint0);
+    let color1 = expand_565(endpoint1);
+    let mix = |a: u8, b: u8, wa: u16, wb: u16| ((a as u16 * wa + b as u16 * wb) / (wa + wb)) as u8;
+
+    let palette: [[u8; 4]; 4] = if always_opaque || endpoint0 > endpoint1 {
+        [
+            color0,
+            color1,
+            std::array::from_fn(|c| mix(color0[c], color1[c], 2, 1)),
+            std::array::from_fn(|c| mix(color0[c], color1[c], 1, 2)),
+        ]
+    } else {
+        [
+            color0,
+            color1,
+            std::array::from_fn(|c| mix(color0[c], color1[c], 1, 1)),
+            [0, 0, 0, 0],
+        ]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| palette[((indices >> (2 * i)) & 0b11) as usize])
+}
+
+fn expand_565(color: u16) -> [u8; 4] {
+    let red = (color >> 11) as u8 & 0x1F;
+    let green = (color >> 5) as u8 & 0x3F;
+    let blue = color as u8 & 0x1F;
+    [
+        (red << 3) | (red >> 2),
+        (green << 2) | (green >> 4),
+        (blue << 3) | (blue >> 2),
+        255,
+    ]
+}
+
+// One channel from two endpoints and 3 bit indices, also BC3's alpha
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (value0, value1) = (block[0] as u16, block[1] as u16);
+    let mut palette = [0u8; 8];
+    palette[0] = value0 as u8;
+    palette[1] = value1 as u8;
+    if value0 > value1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u16) * value0 + i as u16 * value1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u16) * value0 + i as u16 * value1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[((indices >> (3 * i)) & 0b111) as usize])
+}
+
+// Like `decode_bc4`, for endpoints from -127 to 127 where -128 also means -127.
+// The interpolated values are rounded like hardware decoders do.
+fn decode_bc4_snorm(block: &[u8]) -> [i8; 16] {
+    let value0 = (block[0] as i8).max(-127) as f32;
+    let value1 = (block[1] as i8).max(-127) as f32;
+    let mut palette = [0i8; 8];
+    palette[0] = value0 as i8;
+    palette[1] = value1 as i8;
+    if value0 > value1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i) as f32 * value0 + i as f32 * value1) / 7.0).round() as i8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i) as f32 * value0 + i as f32 * value1) / 5.0).round() as i8;
+        }
+        palette[6] = -127;
+        palette[7] = 127;
+    }
+
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[((indices >> (3 * i)) & 0b111) as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bc4_snorm_clamps_and_rounds() {
+        // -128 reads as -127, and ascending endpoints pick the five value palette
+        let block = [0x80, 0x7F, 0x90, 0x0F, 0, 0, 0, 0];
+        let texels = decode_bc4_snorm(&block);
+        assert_eq!(&texels[..4], &[-127, -76, -127, 127]);
+    }
+
+    #[test]
+    fn astc_blocks_cover_their_own_size() {
+        let void_extent = |red: u8| {
+            let mut block = [0xFF; 16];
+            block[..2].copy_from_slice(&0xFDFCu16.to_le_bytes());
+            block[8..].copy_from_slice(&[0, red, 0, 0, 0, 0, 0xFF, 0xFF]);
+            block
+        };
+        let image = CompressedImage {
+            format: wgpu::TextureFormat::Astc {
+                block: wgpu::AstcBlock::B6x5,
+                channel: wgpu::AstcChannel::Unorm,
+            },
+            size: wgpu::Extent3d {
+                width: 12,
+                height: 5,
+                depth_or_array_layers: 1,
+            },
+            dimension: wgpu::TextureViewDimension::D2,
+            levels: vec![[void_extent(0x40), void_extent(0xC0)].concat()],
+        };
+
+        let decoded = image.decompress().unwrap();
+        assert_eq!(decoded.format, wgpu::TextureFormat::Rgba8Unorm);
+        let texels = decoded.levels[0].chunks_exact(4).collect::<Vec<_>>();
+        assert_eq!(texels.len(), 12 * 5);
+        assert_eq!(texels[5], [0x40, 0, 0, 0xFF]);
+        assert_eq!(texels[6], [0xC0, 0, 0, 0xFF]);
+        assert_eq!(texels[4 * 12 + 11], [0xC0, 0, 0, 0xFF]);
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+use crate::scene::{ModelId, NodeId, Scene, Transform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+pub struct MeshRenderer {
+    pub model: ModelId,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LightKind {
+    /// Fades out smoothly, reaching zero at `radius`.
+    Point { radius: f32 },
+    /// Shines along the entity's forward (-Z) axis from infinitely far away.
+    Directional,
+    /// A point light limited to a cone around the forward (-Z) axis. Angles are
+    /// half-angles in radians, with light fading out between the two.
+    Spot {
+        radius: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Point lights use one of `shadow::MAX_POINT_SHADOWS` cube maps.
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn with_shadows(mut self) -> Self {
+        self.cast_shadows = true;
+        self
+    }
+
+    pub fn point(color: [f32; 3], intensity: f32, radius: f32) -> Self {
+        Light {
+            kind: LightKind::Point { radius },
+            color,
+            intensity,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn directional(color: [f32; 3], intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Directional,
+            color,
+            intensity,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn spot(
+        color: [f32; 3],
+        intensity: f32,
+        radius: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                radius,
+                inner_angle,
+                outer_angle,
+            },
+            color,
+            intensity,
+            cast_shadows: false,
+        }
+    }
+}
+
+pub struct Parent(pub Entity);
+
+pub type System = Box<dyn FnMut(&mut World)>;
+
+trait ComponentStorage {
+    fn remove(&mut self, index: usize);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> ComponentStorage for Vec<Option<T>> {
+    fn remove(&mut self, index: usize) {
+        if let Some(component) = self.get_mut(index) {
+            *component = None;
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free_indices: Vec<u32>,
+    components: HashMap<TypeId, Box<dyn ComponentStorage>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free_indices.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity {
+                    index,
+                    generation: self.generations[index as usize],
+                }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        let index = entity.index as usize;
+        for storage in self.components.values_mut() {
+            storage.remove(index);
+        }
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        // A slot whose generation wrapped around could be mistaken for an old
+        // entity, so it is retired instead of reused
+        if self.generations[index] != 0 {
+            self.free_indices.push(entity.index);
+        }
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        index < self.alive.len()
+            && self.alive[index]
+            && self.generations[index] == entity.generation
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.alive
+            .iter()
+            .enumerate()
+            .filter(|(_, alive)| **alive)
+            .map(|(index, _)| self.entity_at(index))
+    }
+
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) {
+        if !self.is_alive(entity) {
+            return;
+        }
+
+        let index = entity.index as usize;
+        let storage = self
+            .components
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Vec::<Option<T>>::new()))
+            .as_any_mut()
+            .downcast_mut::<Vec<Option<T>>>()
+            .unwrap();
+        if storage.len() <= index {
+            storage.resize_with(index + 1, || None);
+        }
+        storage[index] = Some(component);
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storage_mut::<T>()?
+            .get_mut(entity.index as usize)?
+            .take()
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storage::<T>()?.get(entity.index as usize)?.as_ref()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storage_mut::<T>()?
+            .get_mut(entity.index as usize)?
+            .as_mut()
+    }
+
+    pub fn query<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> + '_ {
+        self.storage::<T>()
+            .into_iter()
+            .flat_map(|storage| storage.iter().enumerate())
+            .filter_map(|(index, component)| {
+                component
+                    .as_ref()
+                    .map(|component| (self.entity_at(index), component))
+            })
+    }
+
+    pub fn query_mut<T: 'static>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> + '_ {
+        let generations = &self.generations;
+        self.components
+            .get_mut(&TypeId::of::<T>())
+            .map(|storage| {
+                storage
+                    .as_any_mut()
+                    .downcast_mut::<Vec<Option<T>>>()
+                    .unwrap()
+            })
+            .into_iter()
+            .flat_map(|storage| storage.iter_mut().enumerate())
+            .filter_map(move |(index, component)| {
+                component.as_mut().map(|component| {
+                    let entity = Entity {
+                        index: index as u32,
+                        generation: generations[index],
+                    };
+                    (entity, component)
+                })
+            })
+    }
+
+    pub fn query2<A: 'static, B: 'static>(&self) -> impl Iterator<Item = (Entity, &A, &B)> + '_ {
+        self.query::<A>()
+            .filter_map(|(entity, a)| self.get::<B>(entity).map(|b| (entity, a, b)))
+    }
+
+    fn entity_at(&self, index: usize) -> Entity {
+        Entity {
+            index: index as u32,
+            generation: self.generations[index],
+        }
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&Vec<Option<T>>> {
+        self.components
+            .get(&TypeId::of::<T>())
+            .map(|storage| storage.as_any().downcast_ref::<Vec<Option<T>>>().unwrap())
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> Option<&mut Vec<Option<T>>> {
+        self.components.get_mut(&TypeId::of::<T>()).map(|storage| {
+            storage
+                .as_any_mut()
+                .downcast_mut::<Vec<Option<T>>>()
+                .unwrap()
+        })
+    }
+}
+
+/// Mirrors entities with a `Transform` into scene graph nodes so the renderer can draw them.
+#[derive(Default)]
+pub struct SceneSync {
+    nodes: HashMap<Entity, NodeId>,
+}
+
+impl SceneSync {
+    pub fn node(&self, entity: Entity) -> Option<NodeId> {
+        self.nodes.get(&entity).copied()
+    }
+
+    pub fn sync(&mut self, world: &World, scene: &mut Scene) {
+        self.nodes.retain(|&entity, &mut node| {
+            let keep = world.get::<Transform>(entity).is_some();
+            if !keep {
+                scene.remove_node(node);
+            }
+            keep
+        });
+
+        for (entity, transform) in world.query::<Transform>() {
+            let node = *self
+                .nodes
+                .entry(entity)
+                .or_insert_with(|| scene.add_node("entity", *transform, None));
+            let scene_node = scene.node_mut(node);
+            scene_node.transform = *transform;
+            scene_node.model = world.get::<MeshRenderer>(entity).map(|m| m.model);
+        }
+
+        for (&entity, &node) in &self.nodes {
+            let parent = world
+                .get::<Parent>(entity)
+                .and_then(|parent| self.nodes.get(&parent.0).copied());
+            if scene.node(node).parent() != parent {
+                if let Err(e) = scene.set_parent(node, parent) {
+                    log::warn!("{}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawned_slots_are_reused_with_a_new_generation() {
+        let mut world = World::new();
+        let a = world.spawn();
+        world.insert(a, 1u32);
+        assert!(world.despawn(a));
+        assert!(!world.despawn(a));
+
+        let b = world.spawn();
+        assert_eq!(b.index, a.index);
+        assert_ne!(b.generation, a.generation);
+        assert!(!world.is_alive(a));
+        assert!(world.get::<u32>(a).is_none());
+        // Components of the old entity don't carry over to the new one
+        assert!(world.get::<u32>(b).is_none());
+        assert_eq!(world.entities().count(), 1);
+    }
+
+    #[test]
+    fn slots_are_retired_when_their_generation_wraps() {
+        let mut world = World::new();
+        let a = world.spawn();
+        world.generations[a.index as usize] = u32::MAX;
+        let a = world.entity_at(a.index as usize);
+        world.despawn(a);
+
+        let b = world.spawn();
+        assert_ne!(b.index, a.index);
+        assert!(!world.is_alive(Entity {
+            index: a.index,
+            generation: 0,
+        }));
+    }
+
+    #[test]
+    fn insert_get_and_remove_components() {
+        let mut world = World::new();
+        let a = world.spawn();
+        let b = world.spawn();
+        world.insert(a, "a");
+        world.insert(b, 2i32);
+
+        assert_eq!(world.get::<&str>(a), Some(&"a"));
+        assert!(world.get::<&str>(b).is_none());
+        *world.get_mut::<i32>(b).unwrap() += 1;
+        assert_eq!(world.remove::<i32>(b), Some(3));
+        assert_eq!(world.remove::<i32>(b), None);
+
+        world.despawn(a);
+        world.insert(a, "stale");
+        assert!(world.query::<&str>().next().is_none());
+    }
+
+    #[test]
+    fn queries_only_yield_entities_with_every_component() {
+        let mut world = World::new();
+        let a = world.spawn();
+        let b = world.spawn();
+        let c = world.spawn();
+        world.insert(a, 1u32);
+        world.insert(b, 2u32);
+        world.insert(b, 2.0f32);
+        world.insert(c, 3.0f32);
+
+        let mut single = world
+            .query::<u32>()
+            .map(|(e, &v)| (e, v))
+            .collect::<Vec<_>>();
+        single.sort_by_key(|(_, v)| *v);
+        assert_eq!(single, vec![(a, 1), (b, 2)]);
+        let pairs = world
+            .query2::<u32, f32>()
+            .map(|(e, &x, &y)| (e, x, y))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, vec![(b, 2, 2.0)]);
+
+        for (_, value) in world.query_mut::<f32>() {
+            *value *= 2.0;
+        }
+        assert_eq!(world.get::<f32>(c), Some(&6.0));
+    }
+}
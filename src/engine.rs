@@ -1,23 +1,35 @@
+use std::{path::Path, rc::Rc};
+
 use nalgebra as na;
 use sdl2::{keyboard::Scancode, mouse::RelativeMouseState, video::Window};
 use wgpu::util::DeviceExt;
 
 use crate::{
+    assets::AssetServer,
     camera::{Camera, CameraUniform},
-    light::{DrawLight, LightUniform},
-    model::{DrawModel, Model, ModelVertex, Vertex},
-    pipeline::Pipeline,
+    ecs::{Light, SceneSync, System, World},
+    environment::Environment,
+    hot_reload::{Change, HotReload},
+    light::{LightArrayUniform, LightUniform},
+    model::{ModelVertex, Vertex},
+    pipeline_cache::{LayoutRegistry, PipelineCache, PipelineKey},
+    postprocess::PostProcess,
     resources,
+    scene::{DrawScene, Scene, Transform},
+    shadow::{ShadowSettings, Shadows},
+    skybox::{DrawSkybox, Sky, Skybox},
     texture::Texture,
+    tonemap::{Tonemap, TonemapSettings},
+    vfs::{self, DirectoryMount},
 };
 
-struct Instance {
-    position: na::Vector3<f32>,
-    rotation: na::Quaternion<f32>,
+pub struct Instance {
+    pub position: na::Vector3<f32>,
+    pub rotation: na::Quaternion<f32>,
 }
 
 impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
+    pub fn to_raw(&self) -> InstanceRaw {
         let unit_quaternion = na::UnitQuaternion::from_quaternion(self.rotation);
         let rotation_matrix = na::Matrix4::from(unit_quaternion.to_rotation_matrix());
         let translation_matrix = na::Matrix4::from(na::Translation3::from(self.position));
@@ -31,11 +43,27 @@ impl Instance {
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
+pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
 }
 
+impl InstanceRaw {
+    pub fn from_matrix(model: na::Matrix4<f32>) -> Self {
+        // The inverse transpose keeps normals perpendicular under non-uniform scale
+        let normal = model
+            .fixed_view::<3, 3>(0, 0)
+            .into_owned()
+            .try_inverse()
+            .unwrap_or_else(na::Matrix3::identity)
+            .transpose();
+        InstanceRaw {
+            model: model.into(),
+            normal: normal.into(),
+        }
+    }
+}
+
 impl Vertex for InstanceRaw {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         const ATRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![5 => Float32x4, 6 => Float32x4, 7=> Float32x4, 8=>Float32x4, 9=>Float32x3, 10=>Float32x3, 11=>Float32x3];
@@ -47,30 +75,54 @@ impl Vertex for InstanceRaw {
     }
 }
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
+enum RenderTarget<'a> {
+    Window {
+        surface: wgpu::Surface,
+        window: &'a Window,
+    },
+    Offscreen(Texture),
+}
 
 pub struct Engine<'a> {
-    surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    target: RenderTarget<'a>,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub config: wgpu::SurfaceConfiguration,
     pub size: (u32, u32),
-    window: &'a Window,
-    render_pipeline: wgpu::RenderPipeline,
-    camera: Camera,
+    pub render_pipeline: Rc<wgpu::RenderPipeline>,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    pub camera_bind_group_layout: Rc<wgpu::BindGroupLayout>,
+    pub camera_bind_group: wgpu::BindGroup,
     pub frame_number: usize,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
+    sample_count: u32,
+    supported_sample_counts: Vec<u32>,
     depth_texture: Texture,
-    obj_model: Model,
-    obj_2: Model,
-    light_uniform: LightUniform,
+    // Resolved into the tonemap target when multisampling
+    multisampled_target: Option<wgpu::TextureView>,
+    pub texture_bind_group_layout: Rc<wgpu::BindGroupLayout>,
+    light_uniform: LightArrayUniform,
     light_buffer: wgpu::Buffer,
-    light_bind_group: wgpu::BindGroup,
-    light_pipeline: wgpu::RenderPipeline,
+    pub light_bind_group_layout: Rc<wgpu::BindGroupLayout>,
+    pub light_bind_group: wgpu::BindGroup,
+    pub light_pipeline: Rc<wgpu::RenderPipeline>,
+    /// Holds the "texture", "camera" and "light" layouts above.
+    pub layouts: LayoutRegistry,
+    /// Holds the "shader.wgsl" and "light.wgsl" pipelines.
+    pub pipelines: PipelineCache,
+    shadows: Shadows,
+    environment: Environment,
+    skybox: Skybox,
+    tonemap: Tonemap,
+    pub post_process: PostProcess,
+    /// Lights every surface evenly when no environment map is set.
+    pub ambient_light: [f32; 3],
+    hot_reload: Option<HotReload>,
+    pub assets: AssetServer,
+    pub scene: Scene,
+    pub world: World,
+    scene_sync: SceneSync,
+    systems: Vec<System>,
     pub relative_mouse: bool,
 }
 
@@ -94,17 +146,9 @@ impl<'a> Engine<'a> {
             .await
             .unwrap();
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (device, queue) = Self::request_device(&adapter).await;
+        let sample_counts = Self::query_sample_counts(&adapter, &device);
+        let point_shadows = Self::supports_point_shadows(&adapter);
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -125,44 +169,182 @@ impl<'a> Engine<'a> {
         };
         surface.configure(&device, &config);
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("texture_bind_group_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
+        Self::from_target(
+            device,
+            queue,
+            config,
+            sample_counts,
+            point_shadows,
+            RenderTarget::Window { surface, window },
+        )
+    }
+
+    /// Creates an engine without a window that renders into an owned texture,
+    /// preferring the software fallback adapter so it can run without a GPU.
+    pub async fn new_headless(size: (u32, u32)) -> Engine<'a> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+
+        let fallback_adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptionsBase {
+                power_preference: wgpu::PowerPreference::default(),
+                force_fallback_adapter: true,
+                compatible_surface: None,
+            })
+            .await;
+        let adapter = match fallback_adapter {
+            Some(adapter) => adapter,
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptionsBase {
+                    power_preference: wgpu::PowerPreference::default(),
+                    force_fallback_adapter: false,
+                    compatible_surface: None,
+                })
+                .await
+                .unwrap(),
+        };
+
+        let (device, queue) = Self::request_device(&adapter).await;
+        let sample_counts = Self::query_sample_counts(&adapter, &device);
+        let point_shadows = Self::supports_point_shadows(&adapter);
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.0,
+            height: size.1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+        let render_texture = Texture::create_render_target(&device, &config, "render_target");
+
+        Self::from_target(
+            device,
+            queue,
+            config,
+            sample_counts,
+            point_shadows,
+            RenderTarget::Offscreen(render_texture),
+        )
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        // Compressed textures are decoded on the CPU where these are missing
+        let compression =
+            wgpu::Features::TEXTURE_COMPRESSION_BC | wgpu::Features::TEXTURE_COMPRESSION_ASTC;
+        // Allows sample counts other than 1 and 4
+        let format_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        // Wireframes and points from `PipelineBuilder::polygon_mode`
+        let polygon_modes = wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::POLYGON_MODE_POINT;
+        let optional = compression | format_features | polygon_modes;
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: adapter.features() & optional,
+                    limits: wgpu::Limits::default(),
+                    label: None,
+                },
+                None,
+            )
+            .await
+            .unwrap()
+    }
+
+    /// The sample counts the scene's color and depth formats can both be rendered with.
+    fn query_sample_counts(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Vec<u32> {
+        if !device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            return vec![1, 4];
+        }
+        let color = adapter
+            .get_texture_format_features(Texture::HDR_FORMAT)
+            .flags;
+        let depth = adapter
+            .get_texture_format_features(Texture::DEPTH_FORMAT)
+            .flags;
+        [1, 2, 4, 8, 16]
+            .into_iter()
+            .filter(|&count| {
+                color.sample_count_supported(count) && depth.sample_count_supported(count)
+            })
+            .collect()
+    }
+
+    /// Point shadows are sampled from a cube array, which downlevel adapters
+    /// such as WebGL2 don't support.
+    fn supports_point_shadows(adapter: &wgpu::Adapter) -> bool {
+        let supported = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::CUBE_ARRAY_TEXTURES);
+        if !supported {
+            log::warn!("Cube array textures are unsupported, point lights won't cast shadows");
+        }
+        supported
+    }
+
+    fn from_target(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        supported_sample_counts: Vec<u32>,
+        point_shadows: bool,
+        target: RenderTarget<'a>,
+    ) -> Engine<'a> {
+        let size = (config.width, config.height);
+        let mut layouts = LayoutRegistry::new();
+        // Multisampling is opted into with `set_sample_count`, since every
+        // pipeline drawn in the main pass has to match it
+        let sample_count = 1;
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        // Base color, normal, metallic-roughness, occlusion and emissive maps, see `Material::new`
+        let texture_bind_group_layout = layouts.register(
+            &device,
+            "texture",
+            &[
+                texture_entry(0),
+                sampler_entry(1),
+                texture_entry(2),
+                sampler_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
                     },
-                ],
-            });
+                    count: None,
+                },
+                texture_entry(5),
+                sampler_entry(6),
+                texture_entry(7),
+                sampler_entry(8),
+                texture_entry(9),
+                sampler_entry(10),
+            ],
+        );
 
         let mut camera = Camera {
             position: na::Vector3::new(0.0, 0.0, -2.0),
@@ -188,20 +370,20 @@ impl<'a> Engine<'a> {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
+        let camera_bind_group_layout = layouts.register(
+            &device,
+            "camera",
+            &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        );
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("camera_bind_group"),
@@ -212,53 +394,12 @@ impl<'a> Engine<'a> {
             }],
         });
 
-        let epsilon = 1e-6;
-        let space_between = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = space_between * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = space_between * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let position = na::Vector3::new(x as f32, 0.0, z as f32);
-
-                    let rotation = if position.norm() < epsilon {
-                        na::UnitQuaternion::from_axis_angle(
-                            &na::Unit::new_normalize(na::Vector3::new(0.0, 0.0, 1.0)),
-                            0.0,
-                        )
-                    } else {
-                        na::UnitQuaternion::from_axis_angle(
-                            &na::Unit::new_normalize(position),
-                            45.0,
-                        )
-                    };
-                    Instance {
-                        position,
-                        rotation: *rotation,
-                    }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let obj_model =
-            resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
+        let depth_texture =
+            Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let multisampled_target = Self::create_multisampled_target(&device, &config, sample_count);
 
-        let obj_2 = resources::load_model("blade.obj", &device, &queue, &texture_bind_group_layout)
-            .await
-            .unwrap();
-
-        let light_uniform = LightUniform::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0]);
+        let ambient_light = [0.1; 3];
+        let light_uniform = LightArrayUniform::new(ambient_light, []);
 
         let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light VB"),
@@ -266,10 +407,11 @@ impl<'a> Engine<'a> {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
+        let light_bind_group_layout = layouts.register(
+            &device,
+            "light",
+            &[
+                wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
@@ -278,86 +420,279 @@ impl<'a> Engine<'a> {
                         min_binding_size: None,
                     },
                     count: None,
-                }],
-            });
-
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render pipeline layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &light_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let shader = wgpu::include_wgsl!(".././shaders/shader.wgsl");
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: if point_shadows {
+                            wgpu::TextureViewDimension::CubeArray
+                        } else {
+                            wgpu::TextureViewDimension::Cube
+                        },
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                // Image-based lighting: irradiance, prefiltered specular, BRDF LUT
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        );
 
-        let render_pipeline = Pipeline::new(
+        let shadows = Shadows::new(&device, ShadowSettings::default(), point_shadows);
+        let environment = Environment::empty(&device);
+        let skybox = Skybox::new(
             &device,
-            &render_pipeline_layout,
-            config.format,
-            Some(Texture::DEPTH_FORMAT),
-            &[ModelVertex::desc(), InstanceRaw::desc()],
-            shader,
-        )
-        .pipeline;
+            Texture::HDR_FORMAT,
+            sample_count,
+            &camera_bind_group_layout,
+            Sky::default(),
+        );
+        let tonemap = Tonemap::new(&device, &config, TonemapSettings::default());
+        let post_process = PostProcess::new(&device, &config);
+        let light_bind_group = Self::create_light_bind_group(
+            &device,
+            &light_bind_group_layout,
+            &light_buffer,
+            &shadows,
+            &environment,
+        );
 
-        let light_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light pipeline layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        let shader = wgpu::include_wgsl!(".././shaders/light.wgsl");
-        let light_pipeline = Pipeline::new(
+        let mut pipelines = PipelineCache::new();
+        pipelines.add_shader(
             &device,
-            &light_pipeline_layout,
-            config.format,
-            Some(Texture::DEPTH_FORMAT),
-            &[ModelVertex::desc()],
-            shader,
-        )
-        .pipeline;
+            "shader.wgsl",
+            Self::scene_shader(
+                point_shadows,
+                &resources::load_shader_source(
+                    "shader.wgsl",
+                    include_str!("../shaders/shader.wgsl"),
+                ),
+            ),
+        );
+        pipelines.add_shader(
+            &device,
+            "light.wgsl",
+            resources::load_shader("light.wgsl", include_str!("../shaders/light.wgsl")),
+        );
+        let render_pipeline = pipelines
+            .get(
+                &device,
+                &mut layouts,
+                &Self::render_pipeline_key(sample_count),
+            )
+            .unwrap();
+        let light_pipeline = pipelines
+            .get(
+                &device,
+                &mut layouts,
+                &Self::light_pipeline_key(sample_count),
+            )
+            .unwrap();
+
+        let scene = Scene::new(&device);
+
+        let mut world = World::new();
+        let camera_entity = world.spawn();
+        world.insert(camera_entity, camera);
+        let light_entity = world.spawn();
+        world.insert(
+            light_entity,
+            Transform::from_translation(na::Vector3::new(2.0, 2.0, 2.0)),
+        );
+        world.insert(light_entity, Light::point([1.0, 1.0, 1.0], 1.0, 100.0));
 
         Engine {
-            surface,
+            target,
             device,
             queue,
             config,
             size,
-            window,
             render_pipeline,
-            camera,
             camera_uniform,
             camera_buffer,
+            camera_bind_group_layout,
             camera_bind_group,
             frame_number: 0,
-            instances,
-            instance_buffer,
+            sample_count,
+            supported_sample_counts,
             depth_texture,
-            obj_model,
-            obj_2,
+            multisampled_target,
+            texture_bind_group_layout,
             light_uniform,
             light_buffer,
+            light_bind_group_layout,
             light_bind_group,
             light_pipeline,
+            layouts,
+            pipelines,
+            shadows,
+            environment,
+            skybox,
+            tonemap,
+            post_process,
+            ambient_light,
+            hot_reload: None,
+            assets: AssetServer::new(),
+            scene,
+            world,
+            scene_sync: SceneSync::default(),
+            systems: Vec::new(),
             relative_mouse: true,
         }
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    // `shader.wgsl` behind the point shadow lookup the adapter supports
+    fn scene_shader(point_shadows: bool, source: &str) -> wgpu::ShaderModuleDescriptor<'static> {
+        let lookup = if point_shadows {
+            resources::load_shader_source(
+                "point_shadow_lookup.wgsl",
+                include_str!("../shaders/point_shadow_lookup.wgsl"),
+            )
+        } else {
+            resources::load_shader_source(
+                "point_shadow_lookup_disabled.wgsl",
+                include_str!("../shaders/point_shadow_lookup_disabled.wgsl"),
+            )
+        };
+        wgpu::ShaderModuleDescriptor {
+            label: Some("shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(format!("{lookup}\n{source}").into()),
+        }
+    }
+
+    fn create_light_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        light_buffer: &wgpu::Buffer,
+        shadows: &Shadows,
+        environment: &Environment,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadows.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadows.map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadows.map.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&shadows.point_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&environment.irradiance.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&environment.prefiltered.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&environment.brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&environment.prefiltered.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: environment.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        match self.target {
+            RenderTarget::Window { window, .. } => Some(window),
+            RenderTarget::Offscreen(_) => None,
+        }
     }
 
     pub fn resize(&mut self, new_size: (u32, u32)) {
@@ -365,42 +700,383 @@ impl<'a> Engine<'a> {
             self.size = new_size;
             self.config.width = new_size.0;
             self.config.height = new_size.1;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            match &mut self.target {
+                RenderTarget::Window { surface, .. } => {
+                    surface.configure(&self.device, &self.config)
+                }
+                RenderTarget::Offscreen(texture) => {
+                    *texture =
+                        Texture::create_render_target(&self.device, &self.config, "render_target")
+                }
+            }
+            self.depth_texture = Texture::create_depth_texture(
+                &self.device,
+                &self.config,
+                self.sample_count,
+                "depth_texture",
+            );
+            self.multisampled_target =
+                Self::create_multisampled_target(&self.device, &self.config, self.sample_count);
+            self.tonemap.resize(&self.device, &self.config);
+            self.post_process
+                .resize(&self.device, &self.queue, &self.config);
         }
     }
 
     pub fn input(&mut self, scancodes: &Vec<Scancode>, mouse_state: &RelativeMouseState) {
-        self.camera.transform_camera(scancodes, mouse_state, self.relative_mouse);
+        if let Some((_, camera)) = self.world.query_mut::<Camera>().next() {
+            camera.transform_camera(scancodes, mouse_state, self.relative_mouse);
+        }
+    }
+
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        self.shadows.settings
+    }
+
+    /// Recreates the shadow maps and shadow pipeline with new settings.
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadows = Shadows::new(&self.device, settings, self.shadows.point_shadows);
+        self.light_bind_group = Self::create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.light_buffer,
+            &self.shadows,
+            &self.environment,
+        );
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Replaces the image-based lighting, e.g. with one from `Environment::load_hdr`.
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+        self.light_bind_group = Self::create_light_bind_group(
+            &self.device,
+            &self.light_bind_group_layout,
+            &self.light_buffer,
+            &self.shadows,
+            &self.environment,
+        );
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment.set_intensity(&self.queue, intensity);
+    }
+
+    pub fn sky(&self) -> &Sky {
+        self.skybox.sky()
+    }
+
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.skybox.set_sky(&self.device, &self.queue, sky);
+    }
+
+    /// The number of samples per pixel in the main pass, 1 until changed with
+    /// `set_sample_count`. Pipelines drawn in the `render` callback have to be
+    /// created with the same count.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.supported_sample_counts
+    }
+
+    /// Switches multisampling, recreating the scene pipelines and targets.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> anyhow::Result<()> {
+        if !self.supported_sample_counts.contains(&sample_count) {
+            anyhow::bail!(
+                "{} samples per pixel aren't supported, expected one of {:?}",
+                sample_count,
+                self.supported_sample_counts
+            );
+        }
+        // Builds the pipelines first, so a failure leaves the engine as it was
+        let render_pipeline = self.pipelines.try_get(
+            &self.device,
+            &mut self.layouts,
+            &Self::render_pipeline_key(sample_count),
+        )?;
+        let light_pipeline = self.pipelines.try_get(
+            &self.device,
+            &mut self.layouts,
+            &Self::light_pipeline_key(sample_count),
+        )?;
+
+        self.sample_count = sample_count;
+        self.render_pipeline = render_pipeline;
+        self.light_pipeline = light_pipeline;
+        self.depth_texture = Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            sample_count,
+            "depth_texture",
+        );
+        self.multisampled_target =
+            Self::create_multisampled_target(&self.device, &self.config, sample_count);
+
+        self.skybox = Skybox::new(
+            &self.device,
+            Texture::HDR_FORMAT,
+            sample_count,
+            &self.camera_bind_group_layout,
+            self.skybox.sky().clone(),
+        );
+        Ok(())
+    }
+
+    fn render_pipeline_key(sample_count: u32) -> PipelineKey {
+        PipelineKey {
+            vertex_layouts: vec![(&ModelVertex::desc()).into(), (&InstanceRaw::desc()).into()],
+            ..Self::scene_pipeline_key("shader.wgsl", &["texture", "camera", "light"], sample_count)
+        }
+    }
+
+    fn light_pipeline_key(sample_count: u32) -> PipelineKey {
+        PipelineKey {
+            vertex_layouts: vec![(&ModelVertex::desc()).into()],
+            ..Self::scene_pipeline_key("light.wgsl", &["camera", "light"], sample_count)
+        }
     }
-    
-    pub fn update(&mut self, sdl_context: &sdl2::Sdl) {
-        self.relative_mouse = sdl_context.mouse().relative_mouse_mode();
 
-        self.camera_uniform.update_view_proj(&mut self.camera);
+    /// Drawn into the main pass, with the depth test and blending `Pipeline::new` uses.
+    fn scene_pipeline_key(
+        shader: &str,
+        bind_group_layouts: &[&str],
+        sample_count: u32,
+    ) -> PipelineKey {
+        PipelineKey {
+            color_targets: vec![wgpu::ColorTargetState {
+                format: Texture::HDR_FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            sample_count,
+            ..PipelineKey::new(shader, bind_group_layouts)
+        }
+    }
+
+    fn create_multisampled_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        (sample_count > 1).then(|| {
+            Texture::create_multisampled_target(device, config, sample_count, "multisampled_target")
+        })
+    }
+
+    pub fn tonemap_settings(&self) -> TonemapSettings {
+        self.tonemap.settings()
+    }
+
+    /// Selects the tonemapping curve, manual or automatic exposure and bloom.
+    pub fn set_tonemap_settings(&mut self, settings: TonemapSettings) {
+        self.tonemap.set_settings(settings);
+    }
+
+    /// The number of lights uploaded by the last `update`.
+    pub fn light_count(&self) -> u32 {
+        self.light_uniform.count
+    }
+
+    pub fn add_system(&mut self, system: impl FnMut(&mut World) + 'static) {
+        self.systems.push(Box::new(system));
+    }
+
+    /// Development mode: loads assets and WGSL from the given directories at
+    /// runtime and reloads them whenever they change on disk.
+    ///
+    /// Only `shader.wgsl` and `light.wgsl` are reloaded. The shadow, point
+    /// shadow lookup, skybox, environment, tonemap and post-processing shaders
+    /// are only read when they are created, so changes to them apply on the
+    /// next run.
+    pub fn enable_hot_reload(
+        &mut self,
+        asset_dir: impl AsRef<Path>,
+        shader_dir: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let hot_reload = HotReload::new(asset_dir.as_ref(), shader_dir.as_ref())?;
+        vfs::global()
+            .write()
+            .unwrap()
+            .mount_front(DirectoryMount::new(&hot_reload.asset_dir));
+        self.hot_reload = Some(hot_reload);
+
+        // The embedded shaders may already be out of date
+        self.reload_shader("shader.wgsl");
+        self.reload_shader("light.wgsl");
+        Ok(())
+    }
+
+    fn reload_shader(&mut self, file_name: &str) {
+        let Some(hot_reload) = &self.hot_reload else {
+            return;
+        };
+        if !matches!(file_name, "shader.wgsl" | "light.wgsl") {
+            log::info!(
+                "{} isn't hot reloaded, restart to apply the change",
+                file_name
+            );
+            return;
+        }
+        let source = match hot_reload.load_shader(file_name) {
+            Ok(source) => source,
+            Err(e) => {
+                log::error!("{:#}", e);
+                return;
+            }
+        };
+        let (shader, key) = match file_name {
+            "shader.wgsl" => (
+                Self::scene_shader(self.shadows.point_shadows, &source),
+                Self::render_pipeline_key(self.sample_count),
+            ),
+            _ => (
+                wgpu::ShaderModuleDescriptor {
+                    label: Some(file_name),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                },
+                Self::light_pipeline_key(self.sample_count),
+            ),
+        };
+
+        // Only replaces the shader once every pipeline using it builds
+        if let Err(e) = self.pipelines.try_add_shader(
+            &self.device,
+            &mut self.layouts,
+            file_name,
+            shader,
+            std::slice::from_ref(&key),
+        ) {
+            log::error!("Keeping previous {}: {}", file_name, e);
+            return;
+        }
+        match self.pipelines.get(&self.device, &mut self.layouts, &key) {
+            Ok(pipeline) if file_name == "shader.wgsl" => self.render_pipeline = pipeline,
+            Ok(pipeline) => self.light_pipeline = pipeline,
+            Err(e) => {
+                log::error!("Keeping previous {}: {}", file_name, e);
+                return;
+            }
+        }
+        log::info!("Reloaded {}", file_name);
+    }
+
+    fn reload_asset(&mut self, file_name: &str) {
+        let replaced = pollster::block_on(self.assets.reload(
+            file_name,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+        ));
+        match replaced {
+            Ok(replaced) => {
+                for (old, new) in replaced {
+                    self.scene.replace_model(&old, new);
+                }
+                log::info!("Reloaded {}", file_name);
+            }
+            Err(e) => log::error!("Keeping previous version of {}: {:#}", file_name, e),
+        }
+    }
+
+    pub fn update(&mut self) {
+        let changes = self
+            .hot_reload
+            .as_ref()
+            .map(HotReload::changes)
+            .unwrap_or_default();
+        for change in changes {
+            match change {
+                Change::Shader(file_name) => self.reload_shader(&file_name),
+                Change::Asset(file_name) => self.reload_asset(&file_name),
+            }
+        }
+
+        for system in &mut self.systems {
+            system(&mut self.world);
+        }
+
+        if let Some((_, camera)) = self.world.query_mut::<Camera>().next() {
+            self.camera_uniform.update_view_proj(camera);
+        }
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
 
-        let old_position = na::Vector3::from(self.light_uniform.position);
-        self.light_uniform.position =
-            (na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.1) * old_position)
-                .into();
+        let lights = self
+            .world
+            .query2::<Transform, Light>()
+            .map(|(entity, transform, light)| (entity, *transform, *light))
+            .collect::<Vec<_>>();
+        let camera = self
+            .world
+            .query::<Camera>()
+            .next()
+            .map(|(_, camera)| camera);
+        if self.shadows.reserve(&self.device, &lights) {
+            self.light_bind_group = Self::create_light_bind_group(
+                &self.device,
+                &self.light_bind_group_layout,
+                &self.light_buffer,
+                &self.shadows,
+                &self.environment,
+            );
+        }
+        let shadow_indices = self.shadows.update(&self.queue, camera, &lights);
+        self.light_uniform = LightArrayUniform::new(
+            self.ambient_light,
+            lights
+                .iter()
+                .zip(shadow_indices)
+                .map(|((_, transform, light), shadow_index)| {
+                    let mut uniform = LightUniform::new(transform, light);
+                    uniform.shadow_index = shadow_index;
+                    uniform
+                }),
+        );
         self.queue.write_buffer(
             &self.light_buffer,
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+
+        self.scene_sync.sync(&self.world, &mut self.scene);
+        self.scene.update(&self.device, &self.queue);
+        self.tonemap.update(&self.queue);
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn render<T, F>(&self, data: &T, render_fn: F) -> Result<(), wgpu::SurfaceError>
+    where
+        F: for<'p> FnOnce(&mut wgpu::RenderPass<'p>, &'p Engine<'a>, &'p T),
+    {
+        let (output, view) = match &self.target {
+            RenderTarget::Window { surface, .. } => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            RenderTarget::Offscreen(texture) => (
+                None,
+                texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
 
         let mut encoder = self
             .device
@@ -408,20 +1084,24 @@ impl<'a> Engine<'a> {
                 label: Some("Render encoder"),
             });
 
+        self.shadows.render(&mut encoder, &self.scene);
+
+        let hdr_view = &self.tonemap.target().view;
+        let (color_view, resolve_target) = match &self.multisampled_target {
+            Some(target) => (target, Some(hdr_view)),
+            None => (hdr_view, None),
+        };
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
+                        load: wgpu::LoadOp::Clear(self.skybox.clear_color()),
+                        // Only the resolved samples are read afterwards
+                        store: resolve_target.is_none(),
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
@@ -434,27 +1114,95 @@ impl<'a> Engine<'a> {
                 }),
             });
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-
-            render_pass.set_pipeline(&self.light_pipeline);
-            render_pass.draw_light_model(
-                &self.obj_model,
-                &self.camera_bind_group,
-                &self.light_bind_group,
-            );
-
+            render_pass.draw_skybox(&self.skybox, &self.camera_bind_group);
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
-                &self.camera_bind_group,
-                &self.light_bind_group,
-            );
+            render_pass.draw_scene(&self.scene, &self.camera_bind_group, &self.light_bind_group);
+
+            render_fn(&mut render_pass, self, data);
+        }
+        if self.post_process.is_active() {
+            self.tonemap
+                .render(&mut encoder, &self.post_process.input().view);
+            self.post_process.render(&mut encoder, &view);
+        } else {
+            self.tonemap.render(&mut encoder, &view);
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(output) = output {
+            output.present();
+        }
 
         Ok(())
     }
+
+    /// Copies the last rendered frame of a headless engine back to the CPU.
+    pub fn read_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        let texture = match &self.target {
+            RenderTarget::Offscreen(texture) => texture,
+            RenderTarget::Window { .. } => {
+                anyhow::bail!("Frames can only be read back from a headless engine")
+            }
+        };
+
+        let (width, height) = self.size;
+        let unpadded_bytes_per_row = 4 * width;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(alignment) * alignment;
+
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame readback buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &output_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = output_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let pixels = {
+            let data = buffer_slice.get_mapped_range();
+            data.chunks(padded_bytes_per_row as usize)
+                .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+                .copied()
+                .collect::<Vec<u8>>()
+        };
+        output_buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Frame readback has the wrong size"))
+    }
 }
@@ -0,0 +1,521 @@
+use std::rc::Rc;
+
+use anyhow::bail;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    resources,
+    texture::{SamplerOptions, Texture},
+};
+
+/// Format of every environment cube map and the BRDF lookup table.
+pub const ENVIRONMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+// Every face of one cube map mip, written by the compute shaders
+const STORAGE_CUBE: wgpu::BindingType = wgpu::BindingType::StorageTexture {
+    access: wgpu::StorageTextureAccess::WriteOnly,
+    format: ENVIRONMENT_FORMAT,
+    view_dimension: wgpu::TextureViewDimension::D2Array,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct EnvironmentSettings {
+    /// Face size of the cube map converted from the equirectangular image.
+    pub cube_size: u32,
+    /// Face size of the diffuse irradiance cube map.
+    pub irradiance_size: u32,
+    /// Face size of the first prefiltered specular mip.
+    pub prefiltered_size: u32,
+    /// Prefiltered mips, going from roughness 0 to 1.
+    pub prefiltered_mips: u32,
+    /// GGX samples per prefiltered texel.
+    pub prefilter_samples: u32,
+    pub brdf_lut_size: u32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        EnvironmentSettings {
+            cube_size: 512,
+            irradiance_size: 32,
+            prefiltered_size: 128,
+            prefiltered_mips: 5,
+            prefilter_samples: 512,
+            brdf_lut_size: 256,
+        }
+    }
+}
+
+impl EnvironmentSettings {
+    /// Fails if any size or count is 0, which no map can be created with.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("cube_size", self.cube_size),
+            ("irradiance_size", self.irradiance_size),
+            ("prefiltered_size", self.prefiltered_size),
+            ("prefiltered_mips", self.prefiltered_mips),
+            ("prefilter_samples", self.prefilter_samples),
+            ("brdf_lut_size", self.brdf_lut_size),
+        ] {
+            if value == 0 {
+                bail!("Environment {name} must be at least 1");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EnvironmentUniform {
+    pub intensity: f32,
+    /// Mip of the prefiltered map used for roughness 1.
+    pub max_lod: f32,
+    /// 0 falls back to the constant ambient term in `shader.wgsl`.
+    pub enabled: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterParams {
+    roughness: f32,
+    sample_count: u32,
+    _padding: [u32; 2],
+}
+
+/// Image-based lighting precomputed from an HDR environment.
+pub struct Environment {
+    /// The environment itself with a full mip chain, also usable as a skybox.
+    pub cubemap: Rc<Texture>,
+    pub irradiance: Texture,
+    pub prefiltered: Texture,
+    pub brdf_lut: Texture,
+    pub uniform: EnvironmentUniform,
+    pub uniform_buffer: wgpu::Buffer,
+}
+
+impl Environment {
+    /// Black placeholder maps with image-based lighting switched off.
+    pub fn empty(device: &wgpu::Device) -> Self {
+        let uniform = EnvironmentUniform {
+            intensity: 1.0,
+            max_lod: 0.0,
+            enabled: 0,
+            _padding: 0,
+        };
+        Environment {
+            cubemap: Rc::new(create_cube(device, 1, 1, "environment_cubemap")),
+            irradiance: create_cube(device, 1, 1, "environment_irradiance"),
+            prefiltered: create_cube(device, 1, 1, "environment_prefiltered"),
+            brdf_lut: create_brdf_lut(device, 1),
+            uniform_buffer: create_uniform_buffer(device, uniform),
+            uniform,
+        }
+    }
+
+    pub async fn load_hdr(
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: EnvironmentSettings,
+    ) -> anyhow::Result<Self> {
+        let image = resources::load_hdr(file_name).await?;
+        Self::from_equirect(device, queue, &image, settings)
+    }
+
+    /// Converts an equirectangular image to a cube map and filters it on the GPU.
+    /// Fails if `settings` don't pass `EnvironmentSettings::validate`.
+    pub fn from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::Rgba32FImage,
+        settings: EnvironmentSettings,
+    ) -> anyhow::Result<Self> {
+        settings.validate()?;
+        let cubemap = equirect_to_cubemap(device, queue, image, settings.cube_size)?;
+        let prefiltered_mips = settings
+            .prefiltered_mips
+            .clamp(1, settings.prefiltered_size.ilog2() + 1);
+        let irradiance = create_cube(
+            device,
+            settings.irradiance_size,
+            1,
+            "environment_irradiance",
+        );
+        let prefiltered = create_cube(
+            device,
+            settings.prefiltered_size,
+            prefiltered_mips,
+            "environment_prefiltered",
+        );
+        let brdf_lut = create_brdf_lut(device, settings.brdf_lut_size);
+
+        let shader = device.create_shader_module(resources::load_shader(
+            "environment.wgsl",
+            include_str!("../shaders/environment.wgsl"),
+        ));
+        let filter_layout = create_bind_group_layout(
+            device,
+            &[
+                wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                STORAGE_CUBE,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            ],
+        );
+        let brdf_lut_layout = create_bind_group_layout(
+            device,
+            &[wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: ENVIRONMENT_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }],
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment encoder"),
+        });
+
+        let filter = |encoder: &mut wgpu::CommandEncoder,
+                      pipeline: &wgpu::ComputePipeline,
+                      texture: &Texture,
+                      mip: u32,
+                      roughness: f32| {
+            let params = FilterParams {
+                roughness,
+                sample_count: settings.prefilter_samples,
+                _padding: [0; 2],
+            };
+            let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Environment filter params"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let target = mip_view(texture, mip);
+            let bind_group = create_bind_group(
+                device,
+                &filter_layout,
+                &[
+                    wgpu::BindingResource::TextureView(&cubemap.view),
+                    wgpu::BindingResource::Sampler(&cubemap.sampler),
+                    wgpu::BindingResource::TextureView(&target),
+                    params_buffer.as_entire_binding(),
+                ],
+            );
+            let size = (texture.texture.width() >> mip).max(1);
+            dispatch(encoder, pipeline, &bind_group, size, 6);
+        };
+
+        let pipeline = create_compute_pipeline(device, &shader, "irradiance", &filter_layout);
+        filter(&mut encoder, &pipeline, &irradiance, 0, 0.0);
+
+        let pipeline = create_compute_pipeline(device, &shader, "prefilter", &filter_layout);
+        for mip in 0..prefiltered_mips {
+            let roughness = mip as f32 / (prefiltered_mips - 1).max(1) as f32;
+            filter(&mut encoder, &pipeline, &prefiltered, mip, roughness);
+        }
+
+        let pipeline = create_compute_pipeline(device, &shader, "brdf_lut", &brdf_lut_layout);
+        let bind_group = create_bind_group(
+            device,
+            &brdf_lut_layout,
+            &[wgpu::BindingResource::TextureView(&brdf_lut.view)],
+        );
+        dispatch(
+            &mut encoder,
+            &pipeline,
+            &bind_group,
+            settings.brdf_lut_size,
+            1,
+        );
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let uniform = EnvironmentUniform {
+            intensity: 1.0,
+            max_lod: (prefiltered_mips - 1) as f32,
+            enabled: 1,
+            _padding: 0,
+        };
+        Ok(Environment {
+            cubemap: Rc::new(cubemap),
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            uniform_buffer: create_uniform_buffer(device, uniform),
+            uniform,
+        })
+    }
+
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.uniform.intensity = intensity;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+}
+
+/// Converts an equirectangular image to an `ENVIRONMENT_FORMAT` cube map with
+/// `size` texel faces and a full mip chain.
+pub fn equirect_to_cubemap(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    image: &image::Rgba32FImage,
+    size: u32,
+) -> anyhow::Result<Texture> {
+    if size == 0 {
+        bail!("Cube map faces must be at least 1 texel wide");
+    }
+    if image.width() == 0 || image.height() == 0 {
+        bail!("Equirectangular image is empty");
+    }
+    let equirect = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("environment_equirect"),
+            size: wgpu::Extent3d {
+                width: image.width(),
+                height: image.height(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        bytemuck::cast_slice(image.as_raw()),
+    );
+    let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let cube_mips = size.ilog2() + 1;
+    let cubemap = create_cube(device, size, cube_mips, "environment_cubemap");
+
+    let shader = device.create_shader_module(resources::load_shader(
+        "environment.wgsl",
+        include_str!("../shaders/environment.wgsl"),
+    ));
+    let equirect_layout = create_bind_group_layout(
+        device,
+        &[
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            STORAGE_CUBE,
+        ],
+    );
+    let downsample_layout = create_bind_group_layout(
+        device,
+        &[
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            STORAGE_CUBE,
+        ],
+    );
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Equirectangular encoder"),
+    });
+
+    let pipeline = create_compute_pipeline(device, &shader, "equirect_to_cube", &equirect_layout);
+    let target = mip_view(&cubemap, 0);
+    let bind_group = create_bind_group(
+        device,
+        &equirect_layout,
+        &[
+            wgpu::BindingResource::TextureView(&equirect_view),
+            wgpu::BindingResource::TextureView(&target),
+        ],
+    );
+    dispatch(&mut encoder, &pipeline, &bind_group, size, 6);
+
+    // Environment filtering reads blurrier mips for sparse samples
+    let pipeline = create_compute_pipeline(device, &shader, "downsample", &downsample_layout);
+    for mip in 1..cube_mips {
+        let source = mip_view(&cubemap, mip - 1);
+        let target = mip_view(&cubemap, mip);
+        let bind_group = create_bind_group(
+            device,
+            &downsample_layout,
+            &[
+                wgpu::BindingResource::TextureView(&source),
+                wgpu::BindingResource::TextureView(&target),
+            ],
+        );
+        dispatch(
+            &mut encoder,
+            &pipeline,
+            &bind_group,
+            (size >> mip).max(1),
+            6,
+        );
+    }
+
+    queue.submit(std::iter::once(encoder.finish()));
+    Ok(cubemap)
+}
+
+fn create_cube(device: &wgpu::Device, size: u32, mip_level_count: u32, label: &str) -> Texture {
+    Texture::create_cube(
+        device,
+        size,
+        mip_level_count,
+        ENVIRONMENT_FORMAT,
+        wgpu::TextureUsages::STORAGE_BINDING,
+        label,
+    )
+}
+
+fn create_brdf_lut(device: &wgpu::Device, size: u32) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("environment_brdf_lut"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ENVIRONMENT_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = Rc::new(SamplerOptions::default().create_sampler(device));
+    Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
+
+fn create_uniform_buffer(device: &wgpu::Device, uniform: EnvironmentUniform) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment uniform buffer"),
+        contents: bytemuck::cast_slice(&[uniform]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// All six faces of one mip, for writing from compute shaders
+fn mip_view(texture: &Texture, mip: u32) -> wgpu::TextureView {
+    texture.texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+fn create_bind_group_layout(
+    device: &wgpu::Device,
+    types: &[wgpu::BindingType],
+) -> wgpu::BindGroupLayout {
+    let entries = types
+        .iter()
+        .enumerate()
+        .map(|(binding, ty)| wgpu::BindGroupLayoutEntry {
+            binding: binding as u32,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: *ty,
+            count: None,
+        })
+        .collect::<Vec<_>>();
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &entries,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    resources: &[wgpu::BindingResource],
+) -> wgpu::BindGroup {
+    let entries = resources
+        .iter()
+        .enumerate()
+        .map(|(binding, resource)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource: resource.clone(),
+        })
+        .collect::<Vec<_>>();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &entries,
+    })
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    layout: &wgpu::BindGroupLayout,
+) -> wgpu::ComputePipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(entry_point),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&pipeline_layout),
+        module: shader,
+        entry_point,
+    })
+}
+
+fn dispatch(
+    encoder: &mut wgpu::CommandEncoder,
+    pipeline: &wgpu::ComputePipeline,
+    bind_group: &wgpu::BindGroup,
+    size: u32,
+    layers: u32,
+) {
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    let workgroups = size.div_ceil(WORKGROUP_SIZE);
+    pass.dispatch_workgroups(workgroups, workgroups, layers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_reject_empty_maps() {
+        assert!(EnvironmentSettings::default().validate().is_ok());
+        let settings = EnvironmentSettings {
+            prefiltered_size: 0,
+            ..Default::default()
+        };
+        let error = settings.validate().unwrap_err().to_string();
+        assert!(error.contains("prefiltered_size"), "{error}");
+        let settings = EnvironmentSettings {
+            cube_size: 0,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
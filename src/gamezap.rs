@@ -7,8 +7,21 @@ use sdl2::{
 
 use crate::engine::Engine;
 
-pub async fn run() {
-    env_logger::init();
+/// Opens a window and drives the engine loop.
+///
+/// `setup` runs once after the engine is created and returns the game state,
+/// `update` runs every frame before the uniforms are uploaded, and `render`
+/// records draw calls into the main render pass.
+///
+/// Installs `env_logger` unless the host already set up a logger.
+pub async fn run<T, S, U, R>(setup: S, mut update: U, mut render: R)
+where
+    S: FnOnce(&mut Engine) -> T,
+    U: FnMut(&mut Engine, &mut T),
+    R: for<'p, 'w> FnMut(&mut wgpu::RenderPass<'p>, &'p Engine<'w>, &'p T),
+{
+    // Hosts that installed their own logger keep it
+    let _ = env_logger::try_init();
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -23,6 +36,7 @@ pub async fn run() {
     sdl_context.mouse().set_relative_mouse_mode(true);
 
     let mut engine = Engine::new(&window).await;
+    let mut state = setup(&mut engine);
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
@@ -34,8 +48,13 @@ pub async fn run() {
                     win_event: WindowEvent::Resized(width, height),
                     ..
                 } => engine.resize((width as u32, height as u32)),
-                Event::KeyDown {keycode: Some(Keycode::Escape),..} => {
-                    sdl_context.mouse().set_relative_mouse_mode(!engine.relative_mouse);
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    sdl_context
+                        .mouse()
+                        .set_relative_mouse_mode(!engine.relative_mouse);
                 }
                 _ => {}
             }
@@ -46,9 +65,13 @@ pub async fn run() {
             .collect::<Vec<_>>();
         let mouse_state = event_pump.relative_mouse_state();
         engine.input(&scancodes, &mouse_state);
-        engine.update(&sdl_context);
+        engine.relative_mouse = sdl_context.mouse().relative_mouse_mode();
+        update(&mut engine, &mut state);
+        engine.update();
         engine.frame_number += 1;
-        match engine.render() {
+        match engine.render(&state, |render_pass, engine, state| {
+            render(render_pass, engine, state)
+        }) {
             Ok(_) => {}
             Err(wgpu::SurfaceError::Lost) => engine.resize(engine.size),
             Err(wgpu::SurfaceError::OutOfMemory) => break 'running,
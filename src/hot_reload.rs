@@ -0,0 +1,84 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use anyhow::Context;
+use notify::Watcher;
+
+pub enum Change {
+    Shader(String),
+    Asset(String),
+}
+
+/// Watches the runtime asset and shader directories for edited files.
+pub struct HotReload {
+    pub asset_dir: PathBuf,
+    pub shader_dir: PathBuf,
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<PathBuf>,
+}
+
+impl HotReload {
+    pub fn new(asset_dir: &Path, shader_dir: &Path) -> anyhow::Result<Self> {
+        let asset_dir = asset_dir
+            .canonicalize()
+            .with_context(|| format!("Asset directory {:?} not found", asset_dir))?;
+        let shader_dir = shader_dir
+            .canonicalize()
+            .with_context(|| format!("Shader directory {:?} not found", shader_dir))?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("File watcher error: {}", e),
+            })?;
+        watcher.watch(&asset_dir, notify::RecursiveMode::Recursive)?;
+        watcher.watch(&shader_dir, notify::RecursiveMode::Recursive)?;
+
+        Ok(HotReload {
+            asset_dir,
+            shader_dir,
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Drains the files changed since the last call. Editors often write a file
+    /// several times per save, so each path is only reported once.
+    pub fn changes(&self) -> Vec<Change> {
+        let paths = self.events.try_iter().collect::<HashSet<_>>();
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                if let Ok(relative) = path.strip_prefix(&self.shader_dir) {
+                    Some(Change::Shader(to_key(relative)))
+                } else if let Ok(relative) = path.strip_prefix(&self.asset_dir) {
+                    Some(Change::Asset(to_key(relative)))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn load_shader(&self, file_name: &str) -> anyhow::Result<String> {
+        let path = self.shader_dir.join(file_name);
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))
+    }
+}
+
+// Asset keys use forward slashes like the paths written in OBJ and glTF files
+fn to_key(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
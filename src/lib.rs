@@ -0,0 +1,28 @@
+pub mod archive;
+pub mod assets;
+mod astc;
+mod bptc;
+pub mod camera;
+pub mod compressed;
+pub mod ecs;
+pub mod engine;
+pub mod environment;
+mod gamezap;
+pub mod hot_reload;
+pub mod light;
+pub mod model;
+pub mod pack;
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod postprocess;
+pub mod resources;
+pub mod scene;
+pub mod shadow;
+pub mod skybox;
+pub mod texture;
+pub mod tonemap;
+pub mod utils;
+pub mod vfs;
+
+pub use engine::Engine;
+pub use gamezap::run;
@@ -1,27 +1,86 @@
 use std::ops::Range;
 
-use crate::model::{Mesh, Model};
+use bytemuck::Zeroable;
+use nalgebra as na;
+
+use crate::{
+    ecs::{Light, LightKind},
+    model::{Mesh, Model},
+    scene::Transform,
+};
+
+/// Must match `MAX_LIGHTS` in the shaders.
+pub const MAX_LIGHTS: usize = 16;
+
+const POINT: u32 = 0;
+const DIRECTIONAL: u32 = 1;
+const SPOT: u32 = 2;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
     pub position: [f32; 3],
-    _padding: u32,
+    pub kind: u32,
+    pub direction: [f32; 3],
+    pub radius: f32,
     pub color: [f32; 3],
-    _padding2: u32,
+    pub intensity: f32,
+    pub inner_cos: f32,
+    pub outer_cos: f32,
+    /// First shadow map layer used by this light, or -1 without shadows.
+    pub shadow_index: i32,
+    _padding: u32,
 }
 
 impl LightUniform {
-    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+    pub fn new(transform: &Transform, light: &Light) -> Self {
+        let direction = transform.rotation * -na::Vector3::z();
+        let (kind, radius, inner_angle, outer_angle) = match light.kind {
+            LightKind::Point { radius } => (POINT, radius, 0.0, 0.0),
+            LightKind::Directional => (DIRECTIONAL, 0.0, 0.0, 0.0),
+            LightKind::Spot {
+                radius,
+                inner_angle,
+                outer_angle,
+            } => (SPOT, radius, inner_angle, outer_angle),
+        };
         LightUniform {
-            position,
+            position: transform.translation.into(),
+            kind,
+            direction: direction.into(),
+            radius,
+            color: light.color,
+            intensity: light.intensity,
+            inner_cos: inner_angle.cos(),
+            outer_cos: outer_angle.cos(),
+            shadow_index: -1,
             _padding: 0,
-            color,
-            _padding2: 0,
         }
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightArrayUniform {
+    pub lights: [LightUniform; MAX_LIGHTS],
+    /// Added once to every surface without an environment map, however many lights there are.
+    pub ambient: [f32; 3],
+    pub count: u32,
+}
+
+impl LightArrayUniform {
+    /// Lights past `MAX_LIGHTS` are ignored.
+    pub fn new(ambient: [f32; 3], lights: impl IntoIterator<Item = LightUniform>) -> Self {
+        let mut uniform = Self::zeroed();
+        uniform.ambient = ambient;
+        for (slot, light) in uniform.lights.iter_mut().zip(lights) {
+            *slot = light;
+            uniform.count += 1;
+        }
+        uniform
+    }
+}
+
 // model.rs
 pub trait DrawLight<'a> {
     fn draw_light_mesh(
@@ -1,6 +1,8 @@
-use std::ops::Range;
+use std::{ops::Range, rc::Rc};
 
-use crate::texture::Texture;
+use wgpu::util::DeviceExt;
+
+use crate::texture::{SamplerCache, SamplerOptions, Texture, TextureFilter, TextureKind};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -128,10 +130,130 @@ pub struct Mesh {
     pub material: usize,
 }
 
+/// How a material responds to light, picked per material in `shader.wgsl`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadingModel {
+    /// Blinn-Phong with `specular_color` and `shininess`, for stylised scenes.
+    Phong = 0,
+    /// Cook-Torrance metallic-roughness, using the base color, metallic,
+    /// roughness, occlusion and emissive maps and factors.
+    Pbr = 1,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    /// The Phong diffuse color, or the PBR base color factor.
+    pub diffuse_color: [f32; 3],
+    pub alpha: f32,
+    pub specular_color: [f32; 3],
+    pub shininess: f32,
+    pub emissive: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    pub normal_scale: f32,
+    /// A `ShadingModel` as `u32`.
+    pub shading_model: u32,
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        MaterialUniform {
+            diffuse_color: [1.0; 3],
+            alpha: 1.0,
+            specular_color: [1.0; 3],
+            shininess: 32.0,
+            emissive: [0.0; 3],
+            metallic: 0.0,
+            roughness: 1.0,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+            shading_model: ShadingModel::Phong as u32,
+        }
+    }
+}
+
+/// A texture and how a material samples it. Textures are shared between
+/// materials, so the sampler is only paired with them in the bind group.
+#[derive(Clone)]
+pub struct MaterialMap {
+    pub texture: Rc<Texture>,
+    pub sampler: SamplerOptions,
+}
+
+impl MaterialMap {
+    pub fn new(texture: Rc<Texture>, sampler: SamplerOptions) -> Self {
+        MaterialMap { texture, sampler }
+    }
+}
+
+/// Texture maps of a material. Factors in `MaterialUniform` scale each map,
+/// so the defaults are white, apart from the flat normal map.
+#[derive(Clone)]
+pub struct MaterialTextures {
+    pub diffuse: MaterialMap,
+    pub normal: MaterialMap,
+    /// Roughness in the green channel and metallic in the blue channel, as in glTF.
+    pub metallic_roughness: MaterialMap,
+    /// Ambient occlusion in the red channel.
+    pub occlusion: MaterialMap,
+    pub emissive: MaterialMap,
+}
+
+impl MaterialTextures {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        samplers: &mut SamplerCache,
+    ) -> anyhow::Result<Self> {
+        let options = SamplerOptions::clamp(TextureFilter::Nearest);
+        let sampler = samplers.get(device, options);
+        let white = MaterialMap::new(
+            Texture::default_diffuse(device, queue, sampler.clone())?.into(),
+            options,
+        );
+        let linear_white = MaterialMap::new(
+            Texture::from_color(
+                device,
+                queue,
+                [255, 255, 255, 255],
+                "default_linear",
+                TextureKind::Linear,
+                sampler.clone(),
+            )?
+            .into(),
+            options,
+        );
+        Ok(MaterialTextures {
+            diffuse: white.clone(),
+            normal: MaterialMap::new(
+                Texture::default_normal(device, queue, sampler)?.into(),
+                options,
+            ),
+            metallic_roughness: linear_white.clone(),
+            occlusion: linear_white,
+            emissive: white,
+        })
+    }
+
+    pub fn all(&self) -> [&MaterialMap; 5] {
+        [
+            &self.diffuse,
+            &self.normal,
+            &self.metallic_roughness,
+            &self.occlusion,
+            &self.emissive,
+        ]
+    }
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Texture,
-    pub normal_texture: Texture,
+    pub textures: MaterialTextures,
+    pub uniform: MaterialUniform,
+    pub uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -139,38 +261,67 @@ impl Material {
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: Texture,
-        normal_texture: Texture,
+        textures: MaterialTextures,
+        uniform: MaterialUniform,
         layout: &wgpu::BindGroupLayout,
+        samplers: &mut SamplerCache,
     ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material buffer", name)),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Each map is a texture followed by its sampler from `samplers`, the uniform
+        // stays at binding 4
+        let maps = textures.all();
+        let map_samplers = maps.map(|map| samplers.get(device, map.sampler));
+        let mut entries = Vec::with_capacity(11);
+        for ((binding, map), sampler) in [0, 2, 5, 7, 9].into_iter().zip(maps).zip(&map_samplers) {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&map.texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: 4,
+            resource: uniform_buffer.as_entire_binding(),
+        });
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(name),
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
+            entries: &entries,
         });
 
         Material {
             name: String::from(name),
-            diffuse_texture,
-            normal_texture,
+            textures,
+            uniform,
+            uniform_buffer,
             bind_group,
         }
     }
+
+    /// A white, flat-normal material for meshes that reference no material,
+    /// given the default textures, e.g. from `AssetServer::default_material_textures`.
+    pub fn fallback(
+        device: &wgpu::Device,
+        textures: MaterialTextures,
+        layout: &wgpu::BindGroupLayout,
+        samplers: &mut SamplerCache,
+    ) -> Self {
+        Material::new(
+            device,
+            "default",
+            textures,
+            MaterialUniform::default(),
+            layout,
+            samplers,
+        )
+    }
 }
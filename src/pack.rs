@@ -0,0 +1,159 @@
+//! The packed asset archive format and its writer.
+//!
+//! Layout, all integers little endian:
+//! `MAGIC`, `VERSION: u32`, `entry_count: u32`, then for each entry
+//! `path_len: u16`, `path`, `offset: u64`, `stored_size: u64`, `size: u64`,
+//! `compression: u8`, `hash: [u8; 32]`, followed by the entry data.
+//! `hash` is the BLAKE3 hash of the uncompressed data.
+//!
+//! This file is also compiled into `build.rs`, so it only depends on std,
+//! `anyhow`, `flate2` and `blake3`.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+
+pub const MAGIC: [u8; 4] = *b"GZPK";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+impl Compression {
+    pub fn from_u8(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            _ => anyhow::bail!("Unknown compression {}", value),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+
+    pub fn decompress(self, data: Vec<u8>, size: u64) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Deflate => {
+                // `size` comes from the archive index, so it only bounds how
+                // much is decompressed rather than being allocated up front
+                let mut decompressed = Vec::with_capacity(size.min(1 << 20) as usize);
+                flate2::read::DeflateDecoder::new(data.as_slice())
+                    .take(size.saturating_add(1))
+                    .read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+pub struct ArchiveWriter {
+    files: Vec<(String, Vec<u8>)>,
+    pub compress: bool,
+}
+
+impl ArchiveWriter {
+    pub fn new(compress: bool) -> Self {
+        ArchiveWriter {
+            files: Vec::new(),
+            compress,
+        }
+    }
+
+    pub fn add(&mut self, path: &str, data: Vec<u8>) {
+        self.files.push((path.to_string(), data));
+    }
+
+    /// Adds every file below `dir`, keyed by `prefix/<path relative to dir>`.
+    pub fn add_dir(&mut self, dir: &Path, prefix: &str) -> anyhow::Result<()> {
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(current) = stack.pop() {
+            for entry in std::fs::read_dir(&current)
+                .with_context(|| format!("Failed to read directory {:?}", current))?
+            {
+                let path = entry?.path();
+                if path.is_dir() {
+                    stack.push(path);
+                    continue;
+                }
+
+                let relative = path
+                    .strip_prefix(dir)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let key = if prefix.is_empty() {
+                    relative
+                } else {
+                    format!("{}/{}", prefix, relative)
+                };
+                let data =
+                    std::fs::read(&path).with_context(|| format!("Failed to read {:?}", path))?;
+                self.add(&key, data);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(mut self, writer: &mut impl Write) -> anyhow::Result<()> {
+        // Sorted so the same inputs always produce the same archive
+        self.files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut blobs = Vec::new();
+        for (path, data) in &self.files {
+            let hash = *blake3::hash(data).as_bytes();
+            let compressed = if self.compress {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                Some(encoder.finish()?)
+            } else {
+                None
+            };
+            // Already compressed formats like JPEG are stored as is
+            let (compression, stored) = match compressed {
+                Some(compressed) if compressed.len() < data.len() => {
+                    (Compression::Deflate, compressed)
+                }
+                _ => (Compression::None, data.clone()),
+            };
+            blobs.push((path, data.len() as u64, compression, hash, stored));
+        }
+
+        let index_size = blobs
+            .iter()
+            .map(|(path, ..)| 2 + path.len() as u64 + 8 + 8 + 8 + 1 + 32)
+            .sum::<u64>();
+        let mut offset = MAGIC.len() as u64 + 4 + 4 + index_size;
+
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(blobs.len() as u32).to_le_bytes())?;
+        for (path, size, compression, hash, stored) in &blobs {
+            let path_len = u16::try_from(path.len()).context("Archive path too long")?;
+            writer.write_all(&path_len.to_le_bytes())?;
+            writer.write_all(path.as_bytes())?;
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&(stored.len() as u64).to_le_bytes())?;
+            writer.write_all(&size.to_le_bytes())?;
+            writer.write_all(&[compression.to_u8()])?;
+            writer.write_all(hash)?;
+            offset += stored.len() as u64;
+        }
+        for (.., stored) in &blobs {
+            writer.write_all(stored)?;
+        }
+        Ok(())
+    }
+}
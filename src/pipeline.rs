@@ -8,28 +8,113 @@ impl Pipeline {
         layout: &wgpu::PipelineLayout,
         color_format: wgpu::TextureFormat,
         depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
         vertex_layouts: &[wgpu::VertexBufferLayout],
         shader: wgpu::ShaderModuleDescriptor,
     ) -> Self {
         let shader = device.create_shader_module(shader);
+        let mut builder = PipelineBuilder::new(layout, &shader)
+            .vertex_layouts(vertex_layouts)
+            .color_target(color_format, BlendMode::Replace)
+            .sample_count(sample_count);
+        if let Some(format) = depth_format {
+            builder = builder.depth(format);
+        }
+        builder.build(device)
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: vertex_layouts,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: color_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
+    /// Like `new`, but reports shader compilation and validation errors instead of panicking.
+    pub fn try_new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        shader: wgpu::ShaderModuleDescriptor,
+    ) -> anyhow::Result<Self> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = Self::new(
+            device,
+            layout,
+            color_format,
+            depth_format,
+            sample_count,
+            vertex_layouts,
+            shader,
+        );
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(e) => Err(anyhow::anyhow!("{}", e)),
+            None => Ok(pipeline),
+        }
+    }
+}
+
+/// How a color target combines what a fragment shader writes with what is already there.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    #[default]
+    Replace,
+    /// Blends by the source alpha, for colors that aren't premultiplied.
+    Alpha,
+    /// Adds the source to the target, e.g. for glows and particles.
+    Additive,
+    /// Blends colors that are already multiplied by their alpha.
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn blend_state(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => {
+                let add = wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                };
+                wgpu::BlendState {
+                    color: add,
+                    alpha: add,
+                }
+            }
+            BlendMode::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        }
+    }
+}
+
+/// Configures a render pipeline, starting from the state `Pipeline::new` uses:
+/// `vs_main` and `fs_main` entry points, triangle lists with back faces
+/// culled, and when a depth format is given, `Less` depth testing with writes.
+pub struct PipelineBuilder<'a> {
+    label: &'a str,
+    layout: &'a wgpu::PipelineLayout,
+    shader: &'a wgpu::ShaderModule,
+    vertex_entry_point: &'a str,
+    // None for depth-only pipelines
+    fragment_entry_point: Option<&'a str>,
+    vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    color_targets: Vec<Option<wgpu::ColorTargetState>>,
+    primitive: wgpu::PrimitiveState,
+    depth_format: Option<wgpu::TextureFormat>,
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+    stencil: wgpu::StencilState,
+    depth_bias: wgpu::DepthBiasState,
+    sample_count: u32,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn new(layout: &'a wgpu::PipelineLayout, shader: &'a wgpu::ShaderModule) -> Self {
+        PipelineBuilder {
+            label: "Render pipeline",
+            layout,
+            shader,
+            vertex_entry_point: "vs_main",
+            fragment_entry_point: Some("fs_main"),
+            vertex_layouts: &[],
+            color_targets: Vec::new(),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
@@ -39,21 +124,183 @@ impl Pipeline {
                 polygon_mode: wgpu::PolygonMode::Fill,
                 conservative: false,
             },
-            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            depth_format: None,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            depth_bias: wgpu::DepthBiasState::default(),
+            sample_count: 1,
+        }
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub fn vertex_entry_point(mut self, entry_point: &'a str) -> Self {
+        self.vertex_entry_point = entry_point;
+        self
+    }
+
+    pub fn fragment_entry_point(mut self, entry_point: &'a str) -> Self {
+        self.fragment_entry_point = Some(entry_point);
+        self
+    }
+
+    /// Leaves out the fragment stage, e.g. for shadow maps that only need depth.
+    pub fn without_fragment(mut self) -> Self {
+        self.fragment_entry_point = None;
+        self
+    }
+
+    pub fn vertex_layouts(mut self, layouts: &'a [wgpu::VertexBufferLayout<'a>]) -> Self {
+        self.vertex_layouts = layouts;
+        self
+    }
+
+    /// Adds a color target at the next `@location`, so calling this several
+    /// times renders to multiple targets.
+    pub fn color_target(self, format: wgpu::TextureFormat, blend: BlendMode) -> Self {
+        self.color_target_state(wgpu::ColorTargetState {
+            format,
+            blend: Some(blend.blend_state()),
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    }
+
+    /// Like `color_target`, for custom blending or write masks.
+    pub fn color_target_state(mut self, target: wgpu::ColorTargetState) -> Self {
+        self.color_targets.push(Some(target));
+        self
+    }
+
+    /// Replaces all the primitive state at once.
+    pub fn primitive(mut self, primitive: wgpu::PrimitiveState) -> Self {
+        self.primitive = primitive;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.primitive.front_face = front_face;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    /// `Line` and `Point` fall back to `Fill` on devices without the matching feature.
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Tests against and writes to a depth buffer of this format.
+    pub fn depth(mut self, format: wgpu::TextureFormat) -> Self {
+        self.depth_format = Some(format);
+        self
+    }
+
+    /// Replaces all the depth and stencil state at once.
+    pub fn depth_stencil(mut self, depth_stencil: wgpu::DepthStencilState) -> Self {
+        self.depth_format = Some(depth_stencil.format);
+        self.depth_write_enabled = depth_stencil.depth_write_enabled;
+        self.depth_compare = depth_stencil.depth_compare;
+        self.stencil = depth_stencil.stencil;
+        self.depth_bias = depth_stencil.bias;
+        self
+    }
+
+    pub fn depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        self.depth_compare = compare;
+        self
+    }
+
+    pub fn depth_write(mut self, enabled: bool) -> Self {
+        self.depth_write_enabled = enabled;
+        self
+    }
+
+    /// Needs a depth format with a stencil aspect, see `depth`.
+    pub fn stencil(mut self, stencil: wgpu::StencilState) -> Self {
+        self.stencil = stencil;
+        self
+    }
+
+    pub fn depth_bias(mut self, bias: wgpu::DepthBiasState) -> Self {
+        self.depth_bias = bias;
+        self
+    }
+
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.sample_count = count;
+        self
+    }
+
+    pub fn build(self, device: &wgpu::Device) -> Pipeline {
+        let mut primitive = self.primitive;
+        let required_feature = match primitive.polygon_mode {
+            wgpu::PolygonMode::Fill => wgpu::Features::empty(),
+            wgpu::PolygonMode::Line => wgpu::Features::POLYGON_MODE_LINE,
+            wgpu::PolygonMode::Point => wgpu::Features::POLYGON_MODE_POINT,
+        };
+        if !device.features().contains(required_feature) {
+            log::warn!(
+                "{}: {:?} polygons aren't supported, filling them instead",
+                self.label,
+                primitive.polygon_mode
+            );
+            primitive.polygon_mode = wgpu::PolygonMode::Fill;
+        }
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(self.layout),
+            vertex: wgpu::VertexState {
+                module: self.shader,
+                entry_point: self.vertex_entry_point,
+                buffers: self.vertex_layouts,
+            },
+            fragment: self
+                .fragment_entry_point
+                .map(|entry_point| wgpu::FragmentState {
+                    module: self.shader,
+                    entry_point,
+                    targets: &self.color_targets,
+                }),
+            primitive,
+            depth_stencil: self.depth_format.map(|format| wgpu::DepthStencilState {
                 format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+                depth_write_enabled: self.depth_write_enabled,
+                depth_compare: self.depth_compare,
+                stencil: self.stencil,
+                bias: self.depth_bias,
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: self.sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
         });
 
-        Pipeline { pipeline: render_pipeline }
+        Pipeline { pipeline }
+    }
+
+    /// Like `build`, but reports validation errors instead of panicking.
+    pub fn try_build(self, device: &wgpu::Device) -> anyhow::Result<Pipeline> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = self.build(device);
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(e) => Err(anyhow::anyhow!("{}", e)),
+            None => Ok(pipeline),
+        }
     }
 }
@@ -0,0 +1,468 @@
+use std::{collections::HashMap, path::Path, rc::Rc};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline::PipelineBuilder;
+
+/// Bind group layouts shared by name, so materials can build pipelines
+/// against the engine's texture, camera and light layouts.
+#[derive(Default)]
+pub struct LayoutRegistry {
+    bind_group_layouts: HashMap<String, Rc<wgpu::BindGroupLayout>>,
+    // Layouts with the same entries are only created once, whatever they are called
+    by_entries: HashMap<Vec<wgpu::BindGroupLayoutEntry>, Rc<wgpu::BindGroupLayout>>,
+    pipeline_layouts: HashMap<Vec<String>, Rc<wgpu::PipelineLayout>>,
+    // Bumped whenever a name is registered again with different entries, so
+    // pipeline caches can tell which of their pipelines are out of date
+    generation: u64,
+    replaced: HashMap<String, u64>,
+}
+
+impl LayoutRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a bind group layout under `name`, reusing an existing layout
+    /// with the same entries. Registering a name again with different entries
+    /// drops the pipelines built against it from every `PipelineCache` the
+    /// next time they are used.
+    pub fn register(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> Rc<wgpu::BindGroupLayout> {
+        let layout = self
+            .by_entries
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                Rc::new(
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some(&format!("{name}_bind_group_layout")),
+                        entries,
+                    }),
+                )
+            })
+            .clone();
+        let previous = self
+            .bind_group_layouts
+            .insert(name.to_string(), layout.clone());
+        if previous.is_some_and(|previous| !Rc::ptr_eq(&previous, &layout)) {
+            self.pipeline_layouts
+                .retain(|names, _| !names.iter().any(|n| n == name));
+            self.generation += 1;
+            self.replaced.insert(name.to_string(), self.generation);
+        }
+        layout
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<wgpu::BindGroupLayout>> {
+        self.bind_group_layouts.get(name).cloned()
+    }
+
+    /// The names registered again with different entries after `generation`.
+    fn replaced_since(&self, generation: u64) -> impl Iterator<Item = &str> {
+        self.replaced
+            .iter()
+            .filter(move |(_, &replaced)| replaced > generation)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// A pipeline layout with the named bind group layouts as groups 0, 1, ...
+    pub fn pipeline_layout(
+        &mut self,
+        device: &wgpu::Device,
+        names: &[String],
+    ) -> Result<Rc<wgpu::PipelineLayout>> {
+        if let Some(layout) = self.pipeline_layouts.get(names) {
+            return Ok(layout.clone());
+        }
+        let bind_group_layouts = names
+            .iter()
+            .map(|name| {
+                self.bind_group_layouts
+                    .get(name)
+                    .with_context(|| format!("No bind group layout named {name}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let bind_group_layouts = bind_group_layouts
+            .iter()
+            .map(|layout| layout.as_ref())
+            .collect::<Vec<_>>();
+        let layout = Rc::new(
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&names.join("_")),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            }),
+        );
+        self.pipeline_layouts.insert(names.to_vec(), layout.clone());
+        Ok(layout)
+    }
+}
+
+/// An owned `wgpu::VertexBufferLayout`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VertexLayout {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl From<&wgpu::VertexBufferLayout<'_>> for VertexLayout {
+    fn from(layout: &wgpu::VertexBufferLayout) -> Self {
+        VertexLayout {
+            array_stride: layout.array_stride,
+            step_mode: layout.step_mode,
+            attributes: layout.attributes.to_vec(),
+        }
+    }
+}
+
+impl VertexLayout {
+    pub fn buffer_layout(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.array_stride,
+            step_mode: self.step_mode,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+/// Everything that goes into a render pipeline, by the names its shader and
+/// bind group layouts are registered under.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PipelineKey {
+    /// See `PipelineCache::add_shader`.
+    pub shader: String,
+    /// See `LayoutRegistry::register`.
+    pub bind_group_layouts: Vec<String>,
+    pub vertex_entry_point: String,
+    /// None for depth-only pipelines.
+    pub fragment_entry_point: Option<String>,
+    pub vertex_layouts: Vec<VertexLayout>,
+    pub color_targets: Vec<wgpu::ColorTargetState>,
+    pub primitive: wgpu::PrimitiveState,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    pub sample_count: u32,
+}
+
+impl PipelineKey {
+    /// Starts from the same state as `PipelineBuilder::new`, without any
+    /// vertex buffers or targets.
+    pub fn new(shader: &str, bind_group_layouts: &[&str]) -> Self {
+        PipelineKey {
+            shader: shader.to_string(),
+            bind_group_layouts: bind_group_layouts.iter().map(|s| s.to_string()).collect(),
+            vertex_entry_point: "vs_main".to_string(),
+            fragment_entry_point: Some("fs_main".to_string()),
+            vertex_layouts: Vec::new(),
+            color_targets: Vec::new(),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            sample_count: 1,
+        }
+    }
+}
+
+/// Builds each distinct pipeline once, from shaders registered by name.
+///
+/// The keys of the pipelines built so far can be saved with
+/// `save_warmup_list` and built ahead of time on the next run with `warm_up`.
+#[derive(Default)]
+pub struct PipelineCache {
+    shaders: HashMap<String, wgpu::ShaderModule>,
+    pipelines: HashMap<PipelineKey, Rc<wgpu::RenderPipeline>>,
+    // The `LayoutRegistry` generation the pipelines were last checked against
+    layout_generation: u64,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a shader under `id`. Pipelines built from a shader it
+    /// replaces are dropped from the cache.
+    pub fn add_shader(
+        &mut self,
+        device: &wgpu::Device,
+        id: &str,
+        shader: wgpu::ShaderModuleDescriptor,
+    ) {
+        let module = device.create_shader_module(shader);
+        if self.shaders.insert(id.to_string(), module).is_some() {
+            self.pipelines.retain(|key, _| key.shader != id);
+        }
+    }
+
+    /// Like `add_shader`, but keeps the previous shader and its pipelines if
+    /// the new one fails to compile, or fails to build any cached pipeline
+    /// using it or any of `keys`.
+    pub fn try_add_shader(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &mut LayoutRegistry,
+        id: &str,
+        shader: wgpu::ShaderModuleDescriptor,
+        keys: &[PipelineKey],
+    ) -> Result<()> {
+        self.drop_replaced_layouts(layouts);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(shader);
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            bail!("{}", e);
+        }
+
+        let mut keys = keys.to_vec();
+        for key in self.pipelines.keys().filter(|key| key.shader == id) {
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+        let mut rebuilt = Vec::with_capacity(keys.len());
+        for key in keys {
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let pipeline = build_pipeline(device, layouts, &module, &key);
+            if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+                bail!("{}", e);
+            }
+            rebuilt.push((key, Rc::new(pipeline?)));
+        }
+
+        self.shaders.insert(id.to_string(), module);
+        self.pipelines.retain(|key, _| key.shader != id);
+        self.pipelines.extend(rebuilt);
+        Ok(())
+    }
+
+    pub fn has_shader(&self, id: &str) -> bool {
+        self.shaders.contains_key(id)
+    }
+
+    /// The pipeline for `key`, built on first use. Fails if its shader or
+    /// layouts aren't registered, and panics on validation errors like
+    /// `PipelineBuilder::build`.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &mut LayoutRegistry,
+        key: &PipelineKey,
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        self.drop_replaced_layouts(layouts);
+        if let Some(pipeline) = self.pipelines.get(key) {
+            return Ok(pipeline.clone());
+        }
+        let Some(shader) = self.shaders.get(&key.shader) else {
+            bail!("No shader registered as {}", key.shader);
+        };
+        let pipeline = Rc::new(build_pipeline(device, layouts, shader, key)?);
+
+        self.pipelines.insert(key.clone(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Like `get`, but reports validation errors instead of panicking.
+    pub fn try_get(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &mut LayoutRegistry,
+        key: &PipelineKey,
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = self.get(device, layouts, key);
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            self.pipelines.remove(key);
+            bail!("{}", e);
+        }
+        pipeline
+    }
+
+    /// Drops the pipelines built against bind group layouts that were
+    /// registered again since the last check.
+    fn drop_replaced_layouts(&mut self, layouts: &LayoutRegistry) {
+        if self.layout_generation == layouts.generation {
+            return;
+        }
+        let replaced = layouts
+            .replaced_since(self.layout_generation)
+            .collect::<Vec<_>>();
+        self.pipelines.retain(|key, _| {
+            !key.bind_group_layouts
+                .iter()
+                .any(|name| replaced.contains(&name.as_str()))
+        });
+        self.layout_generation = layouts.generation;
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// Writes the keys of every cached pipeline to `path` as JSON.
+    pub fn save_warmup_list(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_warmup_list(path.as_ref(), self.pipelines.keys())
+    }
+
+    /// Builds the pipelines listed by `save_warmup_list`, skipping any whose
+    /// shader or layouts are no longer registered. Returns how many were built.
+    pub fn warm_up(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &mut LayoutRegistry,
+        path: impl AsRef<Path>,
+    ) -> Result<usize> {
+        let path = path.as_ref();
+        let keys = read_warmup_list(path)?;
+
+        let mut built = 0;
+        for key in keys {
+            if self.pipelines.contains_key(&key) {
+                continue;
+            }
+            if let Err(e) = self.try_get(device, layouts, &key) {
+                log::warn!("Skipping {} pipeline from {:?}: {:#}", key.shader, path, e);
+                continue;
+            }
+            built += 1;
+        }
+        Ok(built)
+    }
+}
+
+fn build_pipeline(
+    device: &wgpu::Device,
+    layouts: &mut LayoutRegistry,
+    shader: &wgpu::ShaderModule,
+    key: &PipelineKey,
+) -> Result<wgpu::RenderPipeline> {
+    let layout = layouts.pipeline_layout(device, &key.bind_group_layouts)?;
+    let vertex_layouts = key
+        .vertex_layouts
+        .iter()
+        .map(VertexLayout::buffer_layout)
+        .collect::<Vec<_>>();
+
+    let mut builder = PipelineBuilder::new(&layout, shader)
+        .label(&key.shader)
+        .vertex_entry_point(&key.vertex_entry_point)
+        .vertex_layouts(&vertex_layouts)
+        .primitive(key.primitive)
+        .sample_count(key.sample_count);
+    builder = match &key.fragment_entry_point {
+        Some(entry_point) => builder.fragment_entry_point(entry_point),
+        None => builder.without_fragment(),
+    };
+    for target in &key.color_targets {
+        builder = builder.color_target_state(target.clone());
+    }
+    if let Some(depth_stencil) = &key.depth_stencil {
+        builder = builder.depth_stencil(depth_stencil.clone());
+    }
+    Ok(builder.build(device).pipeline)
+}
+
+fn write_warmup_list<'a>(path: &Path, keys: impl Iterator<Item = &'a PipelineKey>) -> Result<()> {
+    let keys = keys.collect::<Vec<_>>();
+    let json = serde_json::to_string_pretty(&keys)?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {:?}", path))
+}
+
+fn read_warmup_list(path: &Path) -> Result<Vec<PipelineKey>> {
+    let json =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    serde_json::from_str(&json).with_context(|| format!("Failed to parse {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn scene_key(shader: &str) -> PipelineKey {
+        PipelineKey {
+            vertex_layouts: vec![VertexLayout {
+                array_stride: 32,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes:
+                    wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3]
+                        .to_vec(),
+            }],
+            color_targets: vec![wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba16Float,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            }],
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            ..PipelineKey::new(shader, &["texture", "camera", "light"])
+        }
+    }
+
+    #[test]
+    fn equal_keys_hash_alike_and_any_difference_tells_them_apart() {
+        let mut keys = HashSet::new();
+        assert!(keys.insert(scene_key("shader.wgsl")));
+        assert!(!keys.insert(scene_key("shader.wgsl")));
+
+        let variants = [
+            scene_key("light.wgsl"),
+            PipelineKey {
+                sample_count: 4,
+                ..scene_key("shader.wgsl")
+            },
+            PipelineKey {
+                bind_group_layouts: vec!["texture".to_string(), "camera".to_string()],
+                ..scene_key("shader.wgsl")
+            },
+            PipelineKey {
+                fragment_entry_point: None,
+                ..scene_key("shader.wgsl")
+            },
+            PipelineKey {
+                primitive: wgpu::PrimitiveState {
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    ..scene_key("shader.wgsl").primitive
+                },
+                ..scene_key("shader.wgsl")
+            },
+        ];
+        for key in variants {
+            assert!(keys.insert(key.clone()), "{key:?} collided");
+        }
+        assert_eq!(keys.len(), 6);
+    }
+
+    #[test]
+    fn warmup_list_round_trips() {
+        let keys = [
+            scene_key("shader.wgsl"),
+            PipelineKey {
+                sample_count: 4,
+                depth_stencil: None,
+                ..scene_key("light.wgsl")
+            },
+        ];
+        let path =
+            std::env::temp_dir().join(format!("gamezap_warmup_list_{}.json", std::process::id()));
+        write_warmup_list(&path, keys.iter()).unwrap();
+        let loaded = read_warmup_list(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), keys);
+    }
+}
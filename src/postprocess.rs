@@ -0,0 +1,620 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    resources,
+    texture::{SamplerOptions, Texture, TextureFilter},
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessUniform {
+    texel_size: [f32; 2],
+    srgb_encoded: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vignette {
+    pub intensity: f32,
+    /// Where the darkening ends, with the corners of the screen at 1.
+    pub radius: f32,
+    /// How far inside `radius` the darkening starts.
+    pub smoothness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette {
+            intensity: 0.5,
+            radius: 1.1,
+            smoothness: 0.6,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorGrading {
+    /// Blends between the original colors at 0 and the graded ones at 1.
+    pub intensity: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        ColorGrading { intensity: 1.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Fxaa {
+    /// The longest blur along an edge, in pixels.
+    pub span_max: f32,
+    pub reduce_multiplier: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Fxaa {
+            span_max: 8.0,
+            reduce_multiplier: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChromaticAberration {
+    /// How far the red and blue channels are shifted at the edges, as a
+    /// fraction of the screen.
+    pub intensity: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        ChromaticAberration { intensity: 0.01 }
+    }
+}
+
+/// A fullscreen pass in a `PostProcess` chain.
+///
+/// The shader only provides `fs_main`. `srgb.wgsl` and `postprocess.wgsl` are
+/// put in front of it, declaring the sRGB helpers, the previous pass in group
+/// 0 and the vertex stage. Group 1 holds the effect's uniform at binding 0, if
+/// it has one, followed by a texture and sampler pair for each of its
+/// textures.
+pub struct PostEffect {
+    pub name: String,
+    pub enabled: bool,
+    uniform_buffer: Option<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PostEffect {
+    pub fn new(
+        device: &wgpu::Device,
+        post_process: &PostProcess,
+        name: &str,
+        shader: wgpu::ShaderModuleDescriptor,
+        uniform: &[u8],
+        textures: &[&Texture],
+    ) -> Result<Self> {
+        let shader = resources::with_prelude(&post_process.prelude, shader)
+            .with_context(|| format!("Failed to build the {name} shader"))?;
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let uniform_buffer = (!uniform.is_empty()).then(|| {
+            // Uniform bindings are sized in multiples of 16 bytes
+            let mut contents = uniform.to_vec();
+            contents.resize(uniform.len().next_multiple_of(16), 0);
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{name} uniform buffer")),
+                contents: &contents,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        });
+
+        let mut layout_entries = Vec::new();
+        let mut entries = Vec::new();
+        if let Some(buffer) = &uniform_buffer {
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            });
+        }
+        for (i, texture) in textures.iter().enumerate() {
+            let binding = 1 + 2 * i as u32;
+            let view_dimension = match texture.texture.dimension() {
+                wgpu::TextureDimension::D1 => wgpu::TextureViewDimension::D1,
+                wgpu::TextureDimension::D2 => wgpu::TextureViewDimension::D2,
+                wgpu::TextureDimension::D3 => wgpu::TextureViewDimension::D3,
+            };
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format!("{name}_bind_group_layout")),
+            entries: &layout_entries,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{name}_bind_group")),
+            layout: &bind_group_layout,
+            entries: &entries,
+        });
+
+        let shader = device.create_shader_module(shader);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{name} pipeline layout")),
+            bind_group_layouts: &[&post_process.bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{name} pipeline")),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: post_process.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            bail!("Failed to create the {name} effect: {e}");
+        }
+
+        Ok(PostEffect {
+            name: name.to_string(),
+            enabled: true,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    /// Darkens the corners of the screen.
+    pub fn vignette(
+        device: &wgpu::Device,
+        post_process: &PostProcess,
+        settings: Vignette,
+    ) -> Result<Self> {
+        let shader =
+            resources::load_shader("vignette.wgsl", include_str!("../shaders/vignette.wgsl"));
+        Self::new(
+            device,
+            post_process,
+            "vignette",
+            shader,
+            bytemuck::bytes_of(&settings),
+            &[],
+        )
+    }
+
+    /// Remaps colors through a 3D lookup table, e.g. from `load_lut`.
+    pub fn color_grading(
+        device: &wgpu::Device,
+        post_process: &PostProcess,
+        settings: ColorGrading,
+        lut: &Texture,
+    ) -> Result<Self> {
+        let shader = resources::load_shader(
+            "color_grading.wgsl",
+            include_str!("../shaders/color_grading.wgsl"),
+        );
+        Self::new(
+            device,
+            post_process,
+            "color_grading",
+            shader,
+            bytemuck::bytes_of(&settings),
+            &[lut],
+        )
+    }
+
+    /// Smooths jagged edges.
+    pub fn fxaa(device: &wgpu::Device, post_process: &PostProcess, settings: Fxaa) -> Result<Self> {
+        let shader = resources::load_shader("fxaa.wgsl", include_str!("../shaders/fxaa.wgsl"));
+        Self::new(
+            device,
+            post_process,
+            "fxaa",
+            shader,
+            bytemuck::bytes_of(&settings),
+            &[],
+        )
+    }
+
+    /// Splits the color channels apart towards the edges of the screen.
+    pub fn chromatic_aberration(
+        device: &wgpu::Device,
+        post_process: &PostProcess,
+        settings: ChromaticAberration,
+    ) -> Result<Self> {
+        let shader = resources::load_shader(
+            "chromatic_aberration.wgsl",
+            include_str!("../shaders/chromatic_aberration.wgsl"),
+        );
+        Self::new(
+            device,
+            post_process,
+            "chromatic_aberration",
+            shader,
+            bytemuck::bytes_of(&settings),
+            &[],
+        )
+    }
+
+    /// Replaces the uniform, e.g. with new settings for a built-in effect.
+    pub fn write_uniform<U: bytemuck::Pod>(&self, queue: &wgpu::Queue, uniform: &U) {
+        if let Some(buffer) = &self.uniform_buffer {
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(uniform));
+        }
+    }
+}
+
+/// A chain of fullscreen effects applied after tonemapping, in order.
+///
+/// Each enabled effect reads the output of the one before it from one of two
+/// targets and writes into the other, and the last one writes to the screen.
+pub struct PostProcess {
+    effects: Vec<PostEffect>,
+    format: wgpu::TextureFormat,
+    targets: [Texture; 2],
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // Reading from each of the targets
+    bind_groups: [wgpu::BindGroup; 2],
+    // Put in front of every effect's shader
+    prelude: String,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let targets = Self::create_targets(device, config);
+        let sampler = SamplerOptions::clamp(TextureFilter::Bilinear).create_sampler(device);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post process uniform buffer"),
+            contents: bytemuck::cast_slice(&[PostProcessUniform::new(config)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post_process_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &targets,
+            &sampler,
+            &uniform_buffer,
+        );
+        let prelude =
+            resources::load_shader_source("srgb.wgsl", include_str!("../shaders/srgb.wgsl"))
+                + "\n"
+                + resources::load_shader_source(
+                    "postprocess.wgsl",
+                    include_str!("../shaders/postprocess.wgsl"),
+                );
+
+        PostProcess {
+            effects: Vec::new(),
+            format: config.format,
+            targets,
+            sampler,
+            uniform_buffer,
+            bind_group_layout,
+            bind_groups,
+            prelude: prelude.into_owned(),
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.targets = Self::create_targets(device, config);
+        self.bind_groups = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.targets,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[PostProcessUniform::new(config)]),
+        );
+    }
+
+    /// Whether any effect is enabled. Otherwise the chain is skipped entirely.
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|effect| effect.enabled)
+    }
+
+    /// The target the first effect reads from.
+    pub fn input(&self) -> &Texture {
+        &self.targets[0]
+    }
+
+    pub fn effects(&self) -> &[PostEffect] {
+        &self.effects
+    }
+
+    /// The effects in the order they are applied, free to be rearranged.
+    pub fn effects_mut(&mut self) -> &mut Vec<PostEffect> {
+        &mut self.effects
+    }
+
+    pub fn effect(&self, name: &str) -> Option<&PostEffect> {
+        self.effects.iter().find(|effect| effect.name == name)
+    }
+
+    pub fn effect_mut(&mut self, name: &str) -> Option<&mut PostEffect> {
+        self.effects.iter_mut().find(|effect| effect.name == name)
+    }
+
+    /// Appends an effect to the end of the chain.
+    pub fn push(&mut self, effect: PostEffect) {
+        self.effects.push(effect);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<PostEffect> {
+        let index = self.position(name)?;
+        Some(self.effects.remove(index))
+    }
+
+    /// Moves an effect to `index` in the chain, shifting the ones after it.
+    pub fn move_effect(&mut self, name: &str, index: usize) -> Result<()> {
+        let Some(from) = self.position(name) else {
+            bail!("No post effect named {name}");
+        };
+        if index >= self.effects.len() {
+            bail!(
+                "Can't move {name} to {index} in a chain of {} effects",
+                self.effects.len()
+            );
+        }
+        let effect = self.effects.remove(from);
+        self.effects.insert(index, effect);
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<()> {
+        let Some(effect) = self.effect_mut(name) else {
+            bail!("No post effect named {name}");
+        };
+        effect.enabled = enabled;
+        Ok(())
+    }
+
+    /// Runs the enabled effects on `input`, writing the result to `output`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let enabled = self
+            .effects
+            .iter()
+            .filter(|effect| effect.enabled)
+            .collect::<Vec<_>>();
+        for (i, effect) in enabled.iter().enumerate() {
+            let view = if i + 1 == enabled.len() {
+                output
+            } else {
+                &self.targets[(i + 1) % 2].view
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(&effect.name),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&effect.pipeline);
+            render_pass.set_bind_group(0, &self.bind_groups[i % 2], &[]);
+            render_pass.set_bind_group(1, &effect.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.effects.iter().position(|effect| effect.name == name)
+    }
+
+    fn create_targets(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> [Texture; 2] {
+        [
+            Texture::create_render_target(device, config, "post_process_target_0"),
+            Texture::create_render_target(device, config, "post_process_target_1"),
+        ]
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        targets: &[Texture; 2],
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> [wgpu::BindGroup; 2] {
+        targets.each_ref().map(|target| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post_process_bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&target.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+}
+
+impl PostProcessUniform {
+    fn new(config: &wgpu::SurfaceConfiguration) -> Self {
+        PostProcessUniform {
+            texel_size: [1.0 / config.width as f32, 1.0 / config.height as f32],
+            // The tonemapper applies the sRGB curve itself for other formats
+            srgb_encoded: (!config.format.is_srgb()) as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// An identity lookup table of `size`³ texels for `PostEffect::color_grading`.
+pub fn identity_lut(device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> Result<Texture> {
+    let level = |i: u32| (i * 255 / (size - 1).max(1)) as u8;
+    let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&[level(r), level(g), level(b), 255]);
+            }
+        }
+    }
+    lut_from_data(device, queue, size, &data, "identity_lut")
+}
+
+/// Loads a lookup table for `PostEffect::color_grading` from an image of
+/// `size` square tiles laid out in a row, e.g. 256x16. Red increases to the
+/// right in each tile, green downwards and blue from one tile to the next.
+pub async fn load_lut(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<Texture> {
+    let data = resources::load_binary(file_name).await?;
+    let image = image::load_from_memory(&data)
+        .with_context(|| format!("Failed to decode {:?}", file_name))?
+        .into_rgba8();
+    let size = image.height();
+    if image.width() != size * size {
+        bail!(
+            "LUT {file_name} is {}x{}, expected {}x{size}",
+            image.width(),
+            size,
+            size * size
+        );
+    }
+
+    // Rearranges the tiles into slices of the volume
+    let mut data = Vec::with_capacity(image.as_raw().len());
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&image.get_pixel(b * size + r, g).0);
+            }
+        }
+    }
+    lut_from_data(device, queue, size, &data, file_name)
+}
+
+fn lut_from_data(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    size: u32,
+    data: &[u8],
+    label: &str,
+) -> Result<Texture> {
+    Texture::from_volume(
+        device,
+        queue,
+        wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        data,
+        label,
+    )
+}
@@ -1,14 +1,39 @@
-use std::io::{BufReader, Cursor};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    fmt,
+    io::{BufReader, Cursor},
+    path::Path,
+    rc::Rc,
+};
 
+use anyhow::Context;
+use base64::Engine;
 use cfg_if::cfg_if;
 use nalgebra as na;
 use wgpu::util::DeviceExt;
 
 use crate::{
-    model::{Material, Mesh, Model, ModelVertex},
-    texture::Texture,
+    assets::AssetServer,
+    model::{Material, MaterialMap, MaterialUniform, Mesh, Model, ModelVertex, ShadingModel},
+    texture::{SamplerOptions, Texture, TextureFilter, TextureKind},
 };
 
+#[derive(Debug)]
+pub enum ResourceError {
+    NotFound { path: String },
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceError::NotFound { path } => write!(f, "Resource not found: {:?}", path),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
     let window = web_sys::window().unwrap();
@@ -22,15 +47,15 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             let url = format_url(file_name);
-            let txt = reqwest::get(url)
-                .await?
-                .text()
-                .await?;
+            let response = reqwest::get(url).await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Err(ResourceError::NotFound { path: file_name.to_string() }.into());
+            }
+            let txt = response.text().await?;
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("models")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
+            let data = crate::vfs::global().read().unwrap().read(file_name)?;
+            let txt = String::from_utf8(data)
+                .with_context(|| format!("{:?} is not valid UTF-8", file_name))?;
         }
     }
 
@@ -41,42 +66,126 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             let url = format_url(file_name);
-            let data = reqwest::get(url)
-                .await?
-                .bytes()
-                .await?
-                .to_vec();
+            let response = reqwest::get(url).await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Err(ResourceError::NotFound { path: file_name.to_string() }.into());
+            }
+            let data = response.bytes().await?.to_vec();
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("models")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+            let data = crate::vfs::global().read().unwrap().read(file_name)?;
         }
     }
 
     Ok(data)
 }
 
+/// Reads `shaders/<file_name>` through the VFS, so shaders packed into the
+/// asset archive or mounted by the game replace the copy compiled into the
+/// library, which is only used when no mount has the file.
+pub fn load_shader(
+    file_name: &'static str,
+    compiled: &'static str,
+) -> wgpu::ShaderModuleDescriptor<'static> {
+    wgpu::ShaderModuleDescriptor {
+        label: Some(file_name),
+        source: wgpu::ShaderSource::Wgsl(load_shader_source(file_name, compiled)),
+    }
+}
+
+/// The WGSL `load_shader` builds its module from, e.g. for a prelude that is
+/// put in front of other shaders.
+pub fn load_shader_source(file_name: &'static str, compiled: &'static str) -> Cow<'static, str> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let source = match crate::vfs::global()
+        .read()
+        .unwrap()
+        .read(&format!("shaders/{}", file_name))
+        .and_then(|data| Ok(String::from_utf8(data)?))
+    {
+        Ok(source) => source.into(),
+        Err(e) => {
+            if !matches!(e.downcast_ref(), Some(ResourceError::NotFound { .. })) {
+                log::warn!("Using the built-in {}: {:#}", file_name, e);
+            }
+            compiled.into()
+        }
+    };
+    #[cfg(target_arch = "wasm32")]
+    let source = compiled.into();
+
+    source
+}
+
+/// Puts `prelude` in front of a WGSL shader, for declarations several shaders
+/// share.
+pub fn with_prelude<'a>(
+    prelude: &str,
+    shader: wgpu::ShaderModuleDescriptor<'a>,
+) -> anyhow::Result<wgpu::ShaderModuleDescriptor<'a>> {
+    let wgpu::ShaderSource::Wgsl(source) = shader.source else {
+        anyhow::bail!("Only WGSL shaders can have a prelude");
+    };
+    Ok(wgpu::ShaderModuleDescriptor {
+        label: shader.label,
+        source: wgpu::ShaderSource::Wgsl(format!("{prelude}\n{source}").into()),
+    })
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-    is_normal_map: bool,
+    kind: TextureKind,
+    sampler: Rc<wgpu::Sampler>,
 ) -> anyhow::Result<Texture> {
     let data = load_binary(file_name).await?;
-    Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    Texture::from_bytes(device, queue, &data, file_name, kind, sampler)
+}
+
+/// Loads a Radiance `.hdr` image with its full floating point range.
+pub async fn load_hdr(file_name: &str) -> anyhow::Result<image::Rgba32FImage> {
+    let data = load_binary(file_name).await?;
+    let image = image::load_from_memory_with_format(&data, image::ImageFormat::Hdr)
+        .with_context(|| format!("Failed to decode {:?}", file_name))?;
+    Ok(image.into_rgba32f())
 }
 
+/// Loads a model on its own. Use an `AssetServer` to share textures between models.
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Model> {
+    load_model_with_assets(file_name, device, queue, layout, &mut AssetServer::new()).await
+}
+
+pub(crate) async fn load_model_with_assets(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    assets: &mut AssetServer,
+) -> anyhow::Result<Model> {
+    match Path::new(file_name).extension().and_then(|e| e.to_str()) {
+        Some("gltf") | Some("glb") => load_gltf(file_name, device, queue, layout, assets).await,
+        _ => load_obj(file_name, device, queue, layout, assets).await,
+    }
+}
+
+pub async fn load_obj(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    assets: &mut AssetServer,
 ) -> anyhow::Result<Model> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
+    // tobj only reports a generic failure for a missing MTL, so keep the real error around
+    let mtl_error = RefCell::new(None);
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
@@ -84,138 +193,824 @@ pub async fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let mtl_error = &mtl_error;
+            async move {
+                match load_string(&p).await {
+                    Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                    Err(e) => {
+                        *mtl_error.borrow_mut() = Some(e);
+                        Err(tobj::LoadError::OpenFileFailed)
+                    }
+                }
+            }
         },
     )
     .await?;
+    let obj_materials = match obj_materials {
+        Ok(obj_materials) => obj_materials,
+        Err(e) => return Err(mtl_error.into_inner().unwrap_or_else(|| e.into())),
+    };
 
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture =
-            load_texture(&m.diffuse_texture.unwrap(), device, queue, false).await?;
-        let normal_texture = load_texture(&m.normal_texture.unwrap(), device, queue, true).await?;
+    for m in obj_materials {
+        let mut textures = assets.default_material_textures(device, queue)?;
+        if let Some(map) = &m.diffuse_texture {
+            let (file, sampler) = parse_mtl_map(map, assets.texture_filter);
+            let texture = assets
+                .shared_texture(file, device, queue, TextureKind::Color)
+                .await?;
+            textures.diffuse = MaterialMap::new(texture, sampler);
+        }
+        // Some exporters write the normal map as `norm`, which tobj leaves unparsed
+        let normal_file = m
+            .normal_texture
+            .as_ref()
+            .or_else(|| m.unknown_param.get("norm"));
+        if let Some(map) = normal_file {
+            let (file, sampler) = parse_mtl_map(map, assets.texture_filter);
+            let texture = assets
+                .shared_texture(file, device, queue, TextureKind::Normal)
+                .await?;
+            textures.normal = MaterialMap::new(texture, sampler);
+        }
+        if let Some(map) = m.unknown_param.get("map_Ke") {
+            let (file, sampler) = parse_mtl_map(map, assets.texture_filter);
+            let texture = assets
+                .shared_texture(file, device, queue, TextureKind::Color)
+                .await?;
+            textures.emissive = MaterialMap::new(texture, sampler);
+        }
+
+        let defaults = MaterialUniform::default();
+        let shininess = m.shininess.unwrap_or(defaults.shininess);
+        // The PBR extension to MTL adds `Pr` roughness and `Pm` metallic
+        let roughness = parse_mtl_floats::<1>(&m, "Pr");
+        let metallic = parse_mtl_floats::<1>(&m, "Pm");
+        let shading_model = if roughness.is_some() || metallic.is_some() {
+            ShadingModel::Pbr
+        } else {
+            ShadingModel::Phong
+        };
+        let uniform = MaterialUniform {
+            diffuse_color: m.diffuse.unwrap_or(defaults.diffuse_color),
+            alpha: m.dissolve.unwrap_or(defaults.alpha),
+            specular_color: m.specular.unwrap_or(defaults.specular_color),
+            shininess,
+            emissive: parse_mtl_floats(&m, "Ke").unwrap_or(defaults.emissive),
+            metallic: metallic.map_or(defaults.metallic, |[m]| m),
+            // Approximates the Phong lobe with a GGX lobe of similar width
+            roughness: roughness.map_or((2.0 / (shininess + 2.0)).sqrt(), |[r]| r),
+            shading_model: shading_model as u32,
+            ..defaults
+        };
 
         materials.push(Material::new(
             device,
             &m.name,
-            diffuse_texture,
-            normal_texture,
+            textures,
+            uniform,
             layout,
+            &mut assets.samplers,
         ));
     }
+    // Meshes without a material use one appended after the MTL's own materials
+    let default_material = materials.len();
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let mut vertices = (0..m.mesh.positions.len() / 3)
+            let vertex_count = m.mesh.positions.len() / 3;
+            // Faces without `vt` or `vn` entries leave these empty, like glTF
+            // primitives without the attributes
+            let has_tex_coords = m.mesh.texcoords.len() >= vertex_count * 2;
+            let has_normals = m.mesh.normals.len() >= vertex_count * 3;
+            let mut vertices = (0..vertex_count)
                 .map(|i| ModelVertex {
                     position: [
                         m.mesh.positions[i * 3],
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-
+                    tex_coords: if has_tex_coords {
+                        [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]]
+                    } else {
+                        [0.0; 2]
+                    },
+                    normal: if has_normals {
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    } else {
+                        [0.0; 3]
+                    },
                     tangent: [0.0; 3],
                     bitangent: [0.0; 3],
                 })
                 .collect::<Vec<_>>();
 
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: na::Vector3<_> = v0.position.into();
-                let pos1: na::Vector3<_> = v1.position.into();
-                let pos2: na::Vector3<_> = v2.position.into();
-
-                let uv0: na::Vector2<_> = v0.tex_coords.into();
-                let uv1: na::Vector2<_> = v1.tex_coords.into();
-                let uv2: na::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + na::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + na::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + na::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + na::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + na::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + na::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
+            if !has_normals {
+                compute_normals(&mut vertices, &m.mesh.indices);
             }
+            compute_tangents(&mut vertices, &m.mesh.indices);
 
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let mut v = &mut vertices[i];
-                v.tangent = (na::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (na::Vector3::from(v.bitangent) * denom).into();
+            create_mesh(
+                device,
+                file_name,
+                &vertices,
+                &m.mesh.indices,
+                m.mesh
+                    .material_id
+                    .filter(|&id| id < default_material)
+                    .unwrap_or(default_material),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if meshes.iter().any(|m| m.material == default_material) {
+        let textures = assets.default_material_textures(device, queue)?;
+        materials.push(Material::fallback(
+            device,
+            textures,
+            layout,
+            &mut assets.samplers,
+        ));
+    }
+
+    Ok(Model { meshes, materials })
+}
+
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    assets: &mut AssetServer,
+) -> anyhow::Result<Model> {
+    let data = load_binary(file_name).await?;
+    let gltf = gltf::Gltf::from_slice(&data)?;
+    let base_dir = Path::new(file_name).parent().unwrap_or(Path::new(""));
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        let buffer_data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .context("glTF buffer refers to a missing binary chunk")?,
+            gltf::buffer::Source::Uri(uri) => load_uri(uri, base_dir).await?,
+        };
+        if buffer_data.len() < buffer.length() {
+            anyhow::bail!(
+                "glTF buffer {} has {} bytes but declares {}",
+                buffer.index(),
+                buffer_data.len(),
+                buffer.length()
+            );
+        }
+        buffers.push(buffer_data);
+    }
+
+    let mut materials = Vec::new();
+    for material in gltf.materials() {
+        let name = material.name().unwrap_or(file_name);
+        let pbr = material.pbr_metallic_roughness();
+
+        let mut textures = assets.default_material_textures(device, queue)?;
+        let [r, g, b, a] = pbr.base_color_factor();
+        let mut uniform = MaterialUniform {
+            diffuse_color: [r, g, b],
+            alpha: a,
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: material.emissive_factor(),
+            shading_model: ShadingModel::Pbr as u32,
+            ..Default::default()
+        };
+
+        let mut maps = Vec::new();
+        if let Some(info) = pbr.base_color_texture() {
+            maps.push((
+                &mut textures.diffuse,
+                info.texture(),
+                info.tex_coord(),
+                TextureKind::Color,
+            ));
+        }
+        if let Some(info) = material.normal_texture() {
+            uniform.normal_scale = info.scale();
+            maps.push((
+                &mut textures.normal,
+                info.texture(),
+                info.tex_coord(),
+                TextureKind::Normal,
+            ));
+        }
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            maps.push((
+                &mut textures.metallic_roughness,
+                info.texture(),
+                info.tex_coord(),
+                TextureKind::Linear,
+            ));
+        }
+        if let Some(info) = material.occlusion_texture() {
+            uniform.occlusion_strength = info.strength();
+            maps.push((
+                &mut textures.occlusion,
+                info.texture(),
+                info.tex_coord(),
+                TextureKind::Linear,
+            ));
+        }
+        if let Some(info) = material.emissive_texture() {
+            maps.push((
+                &mut textures.emissive,
+                info.texture(),
+                info.tex_coord(),
+                TextureKind::Color,
+            ));
+        }
+        for (slot, texture, tex_coord, kind) in maps {
+            // Only TEXCOORD_0 reaches the shader
+            if tex_coord != 0 {
+                log::warn!(
+                    "{:?}: material {:?} maps a texture with TEXCOORD_{}, using TEXCOORD_0 instead",
+                    file_name,
+                    name,
+                    tex_coord
+                );
             }
+            *slot = load_gltf_texture(
+                texture, file_name, &buffers, base_dir, device, queue, assets, kind,
+            )
+            .await?;
+        }
+
+        materials.push(Material::new(
+            device,
+            name,
+            textures,
+            uniform,
+            layout,
+            &mut assets.samplers,
+        ));
+    }
+    // Primitives without a material use one appended after the file's own materials
+    let default_material = materials.len();
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .context("glTF file contains no scenes")?;
+
+    let mut meshes = Vec::new();
+    let mut stack = scene
+        .nodes()
+        .map(|node| (node, na::Matrix4::identity()))
+        .collect::<Vec<_>>();
+    while let Some((node, parent_matrix)) = stack.pop() {
+        let world_matrix = parent_matrix * na::Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!(
+                        "Skipping non-triangle primitive in {:?}: {:?}",
+                        file_name,
+                        primitive.mode()
+                    );
+                    continue;
+                }
+                meshes.push(load_gltf_primitive(
+                    &primitive,
+                    &buffers,
+                    world_matrix,
+                    mesh.name().unwrap_or(file_name),
+                    default_material,
+                    device,
+                )?);
             }
+        }
+
+        stack.extend(node.children().map(|child| (child, world_matrix)));
+    }
+
+    if meshes.iter().any(|m| m.material == default_material) {
+        let textures = assets.default_material_textures(device, queue)?;
+        materials.push(Material::fallback(
+            device,
+            textures,
+            layout,
+            &mut assets.samplers,
+        ));
+    }
+
+    Ok(Model { meshes, materials })
+}
+
+fn load_gltf_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    world_matrix: na::Matrix4<f32>,
+    name: &str,
+    default_material: usize,
+    device: &wgpu::Device,
+) -> anyhow::Result<Mesh> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+
+    let positions = reader
+        .read_positions()
+        .context("glTF primitive has no positions")?
+        .collect::<Vec<_>>();
+    let normals = reader.read_normals().map(|n| n.collect::<Vec<_>>());
+    let tex_coords = reader
+        .read_tex_coords(0)
+        .map(|t| t.into_f32().collect::<Vec<_>>());
+    let tangents = reader.read_tangents().map(|t| t.collect::<Vec<_>>());
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        anyhow::bail!(
+            "glTF primitive index {} is out of range for {} vertices",
+            index,
+            positions.len()
+        );
+    }
+    let short_attribute = [
+        normals.as_ref().map(Vec::len),
+        tex_coords.as_ref().map(Vec::len),
+        tangents.as_ref().map(Vec::len),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|len| len < positions.len());
+    if short_attribute {
+        anyhow::bail!("glTF primitive has fewer attributes than positions");
+    }
+
+    let linear_matrix = world_matrix.fixed_view::<3, 3>(0, 0).into_owned();
+    let normal_matrix = linear_matrix
+        .try_inverse()
+        .unwrap_or_else(na::Matrix3::identity)
+        .transpose();
+
+    let mut vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| ModelVertex {
+            position: world_matrix
+                .transform_point(&na::Point3::from(*position))
+                .coords
+                .into(),
+            tex_coords: tex_coords.as_ref().map_or([0.0; 2], |t| t[i]),
+            normal: normals.as_ref().map_or([0.0; 3], |n| {
+                (normal_matrix * na::Vector3::from(n[i])).normalize().into()
+            }),
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
         })
         .collect::<Vec<_>>();
 
-    Ok(Model { meshes, materials })
+    if normals.is_none() {
+        compute_normals(&mut vertices, &indices);
+    }
+
+    match tangents {
+        Some(tangents) => {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                let normal = na::Vector3::from(vertex.normal);
+                let world_tangent = (linear_matrix
+                    * na::Vector3::new(tangent[0], tangent[1], tangent[2]))
+                .normalize();
+                // The w component stores the handedness of the tangent basis
+                let bitangent = normal.cross(&world_tangent) * tangent[3];
+                vertex.tangent = world_tangent.into();
+                vertex.bitangent = bitangent.into();
+            }
+        }
+        None => compute_tangents(&mut vertices, &indices),
+    }
+
+    Ok(create_mesh(
+        device,
+        name,
+        &vertices,
+        &indices,
+        primitive.material().index().unwrap_or(default_material),
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn load_gltf_texture(
+    texture: gltf::Texture<'_>,
+    file_name: &str,
+    buffers: &[Vec<u8>],
+    base_dir: &Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    assets: &mut AssetServer,
+    kind: TextureKind,
+) -> anyhow::Result<MaterialMap> {
+    let image = texture.source();
+    // Embedded images have no path of their own, so key them by their index in the file
+    let key = match image.source() {
+        gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+            base_dir.join(uri).to_string_lossy().into_owned()
+        }
+        _ => format!("{}#image{}", file_name, image.index()),
+    };
+    let sampler = gltf_sampler(&texture.sampler(), assets.texture_filter);
+    if let Some(texture) = assets.cached_texture(&key, kind) {
+        return Ok(MaterialMap::new(texture, sampler));
+    }
+
+    let label = image.name().unwrap_or("glTF texture").to_string();
+    let bytes = match image.source() {
+        gltf::image::Source::View { view, .. } => buffers
+            .get(view.buffer().index())
+            .and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()))
+            .with_context(|| format!("glTF image {} is outside its buffer", image.index()))?
+            .to_vec(),
+        gltf::image::Source::Uri { uri, .. } => load_uri(uri, base_dir).await?,
+    };
+    let texture = Texture::from_bytes(
+        device,
+        queue,
+        &bytes,
+        &label,
+        kind,
+        assets.default_sampler(device),
+    )?;
+    Ok(MaterialMap::new(
+        assets.insert_texture(&key, kind, texture),
+        sampler,
+    ))
+}
+
+// Filters the file asks for explicitly override `filter`
+fn gltf_sampler(sampler: &gltf::texture::Sampler, filter: TextureFilter) -> SamplerOptions {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let filter = match (sampler.mag_filter(), sampler.min_filter()) {
+        (None, None) => filter,
+        (mag, min) => {
+            use wgpu::FilterMode::{Linear, Nearest};
+            let mag = match mag {
+                Some(MagFilter::Nearest) => Nearest,
+                Some(MagFilter::Linear) | None => Linear,
+            };
+            // Minification filters without a mipmap part only read the top mip
+            let (min, mipmap) = match min {
+                Some(MinFilter::Nearest) => (Nearest, None),
+                Some(MinFilter::Linear) => (Linear, None),
+                Some(MinFilter::NearestMipmapNearest) => (Nearest, Some(Nearest)),
+                Some(MinFilter::LinearMipmapNearest) => (Linear, Some(Nearest)),
+                Some(MinFilter::NearestMipmapLinear) => (Nearest, Some(Linear)),
+                Some(MinFilter::LinearMipmapLinear) | None => (Linear, Some(Linear)),
+            };
+            TextureFilter::Custom { mag, min, mipmap }
+        }
+    };
+    SamplerOptions {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        filter,
+    }
+}
+
+async fn load_uri(uri: &str, base_dir: &Path) -> anyhow::Result<Vec<u8>> {
+    match uri.strip_prefix("data:") {
+        Some(data_uri) => {
+            let (_, encoded) = data_uri
+                .split_once(";base64,")
+                .context("Only base64 data URIs are supported")?;
+            Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?)
+        }
+        None => {
+            let path = base_dir.join(uri);
+            load_binary(path.to_str().context("Invalid glTF URI")?).await
+        }
+    }
+}
+
+// Reads a material statement tobj doesn't know, like `Ke 1.0 0.5 0.0`
+fn parse_mtl_floats<const N: usize>(material: &tobj::Material, key: &str) -> Option<[f32; N]> {
+    let mut values = material.unknown_param.get(key)?.split_whitespace();
+    let mut parsed = [0.0; N];
+    for value in &mut parsed {
+        *value = values.next()?.parse().ok()?;
+    }
+    Some(parsed)
+}
+
+// Splits a texture statement like `map_Kd -clamp on -s 2 2 wood.png` into the
+// file name and the sampler its options ask for. Without `-clamp on` the
+// texture repeats.
+fn parse_mtl_map(map: &str, filter: TextureFilter) -> (&str, SamplerOptions) {
+    let mut sampler = SamplerOptions::repeat(filter);
+    let mut rest = map.trim();
+    while let Some(option) = rest.strip_prefix('-') {
+        let (name, mut args) = split_word(option);
+        // `-o`, `-s` and `-t` take one to three numbers, `-mm` two
+        let max_args = match name {
+            "o" | "s" | "t" => 3,
+            "mm" => 2,
+            _ => 1,
+        };
+        for i in 0..max_args {
+            let (arg, after) = split_word(args);
+            if i > 0 && arg.parse::<f32>().is_err() {
+                break;
+            }
+            if name == "clamp" && arg == "on" {
+                sampler = SamplerOptions::clamp(filter);
+            }
+            args = after;
+        }
+        rest = args;
+    }
+    (rest, sampler)
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+fn compute_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut normals = vec![na::Vector3::<f32>::zeros(); vertices.len()];
+    for c in indices.chunks(3) {
+        let pos0: na::Vector3<_> = vertices[c[0] as usize].position.into();
+        let pos1: na::Vector3<_> = vertices[c[1] as usize].position.into();
+        let pos2: na::Vector3<_> = vertices[c[2] as usize].position.into();
+
+        // Area weighted face normal
+        let face_normal = (pos1 - pos0).cross(&(pos2 - pos0));
+        for &i in c {
+            normals[i as usize] += face_normal;
+        }
+    }
+
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = normal
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(na::Vector3::y)
+            .into();
+    }
+}
+
+fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0; vertices.len()];
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: na::Vector3<_> = v0.position.into();
+        let pos1: na::Vector3<_> = v1.position.into();
+        let pos2: na::Vector3<_> = v2.position.into();
+
+        let uv0: na::Vector2<_> = v0.tex_coords.into();
+        let uv1: na::Vector2<_> = v1.tex_coords.into();
+        let uv2: na::Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // Triangles without UVs, or with UVs collapsed to a line, have no
+        // tangent direction to contribute
+        if det.abs() <= f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        vertices[c[0] as usize].tangent =
+            (tangent + na::Vector3::from(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent =
+            (tangent + na::Vector3::from(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent =
+            (tangent + na::Vector3::from(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent =
+            (bitangent + na::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent =
+            (bitangent + na::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent =
+            (bitangent + na::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+
+        // Used to average the tangents/bitangents
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+
+    // Average the tangents/bitangents
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let v = &mut vertices[i];
+        let tangent = na::Vector3::from(v.tangent) / n.max(1) as f32;
+        let bitangent = na::Vector3::from(v.bitangent) / n.max(1) as f32;
+        if n > 0 && tangent.norm() > f32::EPSILON && bitangent.norm() > f32::EPSILON {
+            v.tangent = tangent.into();
+            v.bitangent = bitangent.into();
+        } else {
+            // Normal maps can't be oriented here, but any basis around the
+            // normal keeps the shader's tangent matrix valid
+            let (tangent, bitangent) = orthonormal_basis(v.normal.into());
+            v.tangent = tangent.into();
+            v.bitangent = bitangent.into();
+        }
+    }
+}
+
+// Two unit vectors perpendicular to `normal` and to each other
+fn orthonormal_basis(normal: na::Vector3<f32>) -> (na::Vector3<f32>, na::Vector3<f32>) {
+    let normal = normal
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(na::Vector3::z);
+    let helper = if normal.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::y()
+    };
+    let tangent = helper.cross(&normal).normalize();
+    (tangent, normal.cross(&tangent))
+}
+
+fn create_mesh(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    material: usize,
+) -> Mesh {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex buffer", name)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index buffer", name)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+
+    Mesh {
+        name: name.to_string(),
+        vertex_buffer,
+        index_buffer,
+        num_elements: indices.len() as u32,
+        material,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords,
+            normal: [0.0, 0.0, 1.0],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        }
+    }
+
+    fn assert_basis(vertex: &ModelVertex) {
+        let normal = na::Vector3::from(vertex.normal);
+        let tangent = na::Vector3::from(vertex.tangent);
+        let bitangent = na::Vector3::from(vertex.bitangent);
+        assert!(tangent
+            .iter()
+            .chain(bitangent.iter())
+            .all(|c| c.is_finite()));
+        assert!((tangent.norm() - 1.0).abs() < 1e-5);
+        assert!((bitangent.norm() - 1.0).abs() < 1e-5);
+        assert!(tangent.dot(&normal).abs() < 1e-5);
+        assert!(bitangent.dot(&tangent).abs() < 1e-5);
+    }
+
+    #[test]
+    fn tangents_follow_the_uvs() {
+        let mut vertices = [
+            vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 0.0]),
+        ];
+        compute_tangents(&mut vertices, &[0, 1, 2]);
+        for vertex in &vertices {
+            assert_eq!(vertex.tangent, [1.0, 0.0, 0.0]);
+            assert_eq!(vertex.bitangent, [0.0, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_fall_back_to_a_basis_around_the_normal() {
+        let mut vertices = [
+            vertex([0.0, 0.0, 0.0], [0.0; 2]),
+            vertex([1.0, 0.0, 0.0], [0.0; 2]),
+            vertex([0.0, 1.0, 0.0], [0.0; 2]),
+            // Not referenced by any triangle
+            vertex([5.0, 5.0, 5.0], [0.0; 2]),
+        ];
+        compute_tangents(&mut vertices, &[0, 1, 2]);
+        vertices.iter().for_each(assert_basis);
+    }
+
+    #[test]
+    fn orthonormal_basis_handles_any_normal() {
+        for normal in [
+            na::Vector3::x(),
+            -na::Vector3::y(),
+            na::Vector3::new(1.0, 1.0, 1.0),
+            na::Vector3::zeros(),
+        ] {
+            let (tangent, bitangent) = orthonormal_basis(normal);
+            let mut vertex = vertex([0.0; 3], [0.0; 2]);
+            vertex.normal = normal
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(na::Vector3::z)
+                .into();
+            vertex.tangent = tangent.into();
+            vertex.bitangent = bitangent.into();
+            assert_basis(&vertex);
+        }
+    }
+
+    #[test]
+    fn gltf_samplers_keep_every_filter() {
+        use wgpu::FilterMode::{Linear, Nearest};
+
+        // 9728 is NEAREST, 9729 LINEAR and 9984 NEAREST_MIPMAP_NEAREST
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": {"version": "2.0"},
+                "samplers": [
+                    {"wrapS": 33648},
+                    {"magFilter": 9728, "minFilter": 9729},
+                    {"minFilter": 9984}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let samplers = gltf
+            .samplers()
+            .map(|sampler| gltf_sampler(&sampler, TextureFilter::Anisotropic(8)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(samplers[0].filter, TextureFilter::Anisotropic(8));
+        assert_eq!(samplers[0].address_mode_u, wgpu::AddressMode::MirrorRepeat);
+        assert_eq!(
+            samplers[1].filter,
+            TextureFilter::Custom {
+                mag: Nearest,
+                min: Linear,
+                mipmap: None,
+            }
+        );
+        assert_eq!(
+            samplers[2].filter,
+            TextureFilter::Custom {
+                mag: Linear,
+                min: Nearest,
+                mipmap: Some(Nearest),
+            }
+        );
+    }
 }
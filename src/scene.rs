@@ -0,0 +1,310 @@
+use std::{ops::Range, rc::Rc};
+
+use nalgebra as na;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    engine::InstanceRaw,
+    model::{DrawModel, Model},
+};
+
+const INITIAL_INSTANCE_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelId(usize);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub translation: na::Vector3<f32>,
+    pub rotation: na::UnitQuaternion<f32>,
+    pub scale: na::Vector3<f32>,
+}
+
+impl Transform {
+    pub fn new(
+        translation: na::Vector3<f32>,
+        rotation: na::UnitQuaternion<f32>,
+        scale: na::Vector3<f32>,
+    ) -> Self {
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn from_translation(translation: na::Vector3<f32>) -> Self {
+        Transform {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_matrix(&self) -> na::Matrix4<f32> {
+        na::Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * na::Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: na::Vector3::zeros(),
+            rotation: na::UnitQuaternion::identity(),
+            scale: na::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    pub model: Option<ModelId>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world_matrix: na::Matrix4<f32>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn world_matrix(&self) -> na::Matrix4<f32> {
+        self.world_matrix
+    }
+}
+
+pub struct Scene {
+    nodes: Vec<Option<Node>>,
+    // Slots of removed nodes, reused by `add_node`
+    free_nodes: Vec<usize>,
+    roots: Vec<NodeId>,
+    models: Vec<Rc<Model>>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    model_instances: Vec<Range<u32>>,
+}
+
+impl Scene {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Scene {
+            nodes: Vec::new(),
+            free_nodes: Vec::new(),
+            roots: Vec::new(),
+            models: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, INITIAL_INSTANCE_CAPACITY),
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+            model_instances: Vec::new(),
+        }
+    }
+
+    /// Adds a model to draw, either owned or shared from an `AssetServer`.
+    pub fn add_model(&mut self, model: impl Into<Rc<Model>>) -> ModelId {
+        self.models.push(model.into());
+        ModelId(self.models.len() - 1)
+    }
+
+    pub fn model(&self, id: ModelId) -> &Model {
+        &self.models[id.0]
+    }
+
+    /// Swaps every use of `old` for `new`, e.g. after an asset was hot reloaded.
+    pub fn replace_model(&mut self, old: &Rc<Model>, new: Rc<Model>) {
+        for model in &mut self.models {
+            if Rc::ptr_eq(model, old) {
+                *model = new.clone();
+            }
+        }
+    }
+
+    pub fn add_node(&mut self, name: &str, transform: Transform, parent: Option<NodeId>) -> NodeId {
+        let node = Node {
+            name: name.to_string(),
+            transform,
+            model: None,
+            parent,
+            children: Vec::new(),
+            world_matrix: na::Matrix4::identity(),
+        };
+        let id = match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index] = Some(node);
+                NodeId(index)
+            }
+            None => {
+                self.nodes.push(Some(node));
+                NodeId(self.nodes.len() - 1)
+            }
+        };
+
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    /// Removes a node, promoting its children to roots. Its id may be reused
+    /// by a later `add_node`.
+    pub fn remove_node(&mut self, id: NodeId) {
+        let node = match self.nodes[id.0].take() {
+            Some(node) => node,
+            None => return,
+        };
+        self.free_nodes.push(id.0);
+
+        match node.parent {
+            Some(parent) => self.node_mut(parent).children.retain(|&c| c != id),
+            None => self.roots.retain(|&r| r != id),
+        }
+        for child in node.children {
+            self.node_mut(child).parent = None;
+            self.roots.push(child);
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().unwrap()
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().unwrap()
+    }
+
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> anyhow::Result<()> {
+        let mut ancestor = parent;
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                anyhow::bail!(
+                    "Node {:?} cannot be parented to its own descendant",
+                    self.node(id).name
+                );
+            }
+            ancestor = self.node(ancestor_id).parent;
+        }
+
+        match self.node(id).parent {
+            Some(old_parent) => self.node_mut(old_parent).children.retain(|&c| c != id),
+            None => self.roots.retain(|&r| r != id),
+        }
+        match parent {
+            Some(new_parent) => self.node_mut(new_parent).children.push(id),
+            None => self.roots.push(id),
+        }
+        self.node_mut(id).parent = parent;
+
+        Ok(())
+    }
+
+    pub fn update_world_transforms(&mut self) {
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|&root| (root, na::Matrix4::identity()))
+            .collect::<Vec<_>>();
+
+        while let Some((id, parent_matrix)) = stack.pop() {
+            let node = self.node_mut(id);
+            node.world_matrix = parent_matrix * node.transform.to_matrix();
+            let world_matrix = node.world_matrix;
+            stack.extend(node.children.iter().map(|&child| (child, world_matrix)));
+        }
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.update_world_transforms();
+
+        // Instances are grouped per model so each model is a single instanced draw
+        let mut instance_data = Vec::new();
+        self.model_instances = (0..self.models.len())
+            .map(|model_index| {
+                let start = instance_data.len() as u32;
+                instance_data.extend(
+                    self.nodes
+                        .iter()
+                        .flatten()
+                        .filter(|node| node.model == Some(ModelId(model_index)))
+                        .map(|node| InstanceRaw::from_matrix(node.world_matrix)),
+                );
+                start..instance_data.len() as u32
+            })
+            .collect();
+
+        if instance_data.len() > self.instance_capacity {
+            self.instance_capacity = instance_data.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        if !instance_data.is_empty() {
+            queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&instance_data),
+            );
+        }
+    }
+
+    pub fn instance_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    /// Each model paired with the instances drawing it since the last `update`.
+    pub fn instanced_models(&self) -> impl Iterator<Item = (&Model, Range<u32>)> {
+        self.models
+            .iter()
+            .zip(&self.model_instances)
+            .filter(|(_, instances)| !instances.is_empty())
+            .map(|(model, instances)| (model.as_ref(), instances.clone()))
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        let instance_data = vec![InstanceRaw::from_matrix(na::Matrix4::identity()); capacity];
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Scene instance buffer"),
+            contents: bytemuck::cast_slice(&instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+}
+
+pub trait DrawScene<'a> {
+    fn draw_scene(
+        &mut self,
+        scene: &'a Scene,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawScene<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_scene(
+        &mut self,
+        scene: &'b Scene,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(1, scene.instance_buffer.slice(..));
+        for (model, instances) in scene.models.iter().zip(&scene.model_instances) {
+            if !instances.is_empty() {
+                self.draw_model_instanced(
+                    model,
+                    instances.clone(),
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,719 @@
+use std::num::NonZeroU64;
+
+use nalgebra as na;
+
+use crate::{
+    camera::Camera,
+    ecs::{Entity, Light, LightKind},
+    engine::InstanceRaw,
+    model::{ModelVertex, Vertex},
+    pipeline::PipelineBuilder,
+    resources,
+    scene::{Scene, Transform},
+    texture::Texture,
+};
+
+/// Most layers in the shadow map array, shared by all shadow-casting lights.
+/// The array only grows as large as the casters in the scene need. Must match `MAX_SHADOW_MAPS` in `shader.wgsl`.
+pub const MAX_SHADOW_MAPS: usize = 8;
+pub const MAX_CASCADES: u32 = 4;
+/// Point lights with a cube shadow map at the same time.
+pub const MAX_POINT_SHADOWS: usize = 4;
+
+// Each layer's matrix sits in its own 256 byte slot for dynamic offsets
+const PASS_UNIFORM_STRIDE: wgpu::BufferAddress = 256;
+// The view projection, light position and radius of one cube face
+const POINT_PASS_UNIFORM_SIZE: u64 = 80;
+
+// nalgebra builds OpenGL projections with depth in -1..1, wgpu expects 0..1
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: na::Matrix4<f32> = na::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.5,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    /// Width and height of every shadow map layer.
+    pub resolution: u32,
+    /// Constant depth bias applied while rendering shadow maps, in depth buffer units.
+    pub depth_bias: i32,
+    /// Depth bias scaled by the slope of the rendered triangle.
+    pub slope_bias: f32,
+    /// Shadow map texels sampled in each direction around the lookup, 0 for a
+    /// single tap. Point shadows always take 20 taps spread over this radius.
+    pub pcf_radius: u32,
+    /// Cascades used by each directional light, up to `MAX_CASCADES`.
+    pub cascade_count: u32,
+    /// Blends cascade splits between uniform (0.0) and logarithmic (1.0) spacing.
+    pub cascade_split_lambda: f32,
+    /// Directional shadows end this far from the camera.
+    pub max_distance: f32,
+    /// Width and height of every cube face of a point light shadow map.
+    pub point_resolution: u32,
+    /// Subtracted from the linear depth of point shadow lookups, as a fraction of the light radius.
+    pub point_depth_bias: f32,
+    /// Point shadow maps rerendered per frame. The rest keep last frame's map,
+    /// starting with the ones updated longest ago next frame.
+    pub point_shadow_budget: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 2048,
+            depth_bias: 2,
+            slope_bias: 2.0,
+            pcf_radius: 1,
+            cascade_count: 3,
+            cascade_split_lambda: 0.75,
+            max_distance: 100.0,
+            point_resolution: 1024,
+            point_depth_bias: 0.003,
+            point_shadow_budget: 2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    pub view_proj: [[[f32; 4]; 4]; MAX_SHADOW_MAPS],
+    pub cascade_count: u32,
+    pub pcf_radius: u32,
+    pub texel_size: f32,
+    pub point_depth_bias: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct CubeFaceUniform {
+    view_proj: [[f32; 4]; 4],
+    light_position: [f32; 3],
+    radius: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PointSlot {
+    entity: Entity,
+    // Frame the cube map was last rendered, None until the first time
+    last_update: Option<u64>,
+}
+
+pub struct Shadows {
+    pub settings: ShadowSettings,
+    pub map: Texture,
+    layer_views: Vec<wgpu::TextureView>,
+    /// One cube per point shadow slot, indexed by the light's `shadow_index`.
+    /// A single empty cube without `point_shadows`.
+    pub point_map: Texture,
+    point_face_views: Vec<wgpu::TextureView>,
+    pub uniform: ShadowUniform,
+    pub uniform_buffer: wgpu::Buffer,
+    active_layers: usize,
+    pass_buffer: wgpu::Buffer,
+    pass_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    point_slots: [Option<PointSlot>; MAX_POINT_SHADOWS],
+    point_updates: Vec<usize>,
+    point_pass_buffer: wgpu::Buffer,
+    point_pass_bind_group: wgpu::BindGroup,
+    point_pipeline: wgpu::RenderPipeline,
+    frame: u64,
+    /// False on adapters without cube array textures, where point lights
+    /// cast no shadows.
+    pub point_shadows: bool,
+}
+
+impl Shadows {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings, point_shadows: bool) -> Self {
+        // Single texel placeholders for the light bind group, replaced by
+        // `reserve` once a light casts shadows
+        let map = Texture::create_shadow_map(device, 1, 1, "shadow_map");
+        let point_map = if point_shadows {
+            Texture::create_point_shadow_map(device, 1, 1, "point_shadow_map")
+        } else {
+            Texture::create_point_shadow_cube(device, 1, "point_shadow_map")
+        };
+
+        let uniform = ShadowUniform {
+            view_proj: [na::Matrix4::identity().into(); MAX_SHADOW_MAPS],
+            cascade_count: settings.cascade_count.clamp(1, MAX_CASCADES),
+            pcf_radius: settings.pcf_radius,
+            texel_size: 1.0 / settings.resolution as f32,
+            point_depth_bias: settings.point_depth_bias,
+        };
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow uniform buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pass_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow pass buffer"),
+            size: PASS_UNIFORM_STRIDE * MAX_SHADOW_MAPS as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (pass_bind_group_layout, pass_bind_group) = create_pass_bind_group(
+            device,
+            "shadow_pass",
+            &pass_buffer,
+            64,
+            wgpu::ShaderStages::VERTEX,
+        );
+        let shader = device.create_shader_module(resources::load_shader(
+            "shadow.wgsl",
+            include_str!("../shaders/shadow.wgsl"),
+        ));
+        let pipeline = create_pipeline(
+            device,
+            "Shadow pipeline",
+            &pass_bind_group_layout,
+            &shader,
+            false,
+            wgpu::DepthBiasState {
+                constant: settings.depth_bias,
+                slope_scale: settings.slope_bias,
+                clamp: 0.0,
+            },
+        );
+
+        let point_pass_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point shadow pass buffer"),
+            size: PASS_UNIFORM_STRIDE * (MAX_POINT_SHADOWS * 6) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (point_pass_bind_group_layout, point_pass_bind_group) = create_pass_bind_group(
+            device,
+            "point_shadow_pass",
+            &point_pass_buffer,
+            POINT_PASS_UNIFORM_SIZE,
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        );
+        let point_shader = device.create_shader_module(resources::load_shader(
+            "point_shadow.wgsl",
+            include_str!("../shaders/point_shadow.wgsl"),
+        ));
+        // Written depth is linear, so the bias is applied in shader.wgsl instead
+        let point_pipeline = create_pipeline(
+            device,
+            "Point shadow pipeline",
+            &point_pass_bind_group_layout,
+            &point_shader,
+            true,
+            wgpu::DepthBiasState::default(),
+        );
+
+        Shadows {
+            settings,
+            map,
+            layer_views: Vec::new(),
+            point_map,
+            point_face_views: Vec::new(),
+            uniform,
+            uniform_buffer,
+            active_layers: 0,
+            pass_buffer,
+            pass_bind_group,
+            pipeline,
+            point_slots: [None; MAX_POINT_SHADOWS],
+            point_updates: Vec::new(),
+            point_pass_buffer,
+            point_pass_bind_group,
+            point_pipeline,
+            frame: 0,
+            point_shadows,
+        }
+    }
+
+    /// Grows the shadow maps to fit the layers and cube slots `lights` need,
+    /// up to `MAX_SHADOW_MAPS` and `MAX_POINT_SHADOWS`. Returns true when
+    /// the maps were recreated, so bind groups using them need recreating too.
+    pub fn reserve(
+        &mut self,
+        device: &wgpu::Device,
+        lights: &[(Entity, Transform, Light)],
+    ) -> bool {
+        let cascade_count = self.uniform.cascade_count as usize;
+        let mut layers = 0;
+        let mut cubes = 0;
+        for (_, _, light) in lights.iter().filter(|(_, _, light)| light.cast_shadows) {
+            let needed = match light.kind {
+                LightKind::Directional => cascade_count,
+                LightKind::Spot { .. } => 1,
+                LightKind::Point { .. } => {
+                    cubes += 1;
+                    continue;
+                }
+            };
+            // Same order as `update`, which skips lights that don't fit
+            if layers + needed <= MAX_SHADOW_MAPS {
+                layers += needed;
+            }
+        }
+        let cubes = if self.point_shadows {
+            cubes.min(MAX_POINT_SHADOWS)
+        } else {
+            0
+        };
+
+        let mut changed = false;
+        if layers > self.layer_views.len() {
+            self.map = Texture::create_shadow_map(
+                device,
+                self.settings.resolution,
+                layers as u32,
+                "shadow_map",
+            );
+            self.layer_views = face_views(&self.map, layers as u32, "shadow_map_layer");
+            changed = true;
+        }
+        if cubes > self.point_face_views.len() / 6 {
+            self.point_map = Texture::create_point_shadow_map(
+                device,
+                self.settings.point_resolution,
+                cubes as u32,
+                "point_shadow_map",
+            );
+            self.point_face_views =
+                face_views(&self.point_map, cubes as u32 * 6, "point_shadow_map_face");
+            // The new cubes start out empty
+            for slot in self.point_slots.iter_mut().flatten() {
+                slot.last_update = None;
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    /// Assigns shadow map layers to shadow-casting directional and spot
+    /// lights in order until they run out, and cube map slots to point
+    /// lights. Returns each light's first layer or cube slot, or -1 when it
+    /// has none.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: Option<&Camera>,
+        lights: &[(Entity, Transform, Light)],
+    ) -> Vec<i32> {
+        self.frame += 1;
+        self.update_point_slots(queue, lights);
+
+        let cascade_count = self.uniform.cascade_count as usize;
+        let mut layer = 0;
+        let mut shadow_indices = Vec::with_capacity(lights.len());
+        for (entity, transform, light) in lights {
+            if let LightKind::Point { .. } = light.kind {
+                let slot = self.point_slots.iter().position(|slot| {
+                    slot.is_some_and(|slot| slot.entity == *entity && slot.last_update.is_some())
+                });
+                shadow_indices.push(slot.map_or(-1, |slot| slot as i32));
+                continue;
+            }
+
+            let direction = transform.rotation * -na::Vector3::z();
+            let matrices = match (light.cast_shadows, light.kind, camera) {
+                (true, LightKind::Directional, Some(camera)) => {
+                    self.cascade_matrices(camera, &direction)
+                }
+                (
+                    true,
+                    LightKind::Spot {
+                        radius,
+                        outer_angle,
+                        ..
+                    },
+                    _,
+                ) => vec![spot_matrix(
+                    &transform.translation,
+                    &direction,
+                    radius,
+                    outer_angle,
+                )],
+                _ => Vec::new(),
+            };
+
+            if matrices.is_empty() || layer + matrices.len() > self.layer_views.len() {
+                shadow_indices.push(-1);
+                continue;
+            }
+            debug_assert!(matrices.len() == 1 || matrices.len() == cascade_count);
+            shadow_indices.push(layer as i32);
+            for matrix in matrices {
+                self.uniform.view_proj[layer] = matrix.into();
+                layer += 1;
+            }
+        }
+        self.active_layers = layer;
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+        for (i, view_proj) in self.uniform.view_proj[..layer].iter().enumerate() {
+            queue.write_buffer(
+                &self.pass_buffer,
+                i as wgpu::BufferAddress * PASS_UNIFORM_STRIDE,
+                bytemuck::cast_slice(&[*view_proj]),
+            );
+        }
+
+        shadow_indices
+    }
+
+    // Keeps each shadow-casting point light in the same slot across frames and
+    // picks the slots to rerender within the budget
+    fn update_point_slots(&mut self, queue: &wgpu::Queue, lights: &[(Entity, Transform, Light)]) {
+        let casters = lights
+            .iter()
+            .filter_map(|(entity, transform, light)| match light.kind {
+                LightKind::Point { radius } if light.cast_shadows => {
+                    Some((*entity, transform.translation, radius))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        for slot in &mut self.point_slots {
+            if slot.is_some_and(|slot| !casters.iter().any(|(entity, ..)| *entity == slot.entity)) {
+                *slot = None;
+            }
+        }
+        for (entity, ..) in &casters {
+            if self
+                .point_slots
+                .iter()
+                .any(|slot| slot.is_some_and(|slot| slot.entity == *entity))
+            {
+                continue;
+            }
+            let cubes = self.point_face_views.len() / 6;
+            if let Some(free) = self.point_slots[..cubes]
+                .iter_mut()
+                .find(|slot| slot.is_none())
+            {
+                *free = Some(PointSlot {
+                    entity: *entity,
+                    last_update: None,
+                });
+            }
+        }
+
+        // Slots that were never rendered come first, then the stalest ones
+        let mut updates = (0..MAX_POINT_SHADOWS)
+            .filter(|&i| self.point_slots[i].is_some())
+            .collect::<Vec<_>>();
+        updates.sort_by_key(|&i| self.point_slots[i].map(|slot| slot.last_update));
+        updates.truncate(self.settings.point_shadow_budget as usize);
+
+        for &i in &updates {
+            let Some(slot) = &mut self.point_slots[i] else {
+                continue;
+            };
+            slot.last_update = Some(self.frame);
+            let (_, position, radius) = casters
+                .iter()
+                .find(|(entity, ..)| *entity == slot.entity)
+                .copied()
+                .expect("Slot light is a caster");
+            for (face, view_proj) in cube_face_matrices(&position, radius).iter().enumerate() {
+                let uniform = CubeFaceUniform {
+                    view_proj: (*view_proj).into(),
+                    light_position: position.into(),
+                    // Matches the far plane, which keeps the depth divide finite
+                    radius: radius.max(0.1),
+                };
+                queue.write_buffer(
+                    &self.point_pass_buffer,
+                    (i * 6 + face) as wgpu::BufferAddress * PASS_UNIFORM_STRIDE,
+                    bytemuck::cast_slice(&[uniform]),
+                );
+            }
+        }
+        self.point_updates = updates;
+    }
+
+    /// Renders the depth of the scene into every shadow map layer in use and
+    /// the point shadow cube maps due this frame.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, scene: &Scene) {
+        for (layer, view) in self.layer_views[..self.active_layers].iter().enumerate() {
+            render_depth(
+                encoder,
+                "Shadow pass",
+                view,
+                &self.pipeline,
+                scene,
+                &self.pass_bind_group,
+                (layer as wgpu::BufferAddress * PASS_UNIFORM_STRIDE) as u32,
+            );
+        }
+        for &slot in &self.point_updates {
+            for face in slot * 6..slot * 6 + 6 {
+                render_depth(
+                    encoder,
+                    "Point shadow pass",
+                    &self.point_face_views[face],
+                    &self.point_pipeline,
+                    scene,
+                    &self.point_pass_bind_group,
+                    (face as wgpu::BufferAddress * PASS_UNIFORM_STRIDE) as u32,
+                );
+            }
+        }
+    }
+
+    // Splits the camera frustum and fits an orthographic projection around each slice
+    fn cascade_matrices(
+        &self,
+        camera: &Camera,
+        direction: &na::Vector3<f32>,
+    ) -> Vec<na::Matrix4<f32>> {
+        let count = self.uniform.cascade_count;
+        let near = camera.znear;
+        let far = camera.zfar.min(self.settings.max_distance).max(near + 0.01);
+        let lambda = self.settings.cascade_split_lambda;
+
+        let mut matrices = Vec::with_capacity(count as usize);
+        let mut split_near = near;
+        for i in 1..=count {
+            let p = i as f32 / count as f32;
+            let log_split = near * (far / near).powf(p);
+            let uniform_split = near + (far - near) * p;
+            let split_far = lambda * log_split + (1.0 - lambda) * uniform_split;
+
+            let projection =
+                na::Perspective3::new(camera.aspect, camera.fovy, split_near, split_far);
+            let inverse = (projection.as_matrix() * camera.view_matrix)
+                .try_inverse()
+                .unwrap_or_else(na::Matrix4::identity);
+            let corners = [-1.0, 1.0]
+                .iter()
+                .flat_map(|&x| [-1.0, 1.0].map(move |y| (x, y)))
+                .flat_map(|(x, y)| [-1.0, 1.0].map(move |z| na::Vector4::new(x, y, z, 1.0)))
+                .map(|corner| {
+                    let world = inverse * corner;
+                    world.xyz() / world.w
+                })
+                .collect::<Vec<_>>();
+
+            // A bounding sphere keeps the cascade size fixed as the camera turns
+            let center = corners.iter().sum::<na::Vector3<f32>>() / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .map(|corner| (corner - center).norm())
+                .fold(0.0_f32, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            let eye = na::Point3::from(center - direction * radius);
+            let view = na::Matrix4::look_at_rh(&eye, &na::Point3::from(center), &up_for(direction));
+            // Casters behind the slice still need to land in the map
+            let mut ortho = *na::Orthographic3::new(
+                -radius,
+                radius,
+                -radius,
+                radius,
+                -self.settings.max_distance,
+                2.0 * radius,
+            )
+            .as_matrix();
+
+            // Snap to whole texels so shadow edges don't shimmer while the camera moves
+            let origin = ortho * view * na::Vector4::new(0.0, 0.0, 0.0, 1.0);
+            let half_resolution = self.settings.resolution as f32 / 2.0;
+            let snapped = (origin.xy() * half_resolution).map(f32::round) / half_resolution;
+            let offset = snapped - origin.xy();
+            ortho[(0, 3)] += offset.x;
+            ortho[(1, 3)] += offset.y;
+
+            matrices.push(OPENGL_TO_WGPU_MATRIX * ortho * view);
+            split_near = split_far;
+        }
+        matrices
+    }
+}
+
+fn spot_matrix(
+    position: &na::Vector3<f32>,
+    direction: &na::Vector3<f32>,
+    radius: f32,
+    outer_angle: f32,
+) -> na::Matrix4<f32> {
+    let eye = na::Point3::from(*position);
+    let view = na::Matrix4::look_at_rh(&eye, &(eye + direction), &up_for(direction));
+    let fovy = (outer_angle * 2.0).clamp(0.01, std::f32::consts::PI - 0.01);
+    let projection = na::Perspective3::new(1.0, fovy, 0.05, radius.max(0.1));
+    OPENGL_TO_WGPU_MATRIX * projection.as_matrix() * view
+}
+
+// Faces in +X, -X, +Y, -Y, +Z, -Z order, seen from a light with a 90 degree field of view
+fn cube_face_matrices(position: &na::Vector3<f32>, radius: f32) -> [na::Matrix4<f32>; 6] {
+    let eye = na::Point3::from(*position);
+    // Cube face texel rows run down the face while wgpu clip space points up
+    let flip_y = na::Matrix4::new_nonuniform_scaling(&na::Vector3::new(1.0, -1.0, 1.0));
+    let projection = flip_y
+        * OPENGL_TO_WGPU_MATRIX
+        * na::Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 0.05, radius.max(0.1))
+            .as_matrix();
+    let faces = [
+        (na::Vector3::x(), -na::Vector3::y()),
+        (-na::Vector3::x(), -na::Vector3::y()),
+        (na::Vector3::y(), na::Vector3::z()),
+        (-na::Vector3::y(), -na::Vector3::z()),
+        (na::Vector3::z(), -na::Vector3::y()),
+        (-na::Vector3::z(), -na::Vector3::y()),
+    ];
+    faces.map(|(direction, up)| projection * na::Matrix4::look_at_rh(&eye, &(eye + direction), &up))
+}
+
+// Single layer views of `texture` for rendering into, one per shadow map layer
+// or cube face
+fn face_views(texture: &Texture, layers: u32, label: &str) -> Vec<wgpu::TextureView> {
+    (0..layers)
+        .map(|layer| {
+            texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some(label),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn create_pass_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    buffer: &wgpu::Buffer,
+    size: u64,
+    visibility: wgpu::ShaderStages,
+) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(&format!("{}_bind_group_layout", label)),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: NonZeroU64::new(size),
+            },
+            count: None,
+        }],
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{}_bind_group", label)),
+        layout: &layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer,
+                offset: 0,
+                size: NonZeroU64::new(size),
+            }),
+        }],
+    });
+    (layout, bind_group)
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    writes_depth: bool,
+    bias: wgpu::DepthBiasState,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let vertex_layouts = [ModelVertex::desc(), InstanceRaw::desc()];
+    let builder = PipelineBuilder::new(&layout, shader)
+        .label(label)
+        .vertex_layouts(&vertex_layouts)
+        // Open meshes like the blade still need to cast shadows from both sides
+        .cull_mode(None)
+        .depth(Texture::DEPTH_FORMAT)
+        .depth_compare(wgpu::CompareFunction::LessEqual)
+        .depth_bias(bias);
+    // Point lights keep a fragment stage without color targets, only there to
+    // write frag_depth
+    let builder = if writes_depth {
+        builder
+    } else {
+        builder.without_fragment()
+    };
+    builder.build(device).pipeline
+}
+
+fn render_depth(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    scene: &Scene,
+    bind_group: &wgpu::BindGroup,
+    offset: u32,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.draw_scene_shadow(scene, bind_group, offset);
+}
+
+fn up_for(direction: &na::Vector3<f32>) -> na::Vector3<f32> {
+    if direction.normalize().y.abs() > 0.99 {
+        na::Vector3::z()
+    } else {
+        na::Vector3::y()
+    }
+}
+
+pub trait DrawShadow<'a> {
+    fn draw_scene_shadow(
+        &mut self,
+        scene: &'a Scene,
+        shadow_bind_group: &'a wgpu::BindGroup,
+        offset: u32,
+    );
+}
+
+impl<'a, 'b> DrawShadow<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_scene_shadow(
+        &mut self,
+        scene: &'b Scene,
+        shadow_bind_group: &'b wgpu::BindGroup,
+        offset: u32,
+    ) {
+        self.set_bind_group(0, shadow_bind_group, &[offset]);
+        self.set_vertex_buffer(1, scene.instance_buffer().slice(..));
+        for (model, instances) in scene.instanced_models() {
+            for mesh in &model.meshes {
+                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+        }
+    }
+}
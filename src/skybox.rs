@@ -0,0 +1,277 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    environment,
+    pipeline::{BlendMode, PipelineBuilder},
+    resources,
+    texture::{Texture, TextureKind},
+};
+
+/// What is drawn behind the scene.
+#[derive(Clone)]
+pub enum Sky {
+    /// Clears to a flat color without a skybox pass.
+    Color(wgpu::Color),
+    /// A procedural sky fading from `horizon` up to `zenith` and down to `ground`.
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    /// A cube map, e.g. from `load_cubemap`, `load_equirect` or `Environment::cubemap`.
+    Cubemap(Rc<Texture>),
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky::Color(wgpu::Color {
+            r: 0.1,
+            g: 0.2,
+            b: 0.3,
+            a: 1.0,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    zenith: [f32; 3],
+    mode: u32,
+    horizon: [f32; 3],
+    _padding: u32,
+    ground: [f32; 3],
+    _padding2: u32,
+}
+
+// Must match the constants in `skybox.wgsl`
+const SKY_CUBEMAP: u32 = 0;
+const SKY_GRADIENT: u32 = 1;
+
+pub struct Skybox {
+    sky: Sky,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // Bound in place of a cube map by the other kinds of sky
+    placeholder: Texture,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sky: Sky,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("skybox_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox uniform buffer"),
+            contents: bytemuck::cast_slice(&[SkyUniform::new(&sky)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let placeholder = Texture::create_cube(
+            device,
+            1,
+            1,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::TextureUsages::COPY_DST,
+            "skybox_placeholder",
+        );
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            sky_cubemap(&sky, &placeholder),
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(resources::load_shader(
+            "skybox.wgsl",
+            include_str!("../shaders/skybox.wgsl"),
+        ));
+        let pipeline = PipelineBuilder::new(&pipeline_layout, &shader)
+            .label("Skybox pipeline")
+            .color_target(color_format, BlendMode::Replace)
+            .cull_mode(None)
+            // Drawn first and left out of the depth buffer, so everything else covers it
+            .depth(Texture::DEPTH_FORMAT)
+            .depth_compare(wgpu::CompareFunction::Always)
+            .depth_write(false)
+            .sample_count(sample_count)
+            .build(device)
+            .pipeline;
+
+        Skybox {
+            sky,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            placeholder,
+            pipeline,
+        }
+    }
+
+    pub fn sky(&self) -> &Sky {
+        &self.sky
+    }
+
+    pub fn set_sky(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, sky: Sky) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            sky_cubemap(&sky, &self.placeholder),
+        );
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyUniform::new(&sky)]),
+        );
+        self.sky = sky;
+    }
+
+    /// The color the render pass clears to before the skybox is drawn.
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self.sky {
+            Sky::Color(color) => color,
+            _ => wgpu::Color::BLACK,
+        }
+    }
+}
+
+impl SkyUniform {
+    fn new(sky: &Sky) -> Self {
+        let (mode, zenith, horizon, ground) = match *sky {
+            Sky::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => (SKY_GRADIENT, zenith, horizon, ground),
+            _ => (SKY_CUBEMAP, [0.0; 3], [0.0; 3], [0.0; 3]),
+        };
+        SkyUniform {
+            zenith,
+            mode,
+            horizon,
+            _padding: 0,
+            ground,
+            _padding2: 0,
+        }
+    }
+}
+
+fn sky_cubemap<'a>(sky: &'a Sky, placeholder: &'a Texture) -> &'a Texture {
+    match sky {
+        Sky::Cubemap(cubemap) => cubemap,
+        _ => placeholder,
+    }
+}
+
+/// Loads six square images into a cube map, in +X, -X, +Y, -Y, +Z, -Z order.
+pub async fn load_cubemap(
+    file_names: [&str; 6],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let mut faces = Vec::with_capacity(6);
+    for file_name in file_names {
+        let data = resources::load_binary(file_name).await?;
+        faces.push(image::load_from_memory(&data)?);
+    }
+    Texture::from_cube_images(device, queue, &faces, file_names[0], TextureKind::Color)
+}
+
+/// Loads an equirectangular `.hdr` image as a cube map with `size` texel faces.
+pub async fn load_equirect(
+    file_name: &str,
+    size: u32,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Texture> {
+    let image = resources::load_hdr(file_name).await?;
+    environment::equirect_to_cubemap(device, queue, &image, size)
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    cubemap: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("skybox_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&cubemap.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+            },
+        ],
+    })
+}
+
+pub trait DrawSkybox<'a> {
+    fn draw_skybox(&mut self, skybox: &'a Skybox, camera_bind_group: &'a wgpu::BindGroup);
+}
+
+impl<'a, 'b> DrawSkybox<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_skybox(&mut self, skybox: &'b Skybox, camera_bind_group: &'b wgpu::BindGroup) {
+        if let Sky::Color(_) = skybox.sky {
+            return;
+        }
+        self.set_pipeline(&skybox.pipeline);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, &skybox.bind_group, &[]);
+        self.draw(0..3, 0..1);
+    }
+}
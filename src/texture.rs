@@ -1,34 +1,300 @@
+use std::{collections::HashMap, rc::Rc};
+
 use anyhow::*;
 use image::GenericImageView;
+use nalgebra as na;
+
+use crate::compressed::CompressedImage;
+
+/// How a texture is sampled between texels and between mip levels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureFilter {
+    /// Nearest texel from the nearest mip, for pixel art.
+    Nearest,
+    /// Linear between texels, nearest mip.
+    Bilinear,
+    /// Linear between texels and mips.
+    #[default]
+    Trilinear,
+    /// Trilinear with up to the given number of samples along oblique views,
+    /// clamped to 1..=16.
+    Anisotropic(u16),
+    /// Separate filters for magnification, minification and between mips, as
+    /// glTF samplers specify them. Without a mip filter only the top mip is read.
+    Custom {
+        mag: wgpu::FilterMode,
+        min: wgpu::FilterMode,
+        mipmap: Option<wgpu::FilterMode>,
+    },
+}
+
+/// What the texels of an image hold, which decides its format and how its
+/// mips are filtered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureKind {
+    /// sRGB colors, averaged in linear space so mips don't darken.
+    Color,
+    /// Linear data like metallic, roughness or occlusion, averaged as is.
+    Linear,
+    /// Tangent space normals, renormalized after averaging.
+    Normal,
+}
+
+impl TextureKind {
+    /// The 8-bit format images of this kind are uploaded as.
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureKind::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureKind::Linear | TextureKind::Normal => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Everything that makes one sampler differ from another, so textures can
+/// share samplers through a `SamplerCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub filter: TextureFilter,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self::clamp(TextureFilter::default())
+    }
+}
+
+impl SamplerOptions {
+    pub fn clamp(filter: TextureFilter) -> Self {
+        Self::with_address_mode(wgpu::AddressMode::ClampToEdge, filter)
+    }
+
+    /// Tiles the texture, e.g. for floors.
+    pub fn repeat(filter: TextureFilter) -> Self {
+        Self::with_address_mode(wgpu::AddressMode::Repeat, filter)
+    }
+
+    pub fn with_address_mode(address_mode: wgpu::AddressMode, filter: TextureFilter) -> Self {
+        SamplerOptions {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            filter,
+        }
+    }
+
+    pub fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        use wgpu::FilterMode::{Linear, Nearest};
+
+        let (mag_filter, min_filter, mipmap_filter, anisotropy_clamp) = match self.filter {
+            TextureFilter::Nearest => (Nearest, Nearest, Some(Nearest), 1),
+            TextureFilter::Bilinear => (Linear, Linear, Some(Nearest), 1),
+            TextureFilter::Trilinear => (Linear, Linear, Some(Linear), 1),
+            TextureFilter::Anisotropic(samples) => {
+                (Linear, Linear, Some(Linear), samples.clamp(1, 16))
+            }
+            TextureFilter::Custom { mag, min, mipmap } => (mag, min, mipmap, 1),
+        };
+        let defaults = wgpu::SamplerDescriptor::default();
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mag_filter,
+            min_filter,
+            mipmap_filter: mipmap_filter.unwrap_or(Nearest),
+            lod_max_clamp: if mipmap_filter.is_some() {
+                defaults.lod_max_clamp
+            } else {
+                0.0
+            },
+            anisotropy_clamp,
+            ..defaults
+        })
+    }
+}
+
+/// Hands out one shared sampler per distinct `SamplerOptions`.
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerOptions, Rc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, options: SamplerOptions) -> Rc<wgpu::Sampler> {
+        self.samplers
+            .entry(options)
+            .or_insert_with(|| Rc::new(options.create_sampler(device)))
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+}
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    /// Possibly shared with other textures, see `SamplerCache`.
+    pub sampler: Rc<wgpu::Sampler>,
 }
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// The scene is lit into this format and tonemapped into the output afterwards.
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
+    /// Decodes a PNG or JPEG, or uploads a KTX2 or DDS file as it is.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
-        is_normal_map: bool,
+        kind: TextureKind,
+        sampler: Rc<wgpu::Sampler>,
     ) -> Result<Self> {
+        if CompressedImage::is_container(bytes) {
+            let image = CompressedImage::from_bytes(bytes, kind)
+                .with_context(|| format!("Failed to read {label}"))?;
+            return Self::from_compressed(device, queue, &image, label, sampler);
+        }
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Self::from_image(device, queue, &img, Some(label), kind, sampler)
+    }
+
+    /// Uploads pre-baked mips, decoding them on the CPU first when the device
+    /// lacks the feature for their format.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: &str,
+        sampler: Rc<wgpu::Sampler>,
+    ) -> Result<Self> {
+        let decompressed;
+        let image = if device.features().contains(image.format.required_features()) {
+            image
+        } else {
+            decompressed = image.decompress().with_context(|| {
+                format!(
+                    "{label} is {:?}, which needs {:?}",
+                    image.format,
+                    image.format.required_features()
+                )
+            })?;
+            &decompressed
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: image.size,
+            mip_level_count: image.levels.len() as u32,
+            sample_count: 1,
+            dimension: image.texture_dimension(),
+            format: image.format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let (block_width, block_height) = image.format.block_dimensions();
+        for (mip_level, data) in image.levels.iter().enumerate() {
+            let size = image
+                .level_size(mip_level as u32)
+                .physical_size(image.format);
+            let blocks_wide = size.width / block_width;
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(blocks_wide * image.format.block_size(None).unwrap_or(4)),
+                    rows_per_image: Some(size.height / block_height),
+                },
+                size,
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(image.dimension),
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        kind: TextureKind,
+        sampler: Rc<wgpu::Sampler>,
+    ) -> Result<Self> {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image(device, queue, &img, Some(label), kind, sampler)
+    }
+
+    pub fn default_diffuse(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: Rc<wgpu::Sampler>,
+    ) -> Result<Self> {
+        Self::from_color(
+            device,
+            queue,
+            [255, 255, 255, 255],
+            "default_diffuse",
+            TextureKind::Color,
+            sampler,
+        )
     }
 
+    pub fn default_normal(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: Rc<wgpu::Sampler>,
+    ) -> Result<Self> {
+        Self::from_color(
+            device,
+            queue,
+            [128, 128, 255, 255],
+            "default_normal",
+            TextureKind::Normal,
+            sampler,
+        )
+    }
+
+    /// Uploads `img` with a full mip chain, e.g. sampled with a sampler from a
+    /// `SamplerCache`.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
-        is_normal_map: bool,
+        kind: TextureKind,
+        sampler: Rc<wgpu::Sampler>,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
         let size = wgpu::Extent3d {
@@ -37,60 +303,216 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
-        let format = if is_normal_map {
-            wgpu::TextureFormat::Rgba8Unorm
-        } else {
-            wgpu::TextureFormat::Rgba8UnormSrgb
-        };
+        let format = kind.format();
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
+        write_mips(queue, &texture, 0, img.to_rgba8(), kind);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Six square images in +X, -X, +Y, -Y, +Z, -Z order, viewed as a cube.
+    pub fn from_cube_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage],
+        label: &str,
+        kind: TextureKind,
+    ) -> Result<Self> {
+        if faces.len() != 6 {
+            bail!("Cube map {label} needs six faces, got {}", faces.len());
+        }
+        let (width, height) = faces[0].dimensions();
+        if width != height {
+            bail!("Cube map faces must be square, {label} is {width}x{height}");
+        }
+        Self::from_layers(
+            device,
+            queue,
+            faces,
+            label,
+            kind,
+            wgpu::TextureViewDimension::Cube,
+        )
+    }
+
+    /// Images of the same size as the layers of a 2D array, e.g. terrain splat maps.
+    pub fn from_image_array(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[image::DynamicImage],
+        label: &str,
+        kind: TextureKind,
+    ) -> Result<Self> {
+        if layers.is_empty() {
+            bail!("Texture array {label} has no layers");
+        }
+        Self::from_layers(
+            device,
+            queue,
+            layers,
+            label,
+            kind,
+            wgpu::TextureViewDimension::D2Array,
+        )
+    }
+
+    /// A volume texture from tightly packed texels, slice by slice, e.g. a color
+    /// grading LUT.
+    pub fn from_volume(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+        data: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let (bytes_per_row, expected) = volume_layout(format, size, label)?;
+        if data.len() != expected {
+            bail!(
+                "Volume texture {label} needs {expected} bytes of data, got {}",
+                data.len()
+            );
+        }
 
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
         queue.write_texture(
-            wgpu::ImageCopyTextureBase {
+            wgpu::ImageCopyTexture {
                 texture: &texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &rgba,
+            data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(size.height),
             },
             size,
         );
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+        Ok(Self::from_texture(
+            device,
+            texture,
+            wgpu::TextureViewDimension::D3,
+        ))
+    }
+
+    /// An empty cube map, to be filled by copies or compute shaders depending
+    /// on `usage`.
+    pub fn create_cube(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        Self::from_texture(device, texture, wgpu::TextureViewDimension::Cube)
+    }
+
+    fn from_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[image::DynamicImage],
+        label: &str,
+        kind: TextureKind,
+        dimension: wgpu::TextureViewDimension,
+    ) -> Result<Self> {
+        let (width, height) = layers[0].dimensions();
+        if layers
+            .iter()
+            .any(|layer| layer.dimensions() != (width, height))
+        {
+            bail!("Layers of {label} must all be {width}x{height}");
+        }
+
+        let format = kind.format();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers.len() as u32,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, image) in layers.iter().enumerate() {
+            write_mips(queue, &texture, layer as u32, image.to_rgba8(), kind);
+        }
+
+        Ok(Self::from_texture(device, texture, dimension))
+    }
+
+    // Views the whole texture as `dimension` with a trilinear sampler
+    fn from_texture(
+        device: &wgpu::Device,
+        texture: wgpu::Texture,
+        dimension: wgpu::TextureViewDimension,
+    ) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
             ..Default::default()
         });
+        let sampler = Rc::new(SamplerOptions::default().create_sampler(device));
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
     }
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -102,7 +524,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -111,7 +533,7 @@ impl Texture {
         let texture = device.create_texture(&desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = Rc::new(device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -122,7 +544,95 @@ impl Texture {
             lod_max_clamp: 100.0,
             compare: Some(wgpu::CompareFunction::LessEqual),
             ..Default::default()
+        }));
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// An array of square depth layers, sampled with a comparison sampler
+    /// like `create_depth_texture`.
+    pub fn create_shadow_map(
+        device: &wgpu::Device,
+        resolution: u32,
+        layers: u32,
+        label: &str,
+    ) -> Self {
+        Self::create_depth_array(
+            device,
+            resolution,
+            layers,
+            wgpu::TextureViewDimension::D2Array,
+            label,
+        )
+    }
+
+    /// `cubes` depth cube maps of six layers each, viewed as a cube array.
+    pub fn create_point_shadow_map(
+        device: &wgpu::Device,
+        resolution: u32,
+        cubes: u32,
+        label: &str,
+    ) -> Self {
+        Self::create_depth_array(
+            device,
+            resolution,
+            cubes * 6,
+            wgpu::TextureViewDimension::CubeArray,
+            label,
+        )
+    }
+
+    /// A single depth cube map, for adapters without cube array textures.
+    pub fn create_point_shadow_cube(device: &wgpu::Device, resolution: u32, label: &str) -> Self {
+        Self::create_depth_array(
+            device,
+            resolution,
+            6,
+            wgpu::TextureViewDimension::Cube,
+            label,
+        )
+    }
+
+    fn create_depth_array(
+        device: &wgpu::Device,
+        resolution: u32,
+        layers: u32,
+        dimension: wgpu::TextureViewDimension,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..Default::default()
         });
+        let sampler = Rc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        }));
 
         Self {
             texture,
@@ -130,4 +640,262 @@ impl Texture {
             sampler,
         }
     }
+
+    /// A `HDR_FORMAT` target the size of the output, read back by later passes.
+    pub fn create_hdr_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        Self::create_render_target(
+            device,
+            &wgpu::SurfaceConfiguration {
+                format: Self::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: vec![],
+                ..*config
+            },
+            label,
+        )
+    }
+
+    /// A multisampled `HDR_FORMAT` target, resolved into one from
+    /// `create_hdr_target` at the end of the pass. It is never sampled, so
+    /// only its view is kept.
+    pub fn create_multisampled_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        label: &str,
+    ) -> wgpu::TextureView {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = Rc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        }));
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+// Bytes per row and in total of tightly packed volume data, checked for
+// overflow. Compressed blocks would need rows of blocks rather than texels,
+// so those formats are rejected.
+fn volume_layout(
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    label: &str,
+) -> Result<(u32, usize)> {
+    let texel_size = match format.block_size(None) {
+        Some(texel_size) if !format.is_compressed() => texel_size,
+        _ => bail!("{format:?} can't be uploaded to volume texture {label}"),
+    };
+    let bytes_per_row = texel_size
+        .checked_mul(size.width)
+        .with_context(|| format!("Volume texture {label} is too wide"))?;
+    let total = [size.height, size.depth_or_array_layers]
+        .into_iter()
+        .try_fold(bytes_per_row as u64, |total, n| total.checked_mul(n as u64))
+        .and_then(|total| usize::try_from(total).ok())
+        .with_context(|| format!("Volume texture {label} is too large"))?;
+    Ok((bytes_per_row, total))
+}
+
+// Writes `image` and its downsampled mips into one layer of `texture`
+fn write_mips(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    layer: u32,
+    image: image::RgbaImage,
+    kind: TextureKind,
+) {
+    let mut mip = image;
+    for mip_level in 0..texture.mip_level_count() {
+        if mip_level > 0 {
+            mip = downsample(&mip, kind);
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &mip,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * mip.width()),
+                rows_per_image: Some(mip.height()),
+            },
+            wgpu::Extent3d {
+                width: mip.width(),
+                height: mip.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+// Averages 2x2 blocks into the next mip level. Colors are averaged in linear
+// space so sRGB mips don't darken, and normals are renormalized.
+fn downsample(image: &image::RgbaImage, kind: TextureKind) -> image::RgbaImage {
+    let to_linear: Vec<f32> = (0..=255u8)
+        .map(|c| {
+            let c = c as f32 / 255.0;
+            if kind != TextureKind::Color {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect();
+
+    let (width, height) = image.dimensions();
+    image::RgbaImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut sum = [0.0f32; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1));
+            for channel in 0..3 {
+                sum[channel] += to_linear[pixel[channel] as usize];
+            }
+            sum[3] += pixel[3] as f32 / 255.0;
+        }
+        let mut average = sum.map(|c| c / 4.0);
+
+        match kind {
+            TextureKind::Color => {
+                for channel in average.iter_mut().take(3) {
+                    *channel = if *channel <= 0.0031308 {
+                        *channel * 12.92
+                    } else {
+                        1.055 * channel.powf(1.0 / 2.4) - 0.055
+                    };
+                }
+            }
+            TextureKind::Linear => {}
+            TextureKind::Normal => {
+                let normal = na::Vector3::new(average[0], average[1], average[2]) * 2.0
+                    - na::Vector3::repeat(1.0);
+                let normal = normal.try_normalize(1e-6).unwrap_or(na::Vector3::z());
+                for channel in 0..3 {
+                    average[channel] = normal[channel] * 0.5 + 0.5;
+                }
+            }
+        }
+        image::Rgba(average.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(a: [u8; 4], b: [u8; 4]) -> image::RgbaImage {
+        image::RgbaImage::from_fn(2, 2, |x, y| {
+            image::Rgba(if (x + y) % 2 == 0 { a } else { b })
+        })
+    }
+
+    #[test]
+    fn volume_sizes_are_checked() {
+        let size = |width, height, depth_or_array_layers| wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers,
+        };
+        let rgba = wgpu::TextureFormat::Rgba8Unorm;
+        assert_eq!(
+            volume_layout(rgba, size(16, 16, 16), "lut").unwrap(),
+            (64, 16384)
+        );
+        assert!(volume_layout(rgba, size(u32::MAX, 1, 1), "lut").is_err());
+        // Each factor fits in a u32 but their product doesn't
+        let (_, total) = volume_layout(rgba, size(65536, 65536, 4), "lut").unwrap();
+        assert_eq!(total as u64, 1 << 36);
+        assert!(volume_layout(wgpu::TextureFormat::Bc1RgbaUnorm, size(4, 4, 4), "lut").is_err());
+        assert!(volume_layout(wgpu::TextureFormat::Depth24Plus, size(4, 4, 4), "lut").is_err());
+    }
+
+    #[test]
+    fn linear_maps_are_averaged_as_is() {
+        // Roughness and metallic maps must not be renormalized like normals
+        let mip = downsample(
+            &checker([0, 64, 255, 255], [0, 0, 0, 255]),
+            TextureKind::Linear,
+        );
+        assert_eq!(mip.dimensions(), (1, 1));
+        assert_eq!(mip.get_pixel(0, 0).0, [0, 32, 128, 255]);
+    }
+
+    #[test]
+    fn colors_are_averaged_in_linear_space() {
+        let mip = downsample(&checker([255; 4], [0, 0, 0, 255]), TextureKind::Color);
+        // Half of linear white is brighter than half of the sRGB value
+        assert_eq!(mip.get_pixel(0, 0).0, [188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn normals_are_renormalized() {
+        let mip = downsample(
+            &checker([255, 128, 128, 255], [128, 128, 255, 255]),
+            TextureKind::Normal,
+        );
+        let [r, g, b, _] = mip.get_pixel(0, 0).0.map(|c| c as f32 / 255.0 * 2.0 - 1.0);
+        // +X and +Z average to a shorter vector, which is scaled back to unit length
+        assert!((r - b).abs() < 0.02);
+        assert!(((r * r + g * g + b * b).sqrt() - 1.0).abs() < 0.02);
+    }
 }
@@ -0,0 +1,678 @@
+use instant::Instant;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    resources,
+    texture::{SamplerOptions, Texture, TextureFilter},
+};
+
+/// The curve that maps HDR scene colors into the displayable range.
+#[repr(u32)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TonemapOperator {
+    /// Narkowicz's fit of the ACES filmic curve.
+    #[default]
+    Aces = 0,
+    Reinhard = 1,
+    /// Hable's curve from Uncharted 2.
+    Filmic = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exposure {
+    /// Scales the scene by `2^stops`.
+    Manual { stops: f32 },
+    /// Adapts to the average scene luminance, measured with a histogram of
+    /// the log luminance between `min_log_luminance` and `max_log_luminance`.
+    Auto {
+        min_log_luminance: f32,
+        max_log_luminance: f32,
+        /// Stops added after adapting, e.g. to keep a dark scene dark.
+        compensation: f32,
+        /// How quickly the eye adapts, roughly the inverse of seconds.
+        speed: f32,
+    },
+}
+
+impl Exposure {
+    pub fn auto() -> Self {
+        Exposure::Auto {
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            compensation: 0.0,
+            speed: 1.5,
+        }
+    }
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure::Manual { stops: 0.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Bloom {
+    /// Scene brightness above which colors start to glow, before exposure.
+    pub threshold: f32,
+    /// How far below `threshold` the glow fades in.
+    pub knee: f32,
+    pub intensity: f32,
+    /// As a fraction of the screen height.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.6,
+            radius: 0.03,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    pub exposure: Exposure,
+    /// Glows around colors brighter than the threshold, added to the HDR
+    /// target before exposure and the tonemapping curve.
+    pub bloom: Option<Bloom>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    curve: u32,
+    auto_exposure: u32,
+    // Stops
+    exposure: f32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Fraction of the way to the measured luminance covered this frame
+    adaptation: f32,
+    // The output needs the sRGB curve applied by hand
+    encode_srgb: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    settings: Bloom,
+    texel_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+// Must match `exposure.wgsl`
+const WORKGROUP_SIZE: u32 = 16;
+const HISTOGRAM_BINS: u64 = 256;
+
+/// Renders the scene into an HDR target, then maps it onto the output with
+/// an exposure and a tonemapping curve.
+pub struct Tonemap {
+    settings: TonemapSettings,
+    target: Texture,
+    output_srgb: bool,
+    last_update: Instant,
+    uniform: TonemapUniform,
+    uniform_buffer: wgpu::Buffer,
+    // The adapted average luminance, kept on the GPU between frames
+    luminance_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    exposure_bind_group_layout: wgpu::BindGroupLayout,
+    exposure_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    bloom: BloomPass,
+}
+
+impl Tonemap {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        settings: TonemapSettings,
+    ) -> Self {
+        let target = Texture::create_hdr_target(device, config, "hdr_target");
+        let output_srgb = config.format.is_srgb();
+        let uniform = TonemapUniform::new(settings, output_srgb, 0.0);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap uniform buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let luminance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Average luminance buffer"),
+            contents: bytemuck::cast_slice(&[1.0f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance histogram buffer"),
+            size: HISTOGRAM_BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let hdr_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let buffer_entry = |binding, visibility, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = |read_only| wgpu::BufferBindingType::Storage { read_only };
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        let compute = wgpu::ShaderStages::COMPUTE;
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tonemap_bind_group_layout"),
+            entries: &[
+                hdr_entry(fragment),
+                buffer_entry(1, fragment, wgpu::BufferBindingType::Uniform),
+                buffer_entry(2, fragment, storage(true)),
+            ],
+        });
+        let exposure_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("exposure_bind_group_layout"),
+                entries: &[
+                    hdr_entry(compute),
+                    buffer_entry(1, compute, wgpu::BufferBindingType::Uniform),
+                    buffer_entry(2, compute, storage(false)),
+                    buffer_entry(3, compute, storage(false)),
+                ],
+            });
+
+        let shader = device.create_shader_module(
+            resources::with_prelude(
+                &resources::load_shader_source("srgb.wgsl", include_str!("../shaders/srgb.wgsl")),
+                resources::load_shader("tonemap.wgsl", include_str!("../shaders/tonemap.wgsl")),
+            )
+            .unwrap(),
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let shader = device.create_shader_module(resources::load_shader(
+            "exposure.wgsl",
+            include_str!("../shaders/exposure.wgsl"),
+        ));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure pipeline layout"),
+            bind_group_layouts: &[&exposure_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let histogram_pipeline = compute_pipeline("build_histogram");
+        let average_pipeline = compute_pipeline("average_histogram");
+
+        let (bind_group, exposure_bind_group) = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &exposure_bind_group_layout,
+            &target,
+            &uniform_buffer,
+            &luminance_buffer,
+            &histogram_buffer,
+        );
+        let bloom = BloomPass::new(device, config, &target);
+
+        Tonemap {
+            settings,
+            target,
+            output_srgb,
+            last_update: Instant::now(),
+            uniform,
+            uniform_buffer,
+            luminance_buffer,
+            histogram_buffer,
+            bind_group_layout,
+            bind_group,
+            exposure_bind_group_layout,
+            exposure_bind_group,
+            pipeline,
+            histogram_pipeline,
+            average_pipeline,
+            bloom,
+        }
+    }
+
+    pub fn settings(&self) -> TonemapSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: TonemapSettings) {
+        self.settings = settings;
+    }
+
+    /// The HDR color target the scene is rendered into.
+    pub fn target(&self) -> &Texture {
+        &self.target
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.target = Texture::create_hdr_target(device, config, "hdr_target");
+        (self.bind_group, self.exposure_bind_group) = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.exposure_bind_group_layout,
+            &self.target,
+            &self.uniform_buffer,
+            &self.luminance_buffer,
+            &self.histogram_buffer,
+        );
+        self.bloom.resize(device, config, &self.target);
+    }
+
+    /// Uploads the settings and how far exposure adapts since the last update.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let now = Instant::now();
+        let delta = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        self.uniform = TonemapUniform::new(self.settings, self.output_srgb, delta);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+        if let Some(bloom) = self.settings.bloom {
+            self.bloom.update(queue, bloom, &self.target);
+        }
+    }
+
+    /// Adds bloom to the HDR target and measures the scene luminance when
+    /// they are enabled, then tonemaps the HDR target into `output`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if self.settings.bloom.is_some() {
+            self.bloom.render(encoder, &self.target);
+        }
+
+        if let Exposure::Auto { .. } = self.settings.exposure {
+            let size = self.target.texture.size();
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Exposure pass"),
+            });
+            compute_pass.set_bind_group(0, &self.exposure_bind_group, &[]);
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(WORKGROUP_SIZE),
+                size.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+            compute_pass.set_pipeline(&self.average_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        exposure_layout: &wgpu::BindGroupLayout,
+        target: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+        luminance_buffer: &wgpu::Buffer,
+        histogram_buffer: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tonemap_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: luminance_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let exposure_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("exposure_bind_group"),
+            layout: exposure_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: luminance_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        (bind_group, exposure_bind_group)
+    }
+}
+
+impl TonemapUniform {
+    fn new(settings: TonemapSettings, output_srgb: bool, delta: f32) -> Self {
+        let (auto_exposure, exposure, min_log_luminance, max_log_luminance, speed) =
+            match settings.exposure {
+                Exposure::Manual { stops } => (0, stops, 0.0, 0.0, 0.0),
+                Exposure::Auto {
+                    min_log_luminance,
+                    max_log_luminance,
+                    compensation,
+                    speed,
+                } => (1, compensation, min_log_luminance, max_log_luminance, speed),
+            };
+        TonemapUniform {
+            curve: settings.operator as u32,
+            auto_exposure,
+            exposure,
+            min_log_luminance,
+            log_luminance_range: (max_log_luminance - min_log_luminance).max(1e-3),
+            adaptation: 1.0 - (-delta * speed).exp(),
+            encode_srgb: !output_srgb as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Gathers the bright parts of the HDR target into a glow, then adds it back.
+struct BloomPass {
+    // The glow, read back while it is added to the HDR target
+    target: Texture,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // Reading the HDR target and the glow
+    gather_bind_group: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+    gather_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl BloomPass {
+    fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, hdr: &Texture) -> Self {
+        let target = Texture::create_hdr_target(device, config, "bloom_target");
+        let sampler = SamplerOptions::clamp(TextureFilter::Bilinear).create_sampler(device);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom uniform buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform::new(Bloom::default(), hdr)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(resources::load_shader(
+            "bloom.wgsl",
+            include_str!("../shaders/bloom.wgsl"),
+        ));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = |entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Texture::HDR_FORMAT,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let add = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let gather_pipeline = render_pipeline("fs_gather", wgpu::BlendState::REPLACE);
+        let composite_pipeline = render_pipeline(
+            "fs_composite",
+            wgpu::BlendState {
+                color: add,
+                alpha: add,
+            },
+        );
+
+        let (gather_bind_group, composite_bind_group) = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            hdr,
+            &target,
+            &sampler,
+            &uniform_buffer,
+        );
+
+        BloomPass {
+            target,
+            sampler,
+            uniform_buffer,
+            bind_group_layout,
+            gather_bind_group,
+            composite_bind_group,
+            gather_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        hdr: &Texture,
+    ) {
+        self.target = Texture::create_hdr_target(device, config, "bloom_target");
+        (self.gather_bind_group, self.composite_bind_group) = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            hdr,
+            &self.target,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+    }
+
+    fn update(&self, queue: &wgpu::Queue, settings: Bloom, hdr: &Texture) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::new(settings, hdr)]),
+        );
+    }
+
+    fn render(&self, encoder: &mut wgpu::CommandEncoder, hdr: &Texture) {
+        let passes = [
+            (
+                "Bloom gather pass",
+                &self.target.view,
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                &self.gather_pipeline,
+                &self.gather_bind_group,
+            ),
+            (
+                "Bloom composite pass",
+                &hdr.view,
+                wgpu::LoadOp::Load,
+                &self.composite_pipeline,
+                &self.composite_bind_group,
+            ),
+        ];
+        for (label, view, load, pipeline, bind_group) in passes {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        hdr: &Texture,
+        target: &Texture,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group = |label, source: &Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        (
+            bind_group("bloom_gather_bind_group", hdr),
+            bind_group("bloom_composite_bind_group", target),
+        )
+    }
+}
+
+impl BloomUniform {
+    fn new(settings: Bloom, hdr: &Texture) -> Self {
+        let size = hdr.texture.size();
+        BloomUniform {
+            settings,
+            texel_size: [1.0 / size.width as f32, 1.0 / size.height as f32],
+            _padding: [0.0; 2],
+        }
+    }
+}
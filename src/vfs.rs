@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use anyhow::Context;
+
+use crate::{
+    archive::{Archive, ArchiveMount},
+    resources::ResourceError,
+};
+
+/// A source of asset files, searched by a `Vfs`.
+pub trait Mount: Send + Sync {
+    /// Returns `Ok(None)` when the file is not part of this mount.
+    fn read(&self, path: &str) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+pub struct DirectoryMount {
+    pub root: PathBuf,
+}
+
+impl DirectoryMount {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DirectoryMount { root: root.into() }
+    }
+
+    /// Mounts `subdir` relative to the running executable, for shipped builds.
+    pub fn next_to_exe(subdir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let exe = std::env::current_exe()?;
+        let dir = exe.parent().context("Executable has no parent directory")?;
+        Ok(Self::new(dir.join(subdir)))
+    }
+}
+
+impl Mount for DirectoryMount {
+    fn read(&self, path: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(path)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Files compiled into the binary with `include_bytes!`.
+pub struct EmbeddedMount {
+    files: HashMap<&'static str, &'static [u8]>,
+}
+
+impl EmbeddedMount {
+    pub fn new(files: &[(&'static str, &'static [u8])]) -> Self {
+        EmbeddedMount {
+            files: files.iter().copied().collect(),
+        }
+    }
+
+    /// The `models/` directory, embedded by the build script.
+    #[cfg(feature = "embed-assets")]
+    pub fn bundled() -> Self {
+        Self::new(include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs")))
+    }
+}
+
+impl Mount for EmbeddedMount {
+    fn read(&self, path: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.files.get(path).map(|data| data.to_vec()))
+    }
+}
+
+/// Resolves asset paths against a list of mounts, searched in order.
+pub struct Vfs {
+    mounts: Vec<Box<dyn Mount>>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Vfs { mounts: Vec::new() }
+    }
+
+    /// Adds a mount searched after the existing ones.
+    pub fn mount(&mut self, mount: impl Mount + 'static) {
+        self.mounts.push(Box::new(mount));
+    }
+
+    /// Adds a mount searched before the existing ones.
+    pub fn mount_front(&mut self, mount: impl Mount + 'static) {
+        self.mounts.insert(0, Box::new(mount));
+    }
+
+    pub fn unmount_all(&mut self) {
+        self.mounts.clear();
+    }
+
+    pub fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let normalized = normalize(path)
+            .with_context(|| format!("{:?} points outside the asset roots", path))?;
+        for mount in &self.mounts {
+            if let Some(data) = mount
+                .read(&normalized)
+                .with_context(|| format!("Failed to read {:?}", path))?
+            {
+                return Ok(data);
+            }
+        }
+        Err(ResourceError::NotFound {
+            path: path.to_string(),
+        }
+        .into())
+    }
+}
+
+impl Default for Vfs {
+    /// Searches `models/` and then `assets.gzpk` next to the executable,
+    /// followed by the embedded bundle when the `embed-assets` feature is on.
+    /// Release builds then fall back to the archive packed by the build
+    /// script. Debug builds fall back to its copy of the asset directories in
+    /// `OUT_DIR` instead, as long as that path exists, which is usually only
+    /// on the machine that built them.
+    ///
+    /// Model paths are relative to `models/`, while shaders and images are
+    /// read as `shaders/<name>` and `images/<name>`.
+    fn default() -> Self {
+        let mut vfs = Vfs::new();
+        match DirectoryMount::next_to_exe("models") {
+            Ok(mount) => vfs.mount(mount),
+            Err(e) => log::warn!("{}", e),
+        }
+        if let Some(path) = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join("assets.gzpk")))
+            .filter(|path| path.is_file())
+        {
+            match Archive::open(&path) {
+                Ok(archive) => {
+                    let archive = Arc::new(archive);
+                    vfs.mount(ArchiveMount::new(archive.clone(), "models"));
+                    vfs.mount(ArchiveMount::new(archive, ""));
+                }
+                Err(e) => log::warn!("{:#}", e),
+            }
+        }
+        #[cfg(feature = "embed-assets")]
+        vfs.mount(EmbeddedMount::bundled());
+        #[cfg(packed_assets)]
+        {
+            let archive = Arc::new(
+                Archive::from_static(include_bytes!(concat!(env!("OUT_DIR"), "/assets.gzpk")))
+                    .expect("Packed assets are invalid"),
+            );
+            vfs.mount(ArchiveMount::new(archive.clone(), "models"));
+            vfs.mount(ArchiveMount::new(archive, ""));
+        }
+        #[cfg(all(debug_assertions, not(packed_assets)))]
+        {
+            let out_dir = Path::new(env!("OUT_DIR"));
+            if out_dir.is_dir() {
+                vfs.mount(DirectoryMount::new(out_dir.join("models")));
+                vfs.mount(DirectoryMount::new(out_dir));
+            }
+        }
+        vfs
+    }
+}
+
+/// The VFS used by the loaders in `resources`.
+pub fn global() -> &'static RwLock<Vfs> {
+    static VFS: OnceLock<RwLock<Vfs>> = OnceLock::new();
+    VFS.get_or_init(|| RwLock::new(Vfs::default()))
+}
+
+// Mount keys always use forward slashes, with `.` and `..` resolved. Paths
+// that climb above the root return None, so mounts can't read outside it.
+pub(crate) fn normalize(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_resolves_separators_and_dots() {
+        assert_eq!(normalize("cube/cube.obj").as_deref(), Some("cube/cube.obj"));
+        assert_eq!(
+            normalize("./cube//cube.obj").as_deref(),
+            Some("cube/cube.obj")
+        );
+        assert_eq!(
+            normalize("cube\\cube.obj").as_deref(),
+            Some("cube/cube.obj")
+        );
+        assert_eq!(
+            normalize("cube/textures/../cube.mtl").as_deref(),
+            Some("cube/cube.mtl")
+        );
+        assert_eq!(normalize("/cube.obj").as_deref(), Some("cube.obj"));
+    }
+
+    #[test]
+    fn normalize_rejects_paths_above_the_root() {
+        assert_eq!(normalize("../secret.txt"), None);
+        assert_eq!(normalize("cube/../../secret.txt"), None);
+        assert_eq!(normalize("..\\secret.txt"), None);
+    }
+
+    #[test]
+    fn mounts_are_searched_in_order() {
+        let mut vfs = Vfs::new();
+        vfs.mount(EmbeddedMount::new(&[("a.txt", b"first"), ("b.txt", b"b")]));
+        vfs.mount_front(EmbeddedMount::new(&[("a.txt", b"second")]));
+
+        assert_eq!(vfs.read("a.txt").unwrap(), b"second");
+        assert_eq!(vfs.read("./dir/../b.txt").unwrap(), b"b");
+        let missing = vfs.read("c.txt").unwrap_err();
+        assert!(matches!(
+            missing.downcast_ref::<ResourceError>(),
+            Some(ResourceError::NotFound { .. })
+        ));
+        assert!(vfs.read("../a.txt").is_err());
+
+        vfs.unmount_all();
+        assert!(vfs.read("a.txt").is_err());
+    }
+
+    #[test]
+    fn default_mounts_ship_every_asset_directory() {
+        let vfs = Vfs::default();
+        assert!(vfs.read("cube.obj").is_ok());
+        assert!(vfs.read("shaders/shader.wgsl").is_ok());
+        assert!(vfs.read("images/dude.png").is_ok());
+    }
+}
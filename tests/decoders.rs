@@ -0,0 +1,418 @@
+//! Decodes blocks of every BC and ASTC mode with `CompressedImage::decompress`
+//! and compares the texels with references in `tests/decoders/`. Each line of
+//! a reference holds one block and the texels it decodes to, both in hex.
+//!
+//! The blocks were picked to cover every BC6H and BC7 mode, and for ASTC every
+//! block layout, partition count, dual plane channel, weight range and LDR
+//! endpoint mode. The texels come from the GPU, so from a decoder written
+//! independently of ours. Run with `GAMEZAP_UPDATE_GOLDEN=1` on an adapter
+//! with BC and ASTC support to decode the blocks there again. HDR ASTC has no
+//! references yet, as no adapter at hand supports it.
+
+use std::path::{Path, PathBuf};
+
+use gamezap::compressed::CompressedImage;
+use pollster::block_on;
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+/// How far a channel may drift between decoders. BC1 to BC5 leave the
+/// rounding of their interpolated values up to the decoder.
+fn channel_tolerance(format: TextureFormat) -> u8 {
+    match format {
+        TextureFormat::Bc4RSnorm | TextureFormat::Bc5RgSnorm => 2,
+        TextureFormat::Bc1RgbaUnorm
+        | TextureFormat::Bc2RgbaUnorm
+        | TextureFormat::Bc3RgbaUnorm
+        | TextureFormat::Bc4RUnorm
+        | TextureFormat::Bc5RgUnorm => 1,
+        _ => 0,
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/decoders")
+        .join(format!("{name}.txt"))
+}
+
+fn parse_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// A single block as an image the size of its footprint.
+fn block_image(format: TextureFormat, block: Vec<u8>) -> CompressedImage {
+    let (width, height) = format.block_dimensions();
+    CompressedImage {
+        format,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        dimension: wgpu::TextureViewDimension::D2,
+        levels: vec![block],
+    }
+}
+
+fn check_against_reference(name: &str, format: TextureFormat) {
+    let path = reference_path(name);
+    if std::env::var_os("GAMEZAP_UPDATE_GOLDEN").is_some() {
+        update_reference(&path, format);
+        return;
+    }
+    let reference =
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {path:?}: {e}"));
+
+    let mut different = Vec::new();
+    for line in reference.lines() {
+        let (block, expected) = line
+            .split_once(' ')
+            .unwrap_or_else(|| panic!("{path:?} has a block without texels: {line}"));
+        let decoded = block_image(format, parse_hex(block)).decompress().unwrap();
+        let actual = &decoded.levels[0];
+        let expected = parse_hex(expected);
+        assert_eq!(actual.len(), expected.len(), "{line}");
+
+        let tolerance = channel_tolerance(format);
+        let matches = actual.iter().zip(&expected).all(|(&a, &b)| {
+            if decoded.format == TextureFormat::Rgba8Snorm {
+                (a as i8).abs_diff(b as i8) <= tolerance
+            } else {
+                a.abs_diff(b) <= tolerance
+            }
+        });
+        if !matches {
+            different.push(format!("{block}: got {}", to_hex(actual)));
+        }
+    }
+    assert!(
+        different.is_empty(),
+        "{} blocks decode differently from {path:?}:\n{}",
+        different.len(),
+        different.join("\n")
+    );
+}
+
+#[test]
+fn bc1_to_bc5_match_reference() {
+    check_against_reference("bc1", TextureFormat::Bc1RgbaUnorm);
+    check_against_reference("bc2", TextureFormat::Bc2RgbaUnorm);
+    check_against_reference("bc3", TextureFormat::Bc3RgbaUnorm);
+    check_against_reference("bc4_unorm", TextureFormat::Bc4RUnorm);
+    check_against_reference("bc4_snorm", TextureFormat::Bc4RSnorm);
+    check_against_reference("bc5_unorm", TextureFormat::Bc5RgUnorm);
+    check_against_reference("bc5_snorm", TextureFormat::Bc5RgSnorm);
+}
+
+#[test]
+fn bc6h_matches_reference() {
+    check_against_reference("bc6h_ufloat", TextureFormat::Bc6hRgbUfloat);
+    check_against_reference("bc6h_float", TextureFormat::Bc6hRgbFloat);
+}
+
+#[test]
+fn bc7_matches_reference() {
+    check_against_reference("bc7", TextureFormat::Bc7RgbaUnorm);
+    check_against_reference("bc7_srgb", TextureFormat::Bc7RgbaUnormSrgb);
+}
+
+#[test]
+fn astc_matches_reference() {
+    for block in [
+        AstcBlock::B4x4,
+        AstcBlock::B5x4,
+        AstcBlock::B5x5,
+        AstcBlock::B6x5,
+        AstcBlock::B6x6,
+        AstcBlock::B8x5,
+        AstcBlock::B8x6,
+        AstcBlock::B8x8,
+        AstcBlock::B10x5,
+        AstcBlock::B10x6,
+        AstcBlock::B10x8,
+        AstcBlock::B10x10,
+        AstcBlock::B12x10,
+        AstcBlock::B12x12,
+    ] {
+        let (width, height) = TextureFormat::Astc {
+            block,
+            channel: AstcChannel::Unorm,
+        }
+        .block_dimensions();
+        for (suffix, channel) in [("", AstcChannel::Unorm), ("_srgb", AstcChannel::UnormSrgb)] {
+            check_against_reference(
+                &format!("astc_{width}x{height}{suffix}"),
+                TextureFormat::Astc { block, channel },
+            );
+        }
+    }
+}
+
+/// Decodes the blocks of a reference on the GPU and writes their texels in the
+/// format `decompress` produces.
+fn update_reference(path: &Path, format: TextureFormat) {
+    let reference =
+        std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {path:?}: {e}"));
+    let blocks = reference
+        .lines()
+        .map(|line| line.split(' ').next().unwrap())
+        .collect::<Vec<_>>();
+
+    let (block_width, block_height) = format.block_dimensions();
+    let image = CompressedImage {
+        format,
+        size: wgpu::Extent3d {
+            width: block_width * blocks.len() as u32,
+            height: block_height,
+            depth_or_array_layers: 1,
+        },
+        dimension: wgpu::TextureViewDimension::D2,
+        levels: vec![blocks.iter().flat_map(|block| parse_hex(block)).collect()],
+    };
+    let output_format = block_image(format, parse_hex(blocks[0]))
+        .decompress()
+        .unwrap()
+        .format;
+    let texels = gpu_decode(&image);
+
+    let mut lines = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let mut bytes = Vec::new();
+        for y in 0..block_height {
+            for x in 0..block_width {
+                let texel = texels[(y * image.size.width + i as u32 * block_width + x) as usize];
+                encode_texel(output_format, texel, &mut bytes);
+            }
+        }
+        lines.push_str(&format!("{block} {}\n", to_hex(&bytes)));
+    }
+    std::fs::write(path, lines).unwrap();
+}
+
+/// Stores a texel read back as floats, checking it's exactly representable.
+fn encode_texel(format: TextureFormat, texel: [f32; 4], bytes: &mut Vec<u8>) {
+    let unorm = |v: f32| {
+        let byte = (v * 255.0).round();
+        assert!(
+            (v * 255.0 - byte).abs() < 1e-3,
+            "{v} isn't an 8-bit UNORM value"
+        );
+        byte as u8
+    };
+    for (channel, v) in texel.into_iter().enumerate() {
+        match format {
+            TextureFormat::Rgba8Unorm => bytes.push(unorm(v)),
+            TextureFormat::Rgba8UnormSrgb if channel == 3 => bytes.push(unorm(v)),
+            TextureFormat::Rgba8UnormSrgb => {
+                // The blocks decode to 8-bit sRGB, but GPUs only approximate
+                // its conversion to linear, so this takes the closest byte
+                let byte = (0..=255u8)
+                    .min_by(|&a, &b| {
+                        (srgb_to_linear(a) - v)
+                            .abs()
+                            .total_cmp(&(srgb_to_linear(b) - v).abs())
+                    })
+                    .unwrap();
+                bytes.push(byte);
+            }
+            TextureFormat::Rgba8Snorm => {
+                let value = (v * 127.0).round();
+                assert!(
+                    (v * 127.0 - value).abs() < 1e-3,
+                    "{v} isn't an 8-bit SNORM value"
+                );
+                bytes.push(value as i8 as u8);
+            }
+            TextureFormat::Rgba16Float => bytes.extend(f32_to_f16(v).to_le_bytes()),
+            format => panic!("{format:?} isn't a decompressed format"),
+        }
+    }
+}
+
+fn srgb_to_linear(byte: u8) -> f32 {
+    let c = byte as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts values read back from a half float texture, which are always exact.
+fn f32_to_f16(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    if v == 0.0 {
+        sign
+    } else if v.is_infinite() {
+        sign | 0x7C00
+    } else if exponent >= -14 {
+        assert!(
+            exponent <= 15 && bits & 0x1FFF == 0,
+            "{v} isn't a half float"
+        );
+        sign | (((exponent + 15) as u16) << 10) | ((bits >> 13) & 0x3FF) as u16
+    } else {
+        let mantissa = v.abs() * 16_777_216.0;
+        assert!(mantissa.fract() == 0.0, "{v} isn't a half float");
+        sign | mantissa as u16
+    }
+}
+
+const LOAD_SHADER: &str = r#"
+@group(0) @binding(0) var texels: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(texels, vec2<i32>(position.xy), 0);
+}
+"#;
+
+/// Draws every texel of `image` into a float target and reads them back.
+fn gpu_decode(image: &CompressedImage) -> Vec<[f32; 4]> {
+    let instance = wgpu::Instance::default();
+    let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .expect("Updating the references needs a graphics adapter");
+    let (device, queue) = block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            features: wgpu::Features::TEXTURE_COMPRESSION_BC
+                | wgpu::Features::TEXTURE_COMPRESSION_ASTC,
+            limits: wgpu::Limits::default(),
+        },
+        None,
+    ))
+    .expect("Updating the references needs BC and ASTC support");
+
+    let size = image.size;
+    let (block_width, block_height) = image.format.block_dimensions();
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Compressed Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: image.format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        &image.levels[0],
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(size.width / block_width * image.format.block_size(None).unwrap()),
+            rows_per_image: Some(size.height / block_height),
+        },
+        size,
+    );
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Decoded Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Load Shader"),
+        source: wgpu::ShaderSource::Wgsl(LOAD_SHADER.into()),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Load Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            targets: &[Some(TextureFormat::Rgba32Float.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Load Bind Group"),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(
+                &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        }],
+    });
+
+    let unpadded_bytes_per_row = size.width * 16;
+    let bytes_per_row = unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Decoded Buffer"),
+        size: (bytes_per_row * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Load Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range();
+    data.chunks_exact(bytes_per_row as usize)
+        .flat_map(|row| {
+            bytemuck::cast_slice::<u8, [f32; 4]>(&row[..unpadded_bytes_per_row as usize])
+        })
+        .copied()
+        .collect()
+}
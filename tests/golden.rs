@@ -0,0 +1,142 @@
+//! Renders small scenes headlessly and compares them with reference images
+//! in `tests/golden/`. Run with `GAMEZAP_UPDATE_GOLDEN=1` to write new
+//! references after an intended change to the shaders.
+//!
+//! Without a graphics adapter the tests are skipped, unless
+//! `GAMEZAP_REQUIRE_GPU` is set, e.g. on CI machines that should have one,
+//! where they fail instead.
+
+use std::path::{Path, PathBuf};
+
+use gamezap::{
+    camera::Camera,
+    ecs::{Light, MeshRenderer},
+    light::DrawLight,
+    resources,
+    scene::Transform,
+    Engine,
+};
+use nalgebra as na;
+use pollster::block_on;
+
+/// How far a channel may drift between drivers before a pixel counts as different.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// The fraction of pixels allowed to differ, e.g. along rasterized edges.
+const MAX_DIFFERENT_PIXELS: f32 = 0.005;
+
+fn has_adapter() -> bool {
+    let instance = wgpu::Instance::default();
+    let found =
+        block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).is_some();
+    if !found && std::env::var_os("GAMEZAP_REQUIRE_GPU").is_some() {
+        panic!("No graphics adapter is available and GAMEZAP_REQUIRE_GPU is set");
+    }
+    found
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn compare_with_golden(name: &str, image: &image::RgbaImage) {
+    let path = golden_path(name);
+    if std::env::var_os("GAMEZAP_UPDATE_GOLDEN").is_some() {
+        image.save(&path).unwrap();
+        return;
+    }
+    let golden = image::open(&path)
+        .unwrap_or_else(|e| {
+            panic!("Failed to open {path:?}, run with GAMEZAP_UPDATE_GOLDEN=1 to create it: {e}")
+        })
+        .into_rgba8();
+    assert_eq!(image.dimensions(), golden.dimensions());
+
+    let different = image
+        .pixels()
+        .zip(golden.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let fraction = different as f32 / (image.width() * image.height()) as f32;
+    if fraction > MAX_DIFFERENT_PIXELS {
+        let actual = std::env::temp_dir().join(format!("gamezap_{name}_actual.png"));
+        image.save(&actual).unwrap();
+        panic!(
+            "{different} pixels ({:.2}%) differ from {path:?}, the render was saved to {actual:?}",
+            fraction * 100.0
+        );
+    }
+}
+
+/// A textured cube lit by `shader.wgsl`, with the point light drawn by `light.wgsl`.
+#[test]
+fn lit_cube_matches_golden_image() {
+    if !has_adapter() {
+        eprintln!("Skipping, no graphics adapter is available");
+        return;
+    }
+    let mut engine = block_on(Engine::new_headless((128, 96)));
+    if let Some((_, camera)) = engine.world.query_mut::<Camera>().next() {
+        // Two units back from the cube, as `Camera::transform_camera` would place it
+        camera.fovy = std::f32::consts::FRAC_PI_4;
+        camera.view_matrix = na::Matrix4::new_translation(&camera.position);
+    }
+
+    let cube = engine.scene.add_model(
+        block_on(resources::load_model(
+            "cube.obj",
+            &engine.device,
+            &engine.queue,
+            &engine.texture_bind_group_layout,
+        ))
+        .unwrap(),
+    );
+    let entity = engine.world.spawn();
+    engine.world.insert(
+        entity,
+        Transform::new(
+            na::Vector3::zeros(),
+            na::UnitQuaternion::from_euler_angles(0.5, 0.7, 0.0),
+            na::Vector3::new(0.5, 0.5, 0.5),
+        ),
+    );
+    engine.world.insert(entity, MeshRenderer { model: cube });
+
+    // Replaces the default light with one in front of the cube, in view
+    let lights = engine
+        .world
+        .query::<Light>()
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+    for light in lights {
+        engine.world.despawn(light);
+    }
+    let light = engine.world.spawn();
+    engine.world.insert(
+        light,
+        Transform::from_translation(na::Vector3::new(0.5, 0.3, 0.9)),
+    );
+    engine
+        .world
+        .insert(light, Light::point([1.0, 0.9, 0.8], 2.0, 20.0));
+
+    engine.update();
+    engine
+        .render(&cube, |render_pass, engine, cube| {
+            render_pass.set_pipeline(&engine.light_pipeline);
+            render_pass.draw_light_model_instanced(
+                engine.scene.model(*cube),
+                0..engine.light_count(),
+                &engine.camera_bind_group,
+                &engine.light_bind_group,
+            );
+        })
+        .unwrap();
+
+    compare_with_golden("lit_cube", &engine.read_frame().unwrap());
+}